@@ -0,0 +1,209 @@
+//! BVHと総当たりの速度比較
+//!
+//! `cargo bench --bench bvh`で実行する。
+//! ティーポットの1024枚の三角形と、それを並べて大きくしたメッシュ、
+//! ベジエパッチを細かく分けたティーポットで計測する。
+//! 環境変数`BVH_BENCH_MESH`に`.obj`、`.ply`、`.stl`ファイルを指定すると、それも計測する。
+
+// src/のモジュールをそのまま取り込むので、ここで使わない部分の警告は出さない
+#![allow(dead_code, unused_imports)]
+#[macro_use]
+extern crate glium;
+extern crate cgmath;
+
+#[path = "../src/teapot.rs"]
+mod teapot;
+
+#[path = "../src/ray.rs"]
+mod ray;
+
+#[path = "../src/bvh.rs"]
+mod bvh;
+
+#[path = "../src/mesh.rs"]
+mod mesh;
+
+#[path = "../src/lod.rs"]
+mod lod;
+
+#[path = "../src/bezier.rs"]
+mod bezier;
+
+#[path = "../src/mesh_file.rs"]
+mod mesh_file;
+
+// mesh_file.rsのテストが使う
+#[path = "../src/primitives.rs"]
+mod primitives;
+
+use bvh::{closest_point_on_triangle, Aabb, Bvh, SplitMethod};
+use cgmath::{InnerSpace, Vector3};
+use ray::Ray;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// ティーポットを`n`x`n`個並べたメッシュ
+fn tiled_teapots(n: usize) -> (Vec<Vector3<f32>>, Vec<u32>) {
+    let mut positions = Vec::new();
+    let mut indices = Vec::new();
+    for i in 0..n {
+        for j in 0..n {
+            let offset = Vector3::new(i as f32 * 100.0, 0.0, j as f32 * 100.0);
+            let base = positions.len() as u32;
+            positions.extend(
+                teapot::VERTICES
+                    .iter()
+                    .map(|v| Vector3::from(v.position) + offset),
+            );
+            indices.extend(teapot::INDICES.iter().map(|&i| base + i as u32));
+        }
+    }
+    (positions, indices)
+}
+
+/// メッシュの境界ボックスを囲む円周上から中心に向かうレイ
+fn rays(bounds: &Aabb, count: usize) -> Vec<Ray> {
+    let center = bounds.center();
+    let radius = (bounds.max - bounds.min).magnitude();
+    (0..count)
+        .map(|i| {
+            let angle = i as f32 * 0.618;
+            let height = (i % 17) as f32 / 16.0 - 0.5;
+            let origin = center + Vector3::new(angle.cos(), height, angle.sin()) * radius;
+            let target = center + Vector3::new((i % 7) as f32 - 3.0, 0.0, (i % 5) as f32 - 2.0);
+            Ray::new(origin, target - origin)
+        })
+        .collect()
+}
+
+fn time<T>(mut f: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+    black_box(f());
+    start.elapsed()
+}
+
+fn report(label: &str, count: usize, elapsed: Duration) {
+    print!("  {:<28} {:>10.3} ms", label, elapsed.as_secs_f64() * 1e3);
+    if count > 1 {
+        print!(
+            "  ({:.3} us/query)",
+            elapsed.as_secs_f64() * 1e6 / count as f64
+        );
+    }
+    println!();
+}
+
+fn bench_mesh(name: &str, positions: Vec<Vector3<f32>>, indices: &[u32], brute_force: bool) {
+    println!("{}: {} triangles", name, indices.len() / 3);
+
+    for &split in &[SplitMethod::Median, SplitMethod::Sah] {
+        let elapsed = time(|| Bvh::with_split(positions.clone(), indices, split));
+        report(&format!("build ({:?})", split), 1, elapsed);
+    }
+
+    let median = Bvh::with_split(positions.clone(), indices, SplitMethod::Median);
+    let sah = Bvh::with_split(positions, indices, SplitMethod::Sah);
+    let rays = rays(&sah.bounds(), 2000);
+
+    for (label, bvh) in &[("median", &median), ("sah", &sah)] {
+        let elapsed = time(|| rays.iter().filter_map(|r| bvh.intersect(r)).count());
+        report(&format!("ray cast ({})", label), rays.len(), elapsed);
+    }
+
+    let points: Vec<Vector3<f32>> = rays.iter().map(|r| r.at(10.0)).collect();
+    let elapsed = time(|| points.iter().filter_map(|&p| sah.closest_point(p)).count());
+    report("closest point (sah)", points.len(), elapsed);
+
+    let boxes: Vec<Aabb> = points
+        .iter()
+        .map(|&p| Aabb {
+            min: p - Vector3::new(5.0, 5.0, 5.0),
+            max: p + Vector3::new(5.0, 5.0, 5.0),
+        })
+        .collect();
+    let elapsed = time(|| {
+        boxes
+            .iter()
+            .map(|b| sah.overlapping(b).len())
+            .sum::<usize>()
+    });
+    report("aabb overlap (sah)", boxes.len(), elapsed);
+
+    if !brute_force {
+        return;
+    }
+
+    // BVHと同じく、全ての三角形を調べて一番近い交点を探す
+    let elapsed = time(|| {
+        rays.iter()
+            .filter_map(|r| {
+                (0..sah.triangle_count())
+                    .filter_map(|t| {
+                        let [a, b, c] = sah.triangle(t);
+                        r.intersect_triangle(a, b, c).map(|(t, _, _)| t)
+                    })
+                    .reduce(f32::min)
+            })
+            .count()
+    });
+    report("ray cast (brute force)", rays.len(), elapsed);
+
+    let elapsed = time(|| {
+        points
+            .iter()
+            .map(|&p| {
+                (0..sah.triangle_count())
+                    .map(|t| {
+                        let [a, b, c] = sah.triangle(t);
+                        (closest_point_on_triangle(p, a, b, c) - p).magnitude2()
+                    })
+                    .fold(f32::INFINITY, f32::min)
+            })
+            .sum::<f32>()
+    });
+    report("closest point (brute force)", points.len(), elapsed);
+}
+
+fn main() {
+    let positions = teapot::VERTICES.iter().map(|v| v.position.into()).collect();
+    let indices: Vec<u32> = teapot::INDICES.iter().map(|&i| i as u32).collect();
+    bench_mesh("teapot", positions, &indices, true);
+
+    for &n in &[4, 16] {
+        let (positions, indices) = tiled_teapots(n);
+        bench_mesh(&format!("teapot x{}", n * n), positions, &indices, n <= 4);
+    }
+
+    for &n in &[8, 32] {
+        let tessellation = bezier::teapot(n);
+        let mesh = lod::Mesh::welded(
+            &tessellation.positions,
+            &tessellation.normals,
+            &tessellation.indices,
+        );
+        let positions = mesh.positions.iter().map(|&p| p.into()).collect();
+        bench_mesh(
+            &format!("bezier teapot n={}", n),
+            positions,
+            &mesh.indices,
+            n <= 8,
+        );
+    }
+
+    if let Some(path) = std::env::var_os("BVH_BENCH_MESH") {
+        let path = std::path::Path::new(&path);
+        match mesh_file::load(path) {
+            Ok(mesh) => {
+                let positions = mesh.positions.iter().map(|v| v.position.into()).collect();
+                let brute_force = mesh.indices.len() / 3 <= 100_000;
+                bench_mesh(
+                    &path.display().to_string(),
+                    positions,
+                    &mesh.indices,
+                    brute_force,
+                );
+            }
+            Err(e) => eprintln!("failed to load {}: {}", path.display(), e),
+        }
+    }
+}
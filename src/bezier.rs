@@ -0,0 +1,464 @@
+extern crate cgmath;
+use crate::mesh::MeshVertex;
+use cgmath::{InnerSpace, Vector3};
+
+/// Newellのティーポットの双3次ベジエパッチ (GLUTと同じ並び)
+///
+/// 縁・胴体・蓋・底は1/4周分、取っ手と注ぎ口は半分だけを持ち、残りは鏡に映して作る。
+const PATCHES: [[usize; 16]; 10] = [
+    // 縁
+    [102, 103, 104, 105, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    // 胴体
+    [
+        12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    ],
+    [
+        24, 25, 26, 27, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40,
+    ],
+    // 蓋
+    [
+        96, 96, 96, 96, 97, 98, 99, 100, 101, 101, 101, 101, 0, 1, 2, 3,
+    ],
+    [
+        0, 1, 2, 3, 106, 107, 108, 109, 110, 111, 112, 113, 114, 115, 116, 117,
+    ],
+    // 底
+    [
+        118, 118, 118, 118, 124, 122, 119, 121, 123, 126, 125, 120, 40, 39, 38, 37,
+    ],
+    // 取っ手
+    [
+        41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56,
+    ],
+    [
+        53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 28, 65, 66, 67,
+    ],
+    // 注ぎ口
+    [
+        68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79, 80, 81, 82, 83,
+    ],
+    [
+        80, 81, 82, 83, 84, 85, 86, 87, 88, 89, 90, 91, 92, 93, 94, 95,
+    ],
+];
+
+/// `PATCHES`の先頭からこの数だけは、x軸とy軸の両方で映して4つにする
+const FULL_TURN_PATCHES: usize = 6;
+
+/// 制御点 (z軸が上)
+const CONTROL_POINTS: [[f32; 3]; 127] = [
+    [0.2, 0.0, 2.7],
+    [0.2, -0.112, 2.7],
+    [0.112, -0.2, 2.7],
+    [0.0, -0.2, 2.7],
+    [1.3375, 0.0, 2.53125],
+    [1.3375, -0.749, 2.53125],
+    [0.749, -1.3375, 2.53125],
+    [0.0, -1.3375, 2.53125],
+    [1.4375, 0.0, 2.53125],
+    [1.4375, -0.805, 2.53125],
+    [0.805, -1.4375, 2.53125],
+    [0.0, -1.4375, 2.53125],
+    [1.5, 0.0, 2.4],
+    [1.5, -0.84, 2.4],
+    [0.84, -1.5, 2.4],
+    [0.0, -1.5, 2.4],
+    [1.75, 0.0, 1.875],
+    [1.75, -0.98, 1.875],
+    [0.98, -1.75, 1.875],
+    [0.0, -1.75, 1.875],
+    [2.0, 0.0, 1.35],
+    [2.0, -1.12, 1.35],
+    [1.12, -2.0, 1.35],
+    [0.0, -2.0, 1.35],
+    [2.0, 0.0, 0.9],
+    [2.0, -1.12, 0.9],
+    [1.12, -2.0, 0.9],
+    [0.0, -2.0, 0.9],
+    [-2.0, 0.0, 0.9],
+    [2.0, 0.0, 0.45],
+    [2.0, -1.12, 0.45],
+    [1.12, -2.0, 0.45],
+    [0.0, -2.0, 0.45],
+    [1.5, 0.0, 0.225],
+    [1.5, -0.84, 0.225],
+    [0.84, -1.5, 0.225],
+    [0.0, -1.5, 0.225],
+    [1.5, 0.0, 0.15],
+    [1.5, -0.84, 0.15],
+    [0.84, -1.5, 0.15],
+    [0.0, -1.5, 0.15],
+    [-1.6, 0.0, 2.025],
+    [-1.6, -0.3, 2.025],
+    [-1.5, -0.3, 2.25],
+    [-1.5, 0.0, 2.25],
+    [-2.3, 0.0, 2.025],
+    [-2.3, -0.3, 2.025],
+    [-2.5, -0.3, 2.25],
+    [-2.5, 0.0, 2.25],
+    [-2.7, 0.0, 2.025],
+    [-2.7, -0.3, 2.025],
+    [-3.0, -0.3, 2.25],
+    [-3.0, 0.0, 2.25],
+    [-2.7, 0.0, 1.8],
+    [-2.7, -0.3, 1.8],
+    [-3.0, -0.3, 1.8],
+    [-3.0, 0.0, 1.8],
+    [-2.7, 0.0, 1.575],
+    [-2.7, -0.3, 1.575],
+    [-3.0, -0.3, 1.35],
+    [-3.0, 0.0, 1.35],
+    [-2.5, 0.0, 1.125],
+    [-2.5, -0.3, 1.125],
+    [-2.65, -0.3, 0.9375],
+    [-2.65, 0.0, 0.9375],
+    [-2.0, -0.3, 0.9],
+    [-1.9, -0.3, 0.6],
+    [-1.9, 0.0, 0.6],
+    [1.7, 0.0, 1.425],
+    [1.7, -0.66, 1.425],
+    [1.7, -0.66, 0.6],
+    [1.7, 0.0, 0.6],
+    [2.6, 0.0, 1.425],
+    [2.6, -0.66, 1.425],
+    [3.1, -0.66, 0.825],
+    [3.1, 0.0, 0.825],
+    [2.3, 0.0, 2.1],
+    [2.3, -0.25, 2.1],
+    [2.4, -0.25, 2.025],
+    [2.4, 0.0, 2.025],
+    [2.7, 0.0, 2.4],
+    [2.7, -0.25, 2.4],
+    [3.3, -0.25, 2.4],
+    [3.3, 0.0, 2.4],
+    [2.8, 0.0, 2.475],
+    [2.8, -0.25, 2.475],
+    [3.525, -0.25, 2.49375],
+    [3.525, 0.0, 2.49375],
+    [2.9, 0.0, 2.475],
+    [2.9, -0.15, 2.475],
+    [3.45, -0.15, 2.5125],
+    [3.45, 0.0, 2.5125],
+    [2.8, 0.0, 2.4],
+    [2.8, -0.15, 2.4],
+    [3.2, -0.15, 2.4],
+    [3.2, 0.0, 2.4],
+    [0.0, 0.0, 3.15],
+    [0.8, 0.0, 3.15],
+    [0.8, -0.45, 3.15],
+    [0.45, -0.8, 3.15],
+    [0.0, -0.8, 3.15],
+    [0.0, 0.0, 2.85],
+    [1.4, 0.0, 2.4],
+    [1.4, -0.784, 2.4],
+    [0.784, -1.4, 2.4],
+    [0.0, -1.4, 2.4],
+    [0.4, 0.0, 2.55],
+    [0.4, -0.224, 2.55],
+    [0.224, -0.4, 2.55],
+    [0.0, -0.4, 2.55],
+    [1.3, 0.0, 2.55],
+    [1.3, -0.728, 2.55],
+    [0.728, -1.3, 2.55],
+    [0.0, -1.3, 2.55],
+    [1.3, 0.0, 2.4],
+    [1.3, -0.728, 2.4],
+    [0.728, -1.3, 2.4],
+    [0.0, -1.3, 2.4],
+    [0.0, 0.0, 0.0],
+    [1.425, -0.798, 0.0],
+    [1.5, 0.0, 0.075],
+    [1.425, 0.0, 0.0],
+    [0.798, -1.425, 0.0],
+    [0.0, -1.5, 0.075],
+    [0.0, -1.425, 0.0],
+    [1.5, -0.84, 0.075],
+    [0.84, -1.5, 0.075],
+];
+
+/// teapot.rsの頂点と重なるようにする拡大率と、y軸を上にした後の移動量
+const SCALE: f32 = 28.4268;
+const OFFSET: [f32; 3] = [0.8267, -39.8777, -1.107];
+
+/// 3次のバーンスタイン基底と、その微分
+fn bernstein(t: f32) -> ([f32; 4], [f32; 4]) {
+    let s = 1.0 - t;
+    (
+        [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t],
+        [
+            -3.0 * s * s,
+            3.0 * s * s - 6.0 * t * s,
+            6.0 * t * s - 3.0 * t * t,
+            3.0 * t * t,
+        ],
+    )
+}
+
+/// 4x4の制御点を持つ双3次ベジエ曲面
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Patch {
+    /// `points[i][j]`の`i`がu、`j`がvの向き
+    pub points: [[Vector3<f32>; 4]; 4],
+}
+
+impl Patch {
+    /// `(u, v)`での位置と、uとvで微分した接線
+    pub fn evaluate(&self, u: f32, v: f32) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        let (bu, du) = bernstein(u);
+        let (bv, dv) = bernstein(v);
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        let (mut position, mut tangent_u, mut tangent_v) = (zero, zero, zero);
+        for i in 0..4 {
+            for j in 0..4 {
+                let p = self.points[i][j];
+                position += p * (bu[i] * bv[j]);
+                tangent_u += p * (du[i] * bv[j]);
+                tangent_v += p * (bu[i] * dv[j]);
+            }
+        }
+        (position, tangent_u, tangent_v)
+    }
+
+    /// `(u, v)`での単位法線
+    ///
+    /// 蓋の先や底の中心のように辺が1点に潰れているところでは接線が0になるので、
+    /// パッチの内側に少しずらした所の法線を使う。
+    pub fn normal(&self, u: f32, v: f32) -> Vector3<f32> {
+        let inward = |t: f32| if t < 0.5 { t + 1e-3 } else { t - 1e-3 };
+        let (u, v) = if self.pole(u, v).is_some() {
+            (inward(u), inward(v))
+        } else {
+            (u, v)
+        };
+        let (_, tangent_u, tangent_v) = self.evaluate(u, v);
+        let n = tangent_u.cross(tangent_v);
+        if n.magnitude2() > 0.0 {
+            n.normalize()
+        } else {
+            n
+        }
+    }
+
+    /// `(u, v)`が1点に潰れた辺の上にあれば、その点
+    fn pole(&self, u: f32, v: f32) -> Option<Vector3<f32>> {
+        let p = &self.points;
+        let mut edges = Vec::new();
+        if u == 0.0 || u == 1.0 {
+            edges.push(p[if u == 0.0 { 0 } else { 3 }]);
+        }
+        if v == 0.0 || v == 1.0 {
+            let j = if v == 0.0 { 0 } else { 3 };
+            edges.push([p[0][j], p[1][j], p[2][j], p[3][j]]);
+        }
+        edges
+            .into_iter()
+            .find(|edge| edge.iter().all(|&q| q == edge[0]))
+            .map(|edge| edge[0])
+    }
+
+    /// u方向を逆にして、表と裏を入れ替える
+    fn reversed(&self) -> Patch {
+        let mut points = self.points;
+        points.reverse();
+        Patch { points }
+    }
+}
+
+/// ティーポットの32枚のパッチ (y軸が上で、teapot.rsと同じ大きさ)
+pub fn teapot_patches() -> Vec<Patch> {
+    let point = |index: usize, mirror: [f32; 2]| {
+        let [x, y, z] = CONTROL_POINTS[index];
+        let (x, y) = (x * mirror[0], y * mirror[1]);
+        // z軸が上のデータを、x軸の周りに回してy軸が上にする
+        Vector3::new(
+            x * SCALE + OFFSET[0],
+            z * SCALE + OFFSET[1],
+            -y * SCALE + OFFSET[2],
+        )
+    };
+    let mut patches = Vec::with_capacity(32);
+    for (n, indices) in PATCHES.iter().enumerate() {
+        let mirrors: &[[f32; 2]] = if n < FULL_TURN_PATCHES {
+            &[[1.0, 1.0], [1.0, -1.0], [-1.0, 1.0], [-1.0, -1.0]]
+        } else {
+            &[[1.0, 1.0], [1.0, -1.0]]
+        };
+        for &mirror in mirrors {
+            let mut points = [[Vector3::new(0.0, 0.0, 0.0); 4]; 4];
+            for i in 0..4 {
+                for j in 0..4 {
+                    points[i][j] = point(indices[i * 4 + j], mirror);
+                }
+            }
+            let patch = Patch { points };
+            // 元のデータは表が内側を向いている。片方の軸だけで映したものは、映したときに外を向く
+            if mirror[0] * mirror[1] > 0.0 {
+                patches.push(patch.reversed());
+            } else {
+                patches.push(patch);
+            }
+        }
+    }
+    patches
+}
+
+/// パッチを分割した三角形
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tessellation {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    /// パッチごとの`(u, v)`
+    pub tex_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+impl Tessellation {
+    /// パッチごとのUV付きの頂点 (継ぎ目の頂点はまとめない)
+    pub fn vertices(&self) -> Vec<MeshVertex> {
+        (0..self.positions.len())
+            .map(|i| MeshVertex {
+                tex_coords: self.tex_coords[i],
+                ..MeshVertex::new(self.positions[i], self.normals[i])
+            })
+            .collect()
+    }
+}
+
+/// 各パッチを縦横`subdivisions`個の四角形に分ける
+///
+/// 頂点はパッチごとに持つので、継ぎ目では同じ位置に複数の頂点ができる。
+/// 1点に潰れた辺にできる面積0の三角形は含めない。
+pub fn tessellate(patches: &[Patch], subdivisions: u32) -> Tessellation {
+    let n = subdivisions.max(1) as usize;
+    let mut tessellation = Tessellation {
+        positions: Vec::with_capacity(patches.len() * (n + 1) * (n + 1)),
+        normals: Vec::with_capacity(patches.len() * (n + 1) * (n + 1)),
+        tex_coords: Vec::with_capacity(patches.len() * (n + 1) * (n + 1)),
+        indices: Vec::with_capacity(patches.len() * n * n * 6),
+    };
+    for patch in patches {
+        let base = tessellation.positions.len() as u32;
+        for i in 0..=n {
+            for j in 0..=n {
+                let (u, v) = (i as f32 / n as f32, j as f32 / n as f32);
+                // 潰れた辺は誤差で点がばらけないように1点にまとめる
+                let position = patch.pole(u, v).unwrap_or_else(|| patch.evaluate(u, v).0);
+                tessellation.positions.push(position.into());
+                tessellation.normals.push(patch.normal(u, v).into());
+                tessellation.tex_coords.push([u, v]);
+            }
+        }
+        let index = |i: usize, j: usize| base + (i * (n + 1) + j) as u32;
+        for i in 0..n {
+            for j in 0..n {
+                let quad = [
+                    index(i, j),
+                    index(i + 1, j),
+                    index(i + 1, j + 1),
+                    index(i, j + 1),
+                ];
+                for triangle in &[[quad[0], quad[1], quad[2]], [quad[0], quad[2], quad[3]]] {
+                    let [a, b, c] =
+                        triangle.map(|k| Vector3::from(tessellation.positions[k as usize]));
+                    if (b - a).cross(c - a).magnitude2() > 0.0 {
+                        tessellation.indices.extend_from_slice(triangle);
+                    }
+                }
+            }
+        }
+    }
+    tessellation
+}
+
+/// ティーポットを`subdivisions`の細かさで作る
+pub fn teapot(subdivisions: u32) -> Tessellation {
+    tessellate(&teapot_patches(), subdivisions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::Aabb;
+    use crate::teapot;
+
+    /// 三角形で囲まれた体積 (外向きなら正になる)
+    fn volume(positions: &[[f32; 3]], indices: &[u32]) -> f32 {
+        indices
+            .chunks(3)
+            .map(|t| {
+                let [a, b, c] = [0, 1, 2].map(|k| Vector3::from(positions[t[k] as usize]));
+                a.dot(b.cross(c)) / 6.0
+            })
+            .sum()
+    }
+
+    fn bounds(positions: impl Iterator<Item = [f32; 3]>) -> Aabb {
+        let mut bounds = Aabb::empty();
+        for p in positions {
+            bounds.grow(p.into());
+        }
+        bounds
+    }
+
+    #[test]
+    fn overlaps_teapot_vertices() {
+        assert_eq!(teapot_patches().len(), 32);
+        let tessellation = teapot(8);
+        let ours = bounds(tessellation.positions.iter().copied());
+        // 先頭はダミーの頂点
+        let theirs = bounds(teapot::VERTICES[1..].iter().map(|v| v.position.into()));
+        let size = (theirs.max - theirs.min).magnitude();
+        assert!((ours.min - theirs.min).magnitude() < size * 0.01);
+        assert!((ours.max - theirs.max).magnitude() < size * 0.01);
+    }
+
+    #[test]
+    fn faces_outward() {
+        let tessellation = teapot(8);
+        assert!(volume(&tessellation.positions, &tessellation.indices) > 0.0);
+        // 頂点の法線は三角形の向きと揃っている
+        for t in tessellation.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| Vector3::from(tessellation.positions[t[k] as usize]));
+            let face = (b - a).cross(c - a).normalize();
+            for &i in t {
+                let normal = Vector3::from(tessellation.normals[i as usize]);
+                assert!((normal.magnitude() - 1.0).abs() < 1e-4);
+                assert!(
+                    face.dot(normal) > 0.0,
+                    "{:?}",
+                    tessellation.tex_coords[i as usize]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn refines_with_subdivisions() {
+        let coarse = teapot(8);
+        let fine = teapot(16);
+        assert!(fine.indices.len() > coarse.indices.len() * 3);
+        // 細かくしても形は変わらない
+        let coarse_volume = volume(&coarse.positions, &coarse.indices);
+        let fine_volume = volume(&fine.positions, &fine.indices);
+        assert!((coarse_volume - fine_volume).abs() < fine_volume * 0.02);
+        for uv in &fine.tex_coords {
+            assert!(uv.iter().all(|&t| (0.0..=1.0).contains(&t)));
+        }
+        // 継ぎ目をまとめても三角形は減らない
+        let mesh = crate::lod::Mesh::welded(&fine.positions, &fine.normals, &fine.indices);
+        assert_eq!(mesh.triangle_count(), fine.indices.len() / 3);
+        assert!(mesh.positions.len() < fine.positions.len());
+    }
+
+    #[test]
+    fn normals_match_finite_differences() {
+        let patch = teapot_patches()[4];
+        let (u, v, h) = (0.3, 0.6, 1e-3);
+        let (p, tangent_u, tangent_v) = patch.evaluate(u, v);
+        let du = (patch.evaluate(u + h, v).0 - p) / h;
+        let dv = (patch.evaluate(u, v + h).0 - p) / h;
+        assert!((du - tangent_u).magnitude() < tangent_u.magnitude() * 0.01);
+        assert!((dv - tangent_v).magnitude() < tangent_v.magnitude() * 0.01);
+        assert!(patch.normal(u, v).dot(du.cross(dv).normalize()) > 0.999);
+    }
+}
@@ -0,0 +1,35 @@
+#version 140
+
+in vec2 v_tex_coords;
+
+out vec4 color;
+
+// 0: 明るいところを抜き出す 1: u_directionの向きにぼかす 2: 元の色に足す
+uniform int u_pass;
+uniform sampler2D u_input;
+uniform sampler2D u_bloom;
+// ぼかす向きと1ピクセルの大きさ
+uniform vec2 u_direction;
+// これより明るいところだけをにじませる
+uniform float u_threshold;
+uniform float u_intensity;
+
+// 9タップのガウシアンの重み (真ん中から外へ)
+const float weights[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+void main() {
+	if (u_pass == 0) {
+		vec3 c = texture(u_input, v_tex_coords).rgb;
+		color = vec4(max(c - u_threshold, 0.0), 1.0);
+	} else if (u_pass == 1) {
+		vec3 sum = texture(u_input, v_tex_coords).rgb * weights[0];
+		for (int i = 1; i < 5; i++) {
+			sum += texture(u_input, v_tex_coords + u_direction * float(i)).rgb * weights[i];
+			sum += texture(u_input, v_tex_coords - u_direction * float(i)).rgb * weights[i];
+		}
+		color = vec4(sum, 1.0);
+	} else {
+		vec3 c = texture(u_input, v_tex_coords).rgb;
+		color = vec4(c + texture(u_bloom, v_tex_coords).rgb * u_intensity, 1.0);
+	}
+}
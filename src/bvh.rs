@@ -0,0 +1,645 @@
+extern crate cgmath;
+use crate::ray::{Hit, Ray};
+use cgmath::{InnerSpace, Vector3};
+
+/// 軸に平行な境界ボックス
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    /// 何も含まない空のボックス
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn grow(&mut self, p: Vector3<f32>) {
+        self.min = Vector3::new(
+            self.min.x.min(p.x),
+            self.min.y.min(p.y),
+            self.min.z.min(p.z),
+        );
+        self.max = Vector3::new(
+            self.max.x.max(p.x),
+            self.max.y.max(p.y),
+            self.max.z.max(p.z),
+        );
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut result = *self;
+        result.grow(other.min);
+        result.grow(other.max);
+        result
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) * 0.5
+    }
+
+    /// 8つの角。`i`のビット0, 1, 2が立っていればx, y, zをmaxにする
+    pub fn corner(&self, i: usize) -> Vector3<f32> {
+        Vector3::new(
+            if i & 1 == 0 { self.min.x } else { self.max.x },
+            if i & 2 == 0 { self.min.y } else { self.max.y },
+            if i & 4 == 0 { self.min.z } else { self.max.z },
+        )
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.min.x > self.max.x {
+            return 0.0;
+        }
+        let size = self.max - self.min;
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+            && self.min.z <= other.max.z
+            && self.max.z >= other.min.z
+    }
+
+    /// 点からボックスまでの距離の2乗 (中にあれば0)
+    pub fn distance_squared(&self, p: Vector3<f32>) -> f32 {
+        let below = self.min - p;
+        let above = p - self.max;
+        let d = Vector3::new(
+            below.x.max(above.x).max(0.0),
+            below.y.max(above.y).max(0.0),
+            below.z.max(above.z).max(0.0),
+        );
+        d.magnitude2()
+    }
+
+    /// 最も長い辺の軸 (0: x, 1: y, 2: z)
+    pub fn longest_axis(&self) -> usize {
+        let size = self.max - self.min;
+        if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// スラブ法でレイとの交差を調べ、入る点の`t`を返す
+    pub fn intersect_ray(&self, ray: &Ray, t_max: f32) -> Option<f32> {
+        let mut t_min = 0.0f32;
+        let mut t_max = t_max;
+        for axis in 0..3 {
+            let inv = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inv;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inv;
+            if inv < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN(0 * inf)のときは比較が偽になり、範囲は狭まらない
+            if t0 > t_min {
+                t_min = t0;
+            }
+            if t1 < t_max {
+                t_max = t1;
+            }
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some(t_min)
+    }
+}
+
+/// 葉に入れる三角形の最大数
+const MAX_LEAF_TRIANGLES: usize = 4;
+/// SAHで分割位置を探すときのビンの数
+const SAH_BINS: usize = 12;
+/// SAHで、子をたどる手間が三角形1つを調べる手間の何倍か
+const SAH_TRAVERSAL_COST: f32 = 1.0;
+
+/// ノードを分割する方法
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SplitMethod {
+    /// 重心の中央値で半分に分ける
+    Median,
+    /// Surface Area Heuristicで交差判定のコストが小さくなる位置で分ける
+    // ベンチマークとテストで比べるときだけ使う
+    #[cfg_attr(not(test), allow(dead_code))]
+    Sah,
+}
+
+/// SAHで決めたノードの扱い
+enum Split {
+    /// 分けるより葉のままの方が安い
+    Leaf,
+    /// `triangles`のこの位置で左右に分ける
+    At(usize),
+}
+
+/// 点に最も近い三角形上の点
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ClosestPoint {
+    pub point: Vector3<f32>,
+    pub triangle: usize,
+    pub distance: f32,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    bounds: Aabb,
+    /// 葉なら`triangles`の先頭、内部ノードなら右の子の番号(左の子は直後に並ぶ)
+    offset: usize,
+    /// 葉に含まれる三角形の数 (0なら内部ノード)
+    count: usize,
+}
+
+/// メッシュの三角形に対する境界ボリューム階層
+pub struct Bvh {
+    positions: Vec<Vector3<f32>>,
+    indices: Vec<[u32; 3]>,
+    /// ノード順に並べ替えた三角形の番号
+    triangles: Vec<usize>,
+    nodes: Vec<Node>,
+}
+
+impl Bvh {
+    /// 頂点位置とインデックス(3つで1つの三角形)から構築する
+    ///
+    /// ティーポットのようなメッシュではSAHより中央値分割の方がレイの判定が速かったので
+    /// (`benches/bvh.rs`)、こちらを既定にしている。
+    pub fn new<I: Copy + Into<u32>>(positions: Vec<Vector3<f32>>, indices: &[I]) -> Bvh {
+        Bvh::with_split(positions, indices, SplitMethod::Median)
+    }
+
+    pub fn with_split<I: Copy + Into<u32>>(
+        positions: Vec<Vector3<f32>>,
+        indices: &[I],
+        split: SplitMethod,
+    ) -> Bvh {
+        let indices: Vec<[u32; 3]> = indices
+            .chunks_exact(3)
+            .map(|t| [t[0].into(), t[1].into(), t[2].into()])
+            .collect();
+
+        let mut bvh = Bvh {
+            triangles: (0..indices.len()).collect(),
+            positions,
+            indices,
+            nodes: Vec::new(),
+        };
+        if !bvh.indices.is_empty() {
+            let centroids: Vec<Vector3<f32>> = (0..bvh.indices.len())
+                .map(|i| bvh.triangle_bounds(i).center())
+                .collect();
+            bvh.build(&centroids, split, 0, bvh.indices.len());
+        }
+        bvh
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// メッシュ全体の境界ボックス
+    // ベンチマークでレイを飛ばす範囲にだけ使う
+    #[allow(dead_code)]
+    pub fn bounds(&self) -> Aabb {
+        self.nodes
+            .first()
+            .map(|node| node.bounds)
+            .unwrap_or_else(Aabb::empty)
+    }
+
+    /// 三角形の頂点位置
+    pub fn triangle(&self, index: usize) -> [Vector3<f32>; 3] {
+        let [a, b, c] = self.indices[index];
+        [
+            self.positions[a as usize],
+            self.positions[b as usize],
+            self.positions[c as usize],
+        ]
+    }
+
+    fn triangle_bounds(&self, index: usize) -> Aabb {
+        let mut bounds = Aabb::empty();
+        for p in self.triangle(index).iter() {
+            bounds.grow(*p);
+        }
+        bounds
+    }
+
+    /// `triangles[start..end]`を含むノードを作り、その番号を返す
+    fn build(
+        &mut self,
+        centroids: &[Vector3<f32>],
+        split: SplitMethod,
+        start: usize,
+        end: usize,
+    ) -> usize {
+        let mut bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for &t in &self.triangles[start..end] {
+            bounds = bounds.union(&self.triangle_bounds(t));
+            centroid_bounds.grow(centroids[t]);
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bounds,
+            offset: start,
+            count: end - start,
+        });
+        if end - start <= MAX_LEAF_TRIANGLES {
+            return index;
+        }
+
+        let axis = centroid_bounds.longest_axis();
+        let mid = match split {
+            SplitMethod::Median => None,
+            SplitMethod::Sah => {
+                match self.partition_sah(centroids, &centroid_bounds, &bounds, start, end) {
+                    Some(Split::Leaf) => return index,
+                    Some(Split::At(mid)) => Some(mid),
+                    None => None,
+                }
+            }
+        }
+        .unwrap_or_else(|| {
+            // 重心が一番広がっている軸の中央値で分割する
+            let mid = (start + end) / 2;
+            self.triangles[start..end].select_nth_unstable_by(mid - start, |&a, &b| {
+                centroids[a][axis]
+                    .partial_cmp(&centroids[b][axis])
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            mid
+        });
+
+        self.build(centroids, split, start, mid);
+        let right = self.build(centroids, split, mid, end);
+        self.nodes[index].offset = right;
+        self.nodes[index].count = 0;
+        index
+    }
+
+    /// ビンに分けてSAHのコストが最小になる面で`triangles[start..end]`を並べ替え、
+    /// 分割位置を返す。葉のままの方が安ければ`Split::Leaf`、うまく分けられないときは`None`
+    fn partition_sah(
+        &mut self,
+        centroids: &[Vector3<f32>],
+        centroid_bounds: &Aabb,
+        bounds: &Aabb,
+        start: usize,
+        end: usize,
+    ) -> Option<Split> {
+        let mut best: Option<(f32, usize, usize)> = None;
+        for axis in 0..3 {
+            let low = centroid_bounds.min[axis];
+            let extent = centroid_bounds.max[axis] - low;
+            if extent <= 0.0 {
+                continue;
+            }
+            let mut bins = [(Aabb::empty(), 0usize); SAH_BINS];
+            for &t in &self.triangles[start..end] {
+                let bin = &mut bins[sah_bin(centroids[t][axis], low, extent)];
+                bin.0 = bin.0.union(&self.triangle_bounds(t));
+                bin.1 += 1;
+            }
+
+            // 左から累積した面積と数
+            let mut left = [(0.0f32, 0usize); SAH_BINS];
+            let mut acc = (Aabb::empty(), 0);
+            for i in 0..SAH_BINS - 1 {
+                acc = (acc.0.union(&bins[i].0), acc.1 + bins[i].1);
+                left[i] = (acc.0.surface_area(), acc.1);
+            }
+            let mut acc = (Aabb::empty(), 0);
+            for i in (1..SAH_BINS).rev() {
+                acc = (acc.0.union(&bins[i].0), acc.1 + bins[i].1);
+                let cost =
+                    left[i - 1].0 * left[i - 1].1 as f32 + acc.0.surface_area() * acc.1 as f32;
+                if left[i - 1].1 > 0 && acc.1 > 0 && best.is_none_or(|(c, _, _)| cost < c) {
+                    best = Some((cost, axis, i));
+                }
+            }
+        }
+
+        let (cost, axis, split_bin) = best?;
+        let count = end - start;
+        // 分割しても葉のままより安くならないなら、小さいノードは葉にしておく
+        let area = bounds.surface_area();
+        if count <= MAX_LEAF_TRIANGLES * 4
+            && area * SAH_TRAVERSAL_COST + cost >= area * count as f32
+        {
+            return Some(Split::Leaf);
+        }
+
+        let low = centroid_bounds.min[axis];
+        let extent = centroid_bounds.max[axis] - low;
+        let mut mid = start;
+        for i in start..end {
+            let t = self.triangles[i];
+            if sah_bin(centroids[t][axis], low, extent) < split_bin {
+                self.triangles.swap(i, mid);
+                mid += 1;
+            }
+        }
+        if mid == start || mid == end {
+            None
+        } else {
+            Some(Split::At(mid))
+        }
+    }
+
+    /// レイと最も近くで交差する三角形を探す
+    pub fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let mut closest: Option<Hit> = None;
+        let mut t_max = f32::INFINITY;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.bounds.intersect_ray(ray, t_max).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                // 手前にある子を後に積んで先に調べる
+                let (left, right) = (index + 1, node.offset);
+                let tl = self.nodes[left].bounds.intersect_ray(ray, t_max);
+                let tr = self.nodes[right].bounds.intersect_ray(ray, t_max);
+                match (tl, tr) {
+                    (Some(tl), Some(tr)) if tl < tr => {
+                        stack.push(right);
+                        stack.push(left);
+                    }
+                    (Some(_), Some(_)) => {
+                        stack.push(left);
+                        stack.push(right);
+                    }
+                    (Some(_), None) => stack.push(left),
+                    (None, Some(_)) => stack.push(right),
+                    (None, None) => (),
+                }
+                continue;
+            }
+            for &triangle in &self.triangles[node.offset..node.offset + node.count] {
+                let [a, b, c] = self.triangle(triangle);
+                if let Some((t, u, v)) = ray.intersect_triangle(a, b, c) {
+                    if t < t_max {
+                        t_max = t;
+                        closest = Some(Hit {
+                            t,
+                            point: ray.at(t),
+                            triangle,
+                            barycentric: (u, v),
+                        });
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    /// 点に最も近い三角形上の点を探す
+    pub fn closest_point(&self, p: Vector3<f32>) -> Option<ClosestPoint> {
+        let mut closest: Option<ClosestPoint> = None;
+        let mut best = f32::INFINITY;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.bounds.distance_squared(p) >= best {
+                continue;
+            }
+            if node.count == 0 {
+                // 近い方の子を後に積んで先に調べる
+                let (left, right) = (index + 1, node.offset);
+                let dl = self.nodes[left].bounds.distance_squared(p);
+                let dr = self.nodes[right].bounds.distance_squared(p);
+                if dl < dr {
+                    stack.push(right);
+                    stack.push(left);
+                } else {
+                    stack.push(left);
+                    stack.push(right);
+                }
+                continue;
+            }
+            for &triangle in &self.triangles[node.offset..node.offset + node.count] {
+                let [a, b, c] = self.triangle(triangle);
+                let q = closest_point_on_triangle(p, a, b, c);
+                let d = (q - p).magnitude2();
+                if d < best {
+                    best = d;
+                    closest = Some(ClosestPoint {
+                        point: q,
+                        triangle,
+                        distance: d.sqrt(),
+                    });
+                }
+            }
+        }
+        closest
+    }
+
+    /// 境界ボックスが`bounds`と重なる三角形の番号を集める
+    pub fn overlapping(&self, bounds: &Aabb) -> Vec<usize> {
+        let mut result = Vec::new();
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bounds.overlaps(bounds) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.offset);
+                stack.push(index + 1);
+                continue;
+            }
+            for &triangle in &self.triangles[node.offset..node.offset + node.count] {
+                if self.triangle_bounds(triangle).overlaps(bounds) {
+                    result.push(triangle);
+                }
+            }
+        }
+        result
+    }
+}
+
+/// 重心の座標が入るSAHのビンの番号
+fn sah_bin(value: f32, low: f32, extent: f32) -> usize {
+    let bin = ((value - low) / extent * SAH_BINS as f32) as usize;
+    bin.min(SAH_BINS - 1)
+}
+
+/// 三角形`abc`上で`p`に最も近い点 (Ericson, Real-Time Collision Detection 5.1.5)
+pub fn closest_point_on_triangle(
+    p: Vector3<f32>,
+    a: Vector3<f32>,
+    b: Vector3<f32>,
+    c: Vector3<f32>,
+) -> Vector3<f32> {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    let v = vb * denom;
+    let w = vc * denom;
+    a + ab * v + ac * w
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::teapot;
+
+    fn teapot_bvh(split: SplitMethod) -> Bvh {
+        let positions = teapot::VERTICES.iter().map(|v| v.position.into()).collect();
+        Bvh::with_split(positions, &teapot::INDICES, split)
+    }
+
+    /// 全ての三角形を総当たりで調べる
+    fn brute_force(bvh: &Bvh, ray: &Ray) -> Option<(usize, f32)> {
+        let mut closest: Option<(usize, f32)> = None;
+        for i in 0..bvh.indices.len() {
+            let [a, b, c] = bvh.triangle(i);
+            if let Some((t, _, _)) = ray.intersect_triangle(a, b, c) {
+                if closest.is_none_or(|(_, best)| t < best) {
+                    closest = Some((i, t));
+                }
+            }
+        }
+        closest
+    }
+
+    #[test]
+    /// BVHの結果が総当たりと一致するかのテスト
+    fn matches_brute_force() {
+        for &split in &[SplitMethod::Median, SplitMethod::Sah] {
+            check_rays(&teapot_bvh(split));
+        }
+    }
+
+    fn check_rays(bvh: &Bvh) {
+        for i in 0..64 {
+            let angle = i as f32 * 0.1;
+            let origin = Vector3::new(angle.cos() * 100.0, 20.0 + i as f32, angle.sin() * 100.0);
+            let ray = Ray::new(origin, Vector3::new(0.0, 30.0, 0.0) - origin);
+            let hit = bvh.intersect(&ray).map(|h| (h.triangle, h.t));
+            let expected = brute_force(bvh, &ray);
+            match (hit, expected) {
+                (Some((_, t)), Some((_, expected_t))) => assert!((t - expected_t).abs() < 1e-3),
+                (None, None) => (),
+                _ => panic!("mismatch for ray {:?}: {:?} vs {:?}", ray, hit, expected),
+            }
+        }
+    }
+
+    #[test]
+    /// 最近点が総当たりと一致するかのテスト
+    fn closest_point_matches_brute_force() {
+        let bvh = teapot_bvh(SplitMethod::Sah);
+        for i in 0..32 {
+            let angle = i as f32 * 0.3;
+            let p = Vector3::new(angle.cos() * 60.0, i as f32 * 2.0, angle.sin() * 45.0);
+            let found = bvh.closest_point(p).unwrap();
+            let expected = (0..bvh.triangle_count())
+                .map(|t| {
+                    let [a, b, c] = bvh.triangle(t);
+                    (closest_point_on_triangle(p, a, b, c) - p).magnitude()
+                })
+                .fold(f32::INFINITY, f32::min);
+            assert!((found.distance - expected).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    /// 重なりの問い合わせが総当たりと一致するかのテスト
+    fn overlapping_matches_brute_force() {
+        let bvh = teapot_bvh(SplitMethod::Sah);
+        let query = Aabb {
+            min: Vector3::new(30.0, 20.0, -10.0),
+            max: Vector3::new(50.0, 40.0, 10.0),
+        };
+        let mut found = bvh.overlapping(&query);
+        found.sort_unstable();
+        let expected: Vec<usize> = (0..bvh.triangle_count())
+            .filter(|&t| bvh.triangle_bounds(t).overlaps(&query))
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    /// 分けても安くならない小さいノードを、SAHでは葉のまま残すかのテスト
+    fn sah_keeps_cheap_leaves() {
+        // 8枚ともほとんど同じ箱に収まるので、どこで分けても子の箱は親とほぼ同じ大きさになる
+        let mut positions = Vec::new();
+        for i in 0..8 {
+            positions.push(Vector3::new(-1.0, 0.0, -1.0));
+            positions.push(Vector3::new(1.0, 0.0, 1.0));
+            positions.push(Vector3::new(0.0, 1.0 + i as f32 * 0.01, 0.0));
+        }
+        let indices: Vec<u32> = (0..24).collect();
+        let sah = Bvh::with_split(positions.clone(), &indices, SplitMethod::Sah);
+        assert_eq!(sah.nodes.len(), 1);
+        assert_eq!(sah.nodes[0].count, 8);
+        // 中央値では葉の大きさまで分ける
+        let median = Bvh::with_split(positions, &indices, SplitMethod::Median);
+        assert!(median.nodes.iter().all(|n| n.count <= MAX_LEAF_TRIANGLES));
+    }
+}
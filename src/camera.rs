@@ -1,9 +1,40 @@
 extern crate cgmath;
+use crate::collision::Collision;
 use glium::glutin;
+
+/// 1秒に進む距離
+const MOVE_SPEED: f32 = 0.6;
+/// 歩行モードの重力加速度
+const GRAVITY: f32 = 1.8;
+/// 歩行モードでジャンプしたときの上向きの速さ (1秒あたり)
+const JUMP_SPEED: f32 = 1.2;
+/// 歩行モードで地面から目までの高さ
+const EYE_HEIGHT: f32 = 0.15;
+/// 描画する範囲の手前と奥の距離
+pub const ZNEAR: f32 = 0.1;
+pub const ZFAR: f32 = 1024.0;
+
+/// カメラの動き方
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MoveMode {
+    /// 何にもぶつからずに自由に飛ぶ
+    Fly,
+    /// 飛びながら、シーンの三角形にぶつかると面に沿って滑る
+    Collide,
+    /// 重力があり、地面や三角形の上を歩く
+    Walk,
+}
+
 pub struct CameraState {
     aspect_ratio: f32,
+    /// 縦の視野角 (ラジアン)
+    fov: f32,
     position: (f32, f32, f32),
     //position: cgmath::Vector3<f32>,
+    /// 前回の`update`の前の位置 (描画のときに補間する)
+    previous_position: (f32, f32, f32),
+    /// `previous_position`と`position`の間のどこを描くか
+    alpha: f32,
     direction: (f32, f32, f32),
     // direction: cgmath::Vector3<f32>
     up: (f32, f32, f32),
@@ -17,13 +48,23 @@ pub struct CameraState {
     moving_right: bool,
     moving_forward: bool,
     moving_backward: bool,
+
+    move_mode: MoveMode,
+    collision: Option<Collision>,
+    /// 地面の高さ (歩行モードで使う)
+    ground: Option<f32>,
+    vertical_speed: f32,
+    grounded: bool,
 }
 
 impl CameraState {
     pub fn new() -> CameraState {
         CameraState {
             aspect_ratio: 1024.0 / 768.0,
+            fov: std::f32::consts::PI / 2.0,
             position: (0.0, 0.0, 0.0),
+            previous_position: (0.0, 0.0, 0.0),
+            alpha: 1.0,
             direction: (0.0, 0.0, -1.0),
             up: (0.0, 1.0, 0.0),
             old_cursor_position: glutin::dpi::PhysicalPosition { x: 750f64, y: 750f64 },
@@ -33,11 +74,32 @@ impl CameraState {
             moving_right: false,
             moving_forward: false,
             moving_backward: false,
+            move_mode: MoveMode::Fly,
+            collision: None,
+            ground: None,
+            vertical_speed: 0.0,
+            grounded: false,
         }
     }
 
     pub fn set_position(&mut self, pos: (f32, f32, f32)) {
         self.position = pos;
+        self.previous_position = pos;
+    }
+
+    /// 描画するときに前回の更新からどれだけ進んだ位置を使うか (0から1)
+    pub fn set_interpolation(&mut self, alpha: f32) {
+        self.alpha = alpha;
+    }
+
+    /// 補間した、描画に使う位置
+    fn render_position(&self) -> (f32, f32, f32) {
+        let (p, q, a) = (self.previous_position, self.position, self.alpha);
+        (
+            p.0 + (q.0 - p.0) * a,
+            p.1 + (q.1 - p.1) * a,
+            p.2 + (q.2 - p.2) * a,
+        )
     }
 
     pub fn set_direction(&mut self, dir: (f32, f32, f32)) {
@@ -48,10 +110,45 @@ impl CameraState {
         self.up = up;
     }
 
+    pub fn set_fov(&mut self, fov: f32) {
+        self.fov = fov;
+    }
+
+    pub fn get_position(&self) -> (f32, f32, f32) {
+        self.position
+    }
+
+    pub fn set_move_mode(&mut self, mode: MoveMode) {
+        self.move_mode = mode;
+        self.vertical_speed = 0.0;
+        self.grounded = false;
+    }
+
+    /// Fly → Collide → Walk の順に動き方を切り替える
+    pub fn cycle_move_mode(&mut self) -> MoveMode {
+        let next = match self.move_mode {
+            MoveMode::Fly => MoveMode::Collide,
+            MoveMode::Collide => MoveMode::Walk,
+            MoveMode::Walk => MoveMode::Fly,
+        };
+        self.set_move_mode(next);
+        next
+    }
+
+    /// ぶつかる対象のシーンを設定する
+    pub fn set_collision(&mut self, collision: Option<Collision>) {
+        self.collision = collision;
+    }
+
+    /// 歩行モードの地面の高さを設定する
+    pub fn set_ground(&mut self, ground: Option<f32>) {
+        self.ground = ground;
+    }
+
     pub fn get_perspective(&self) -> [[f32; 4]; 4] {
-        let fov: f32 = 3.141592 / 2.0;
-        let zfar = 1024.0;
-        let znear = 0.1;
+        let fov = self.fov;
+        let zfar = ZFAR;
+        let znear = ZNEAR;
 
         let f = 1.0 / (fov / 2.0).tan();
 
@@ -91,10 +188,11 @@ impl CameraState {
             s_norm.0 * f.1 - s_norm.1 * f.0,
         );
 
+        let position = self.render_position();
         let p = (
-            -self.position.0 * s.0 - self.position.1 * s.1 - self.position.2 * s.2,
-            -self.position.0 * u.0 - self.position.1 * u.1 - self.position.2 * u.2,
-            -self.position.0 * f.0 - self.position.1 * f.1 - self.position.2 * f.2,
+            -position.0 * s.0 - position.1 * s.1 - position.2 * s.2,
+            -position.0 * u.0 - position.1 * u.1 - position.2 * u.2,
+            -position.0 * f.0 - position.1 * f.1 - position.2 * f.2,
         );
 
         // note: remember that this is column-major, so the lines of code are actually columns
@@ -106,7 +204,10 @@ impl CameraState {
         ]
     }
 
-    pub fn update(&mut self) {
+    /// `dt`秒だけ動かす
+    pub fn update(&mut self, dt: f32) {
+        self.previous_position = self.position;
+
         let f = {
             let f = self.direction;
             let len = f.0 * f.0 + f.1 * f.1 + f.2 * f.2;
@@ -134,41 +235,88 @@ impl CameraState {
             s.0 * f.1 - s.1 * f.0,
         );
 
+        // 歩行モードでは水平にだけ動き、上下は重力とジャンプで決まる
+        let (f, s, u) = if self.move_mode == MoveMode::Walk {
+            (horizontal(f), horizontal(s), (0.0, 0.0, 0.0))
+        } else {
+            (f, s, u)
+        };
+
+        let speed = MOVE_SPEED * dt;
+        let mut delta = (0.0, 0.0, 0.0);
+
         if self.moving_up {
-            self.position.0 += u.0 * 0.01;
-            self.position.1 += u.1 * 0.01;
-            self.position.2 += u.2 * 0.01;
+            delta.0 += u.0 * speed;
+            delta.1 += u.1 * speed;
+            delta.2 += u.2 * speed;
         }
 
         if self.moving_left {
-            self.position.0 -= s.0 * 0.01;
-            self.position.1 -= s.1 * 0.01;
-            self.position.2 -= s.2 * 0.01;
+            delta.0 -= s.0 * speed;
+            delta.1 -= s.1 * speed;
+            delta.2 -= s.2 * speed;
         }
 
         if self.moving_down {
-            self.position.0 -= u.0 * 0.01;
-            self.position.1 -= u.1 * 0.01;
-            self.position.2 -= u.2 * 0.01;
+            delta.0 -= u.0 * speed;
+            delta.1 -= u.1 * speed;
+            delta.2 -= u.2 * speed;
         }
 
         if self.moving_right {
-            self.position.0 += s.0 * 0.01;
-            self.position.1 += s.1 * 0.01;
-            self.position.2 += s.2 * 0.01;
+            delta.0 += s.0 * speed;
+            delta.1 += s.1 * speed;
+            delta.2 += s.2 * speed;
         }
 
         if self.moving_forward {
-            self.position.0 += f.0 * 0.01;
-            self.position.1 += f.1 * 0.01;
-            self.position.2 += f.2 * 0.01;
+            delta.0 += f.0 * speed;
+            delta.1 += f.1 * speed;
+            delta.2 += f.2 * speed;
         }
 
         if self.moving_backward {
-            self.position.0 -= f.0 * 0.01;
-            self.position.1 -= f.1 * 0.01;
-            self.position.2 -= f.2 * 0.01;
+            delta.0 -= f.0 * speed;
+            delta.1 -= f.1 * speed;
+            delta.2 -= f.2 * speed;
+        }
+
+        if self.move_mode == MoveMode::Walk {
+            if self.moving_up && self.grounded {
+                self.vertical_speed = JUMP_SPEED;
+            }
+            self.vertical_speed -= GRAVITY * dt;
+            delta.1 += self.vertical_speed * dt;
+        }
+
+        self.move_by(delta);
+    }
+
+    /// 動き方に合わせて`delta`だけ動かす
+    fn move_by(&mut self, delta: (f32, f32, f32)) {
+        let from = cgmath::Vector3::from(self.position);
+        let delta = cgmath::Vector3::from(delta);
+        let (mut position, mut grounded) = match (&self.collision, self.move_mode) {
+            (Some(collision), MoveMode::Collide) | (Some(collision), MoveMode::Walk) => {
+                collision.slide(from, delta)
+            }
+            _ => (from + delta, false),
+        };
+
+        if self.move_mode == MoveMode::Walk {
+            if let Some(ground) = self.ground {
+                if position.y < ground + EYE_HEIGHT {
+                    position.y = ground + EYE_HEIGHT;
+                    grounded = true;
+                }
+            }
+            if grounded && self.vertical_speed < 0.0 {
+                self.vertical_speed = 0.0;
+            }
+            self.grounded = grounded;
         }
+
+        self.position = position.into();
     }
 
     pub fn process_input(&mut self, event: &glutin::event::WindowEvent<'_>) {
@@ -205,10 +353,21 @@ impl CameraState {
     }
 }
 
+/// ベクトルのy成分を消して正規化する
+fn horizontal(v: (f32, f32, f32)) -> (f32, f32, f32) {
+    let len = (v.0 * v.0 + v.2 * v.2).sqrt();
+    if len == 0.0 {
+        return (0.0, 0.0, 0.0);
+    }
+    (v.0 / len, 0.0, v.2 / len)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const DT: f32 = 1.0 / 60.0;
+
     #[test]
     /// `view_matrix`がしっかり動くかのテスト
     fn can_calculate_view_matrix() {
@@ -226,4 +385,69 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    /// 衝突モードでは壁の手前で止まるかのテスト
+    fn collide_mode_stops_at_wall() {
+        let mut camera = CameraState::new();
+        camera.set_direction((1.0, 0.0, 0.0));
+        camera.set_collision(Some(crate::collision::wall(1.0, 0.1)));
+        camera.set_move_mode(MoveMode::Collide);
+        camera.moving_forward = true;
+        for _ in 0..200 {
+            camera.update(DT);
+        }
+        let (x, y, z) = camera.get_position();
+        assert!((x - 0.9).abs() < 1e-4);
+        assert!(y.abs() < 1e-6 && z.abs() < 1e-6);
+
+        // 自由飛行に戻すと壁を通り抜ける
+        camera.set_move_mode(MoveMode::Fly);
+        for _ in 0..50 {
+            camera.update(DT);
+        }
+        assert!(camera.get_position().0 > 1.0);
+    }
+
+    #[test]
+    /// 歩行モードで地面に落ちて、目の高さで止まるかのテスト
+    fn walk_mode_falls_to_ground() {
+        let mut camera = CameraState::new();
+        camera.set_position((0.0, 2.0, 0.0));
+        camera.set_direction((0.0, 0.0, 1.0));
+        camera.set_ground(Some(0.0));
+        camera.set_move_mode(MoveMode::Walk);
+        for _ in 0..200 {
+            camera.update(DT);
+        }
+        assert!((camera.get_position().1 - EYE_HEIGHT).abs() < 1e-6);
+        assert!(camera.grounded);
+
+        // 前に歩いても高さは変わらない
+        camera.set_direction((0.0, 1.0, 1.0));
+        camera.moving_forward = true;
+        for _ in 0..10 {
+            camera.update(DT);
+        }
+        let (_, y, z) = camera.get_position();
+        assert!((y - EYE_HEIGHT).abs() < 1e-6);
+        assert!((z - 10.0 * MOVE_SPEED * DT).abs() < 1e-4);
+    }
+
+    #[test]
+    /// 補間の係数が0なら前の位置、1なら今の位置から見た行列になるかのテスト
+    fn interpolates_view_between_updates() {
+        let mut camera = CameraState::new();
+        camera.set_direction((0.0, 0.0, 1.0));
+        let before = camera.get_view();
+        camera.moving_forward = true;
+        camera.update(1.0);
+        let after = camera.get_view();
+        assert_ne!(before, after);
+
+        camera.set_interpolation(0.0);
+        assert_eq!(camera.get_view(), before);
+        camera.set_interpolation(0.5);
+        assert!((camera.get_view()[3][2] - (before[3][2] + after[3][2]) / 2.0).abs() < 1e-6);
+    }
 }
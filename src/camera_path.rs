@@ -0,0 +1,393 @@
+extern crate cgmath;
+use crate::camera::CameraState;
+use cgmath::{InnerSpace, Matrix3, Quaternion, Rotation, Vector3};
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+
+/// キーフレームの間の補間の仕方
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interpolation {
+    /// 前後のキーフレームから接線を決める
+    CatmullRom,
+    /// キーフレームの`tangent`を制御点に使う3次ベジェ曲線
+    /// (`tangent`が無いキーフレームはCatmull-Romと同じ接線にする)
+    Bezier,
+}
+
+/// ある時刻のカメラの位置と向き
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraPose {
+    pub position: Vector3<f32>,
+    /// カメラの+zを前、+yを上に向ける回転
+    pub orientation: Quaternion<f32>,
+    /// 縦の視野角 (ラジアン)
+    pub fov: f32,
+}
+
+impl CameraPose {
+    /// `target`の方を向く姿勢を作る
+    pub fn look_at(position: Vector3<f32>, target: Vector3<f32>, fov: f32) -> CameraPose {
+        let forward = (target - position).normalize();
+        let right = Vector3::unit_y().cross(forward).normalize();
+        let up = forward.cross(right);
+        CameraPose {
+            position,
+            orientation: Quaternion::from(Matrix3::from_cols(right, up, forward)),
+            fov,
+        }
+    }
+
+    pub fn direction(&self) -> Vector3<f32> {
+        self.orientation.rotate_vector(Vector3::unit_z())
+    }
+
+    pub fn up(&self) -> Vector3<f32> {
+        self.orientation.rotate_vector(Vector3::unit_y())
+    }
+
+    pub fn apply(&self, camera: &mut CameraState) {
+        camera.set_position(self.position.into());
+        camera.set_direction(self.direction().into());
+        camera.set_up(self.up().into());
+        camera.set_fov(self.fov);
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    /// 秒
+    pub time: f32,
+    pub pose: CameraPose,
+    /// ベジェ補間で使う、この点での位置の接線 (1秒あたり)
+    pub tangent: Option<Vector3<f32>>,
+}
+
+/// キーフレームを補間するカメラパス
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraPath {
+    pub interpolation: Interpolation,
+    keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn new(interpolation: Interpolation) -> CameraPath {
+        CameraPath {
+            interpolation,
+            keyframes: Vec::new(),
+        }
+    }
+
+    /// キーフレームを時刻順になるように追加する
+    pub fn add(&mut self, keyframe: Keyframe) {
+        let index = self
+            .keyframes
+            .iter()
+            .position(|k| k.time > keyframe.time)
+            .unwrap_or(self.keyframes.len());
+        self.keyframes.insert(index, keyframe);
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// 最後のキーフレームの時刻
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    /// `center`の周りを`duration`秒で1周するパス
+    pub fn turntable(
+        center: Vector3<f32>,
+        radius: f32,
+        height: f32,
+        duration: f32,
+        fov: f32,
+    ) -> CameraPath {
+        const STEPS: usize = 8;
+        let mut path = CameraPath::new(Interpolation::CatmullRom);
+        for i in 0..=STEPS {
+            let angle = i as f32 / STEPS as f32 * std::f32::consts::PI * 2.0;
+            let position = center + Vector3::new(angle.cos(), 0.0, angle.sin()) * radius;
+            let position = position + Vector3::unit_y() * height;
+            path.add(Keyframe {
+                time: duration * i as f32 / STEPS as f32,
+                pose: CameraPose::look_at(position, center, fov),
+                tangent: None,
+            });
+        }
+        path
+    }
+
+    /// 時刻`time`の姿勢を求める。範囲外の時刻は端に合わせる
+    pub fn sample(&self, time: f32) -> Option<CameraPose> {
+        let keys = &self.keyframes;
+        let first = keys.first()?;
+        let last = keys.last()?;
+        if keys.len() == 1 || time <= first.time {
+            return Some(first.pose);
+        }
+        if time >= last.time {
+            return Some(last.pose);
+        }
+
+        let i = keys.iter().rposition(|k| k.time <= time)?;
+        let (k1, k2) = (&keys[i], &keys[i + 1]);
+        let span = k2.time - k1.time;
+        let u = if span > 0.0 {
+            (time - k1.time) / span
+        } else {
+            0.0
+        };
+        // 端では隣のキーフレームを自分自身として扱う
+        let k0 = &keys[i.saturating_sub(1)];
+        let k3 = &keys[(i + 2).min(keys.len() - 1)];
+
+        let (p0, p1, p2, p3) = (
+            k0.pose.position,
+            k1.pose.position,
+            k2.pose.position,
+            k3.pose.position,
+        );
+        let position = match self.interpolation {
+            Interpolation::CatmullRom => catmull_rom(p0, p1, p2, p3, u),
+            Interpolation::Bezier => {
+                // 接線は1秒あたりなので区間の長さを掛けてから制御点にする
+                let t1 = k1.tangent.map_or_else(|| (p2 - p0) * 0.5, |t| t * span);
+                let t2 = k2.tangent.map_or_else(|| (p3 - p1) * 0.5, |t| t * span);
+                bezier(p1, p1 + t1 / 3.0, p2 - t2 / 3.0, p2, u)
+            }
+        };
+        let fov = catmull_rom(k0.pose.fov, k1.pose.fov, k2.pose.fov, k3.pose.fov, u);
+
+        Some(CameraPose {
+            position,
+            orientation: k1.pose.orientation.slerp(k2.pose.orientation, u),
+            fov,
+        })
+    }
+
+    /// テキスト形式で保存する
+    ///
+    /// ```text
+    /// interpolation catmull-rom
+    /// key <time> <x> <y> <z> <qw> <qx> <qy> <qz> <fov> [<tx> <ty> <tz>]
+    /// ```
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.to_text())
+    }
+
+    pub fn load(path: &Path) -> io::Result<CameraPath> {
+        CameraPath::from_text(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let interpolation = match self.interpolation {
+            Interpolation::CatmullRom => "catmull-rom",
+            Interpolation::Bezier => "bezier",
+        };
+        writeln!(text, "interpolation {}", interpolation).unwrap();
+        for k in &self.keyframes {
+            let p = k.pose.position;
+            let q = k.pose.orientation;
+            write!(
+                text,
+                "key {} {} {} {} {} {} {} {} {}",
+                k.time, p.x, p.y, p.z, q.s, q.v.x, q.v.y, q.v.z, k.pose.fov
+            )
+            .unwrap();
+            if let Some(t) = k.tangent {
+                write!(text, " {} {} {}", t.x, t.y, t.z).unwrap();
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> io::Result<CameraPath> {
+        let invalid = |line: usize, message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("camera path line {}: {}", line + 1, message),
+            )
+        };
+
+        let mut path = CameraPath::new(Interpolation::CatmullRom);
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("interpolation") => {
+                    path.interpolation = match words.next() {
+                        Some("catmull-rom") => Interpolation::CatmullRom,
+                        Some("bezier") => Interpolation::Bezier,
+                        _ => return Err(invalid(n, "unknown interpolation")),
+                    }
+                }
+                Some("key") => {
+                    let values = words
+                        .map(str::parse::<f32>)
+                        .collect::<Result<Vec<f32>, _>>()
+                        .map_err(|e| invalid(n, &e.to_string()))?;
+                    if values.len() != 9 && values.len() != 12 {
+                        return Err(invalid(n, "a key needs 9 or 12 numbers"));
+                    }
+                    let v = &values;
+                    path.add(Keyframe {
+                        time: v[0],
+                        pose: CameraPose {
+                            position: Vector3::new(v[1], v[2], v[3]),
+                            orientation: Quaternion::new(v[4], v[5], v[6], v[7]).normalize(),
+                            fov: v[8],
+                        },
+                        tangent: v.get(9..12).map(|t| Vector3::new(t[0], t[1], t[2])),
+                    });
+                }
+                _ => return Err(invalid(n, "expected `interpolation` or `key`")),
+            }
+        }
+        Ok(path)
+    }
+}
+
+/// カメラパスを決まったフレームレートで再生する
+pub struct Playback {
+    path: CameraPath,
+    fps: f32,
+}
+
+impl Playback {
+    pub fn new(path: CameraPath, fps: f32) -> Playback {
+        Playback { path, fps }
+    }
+
+    pub fn path(&self) -> &CameraPath {
+        &self.path
+    }
+
+    /// 最後のキーフレームまでを含むフレーム数
+    pub fn frame_count(&self) -> usize {
+        (self.path.duration() * self.fps).floor() as usize + 1
+    }
+
+    /// `frame`番目のフレームの姿勢。最後まで行ったら先頭に戻る
+    pub fn pose(&self, frame: usize) -> Option<CameraPose> {
+        let frame = frame % self.frame_count();
+        self.path.sample(frame as f32 / self.fps)
+    }
+}
+
+fn catmull_rom<V>(p0: V, p1: V, p2: V, p3: V, t: f32) -> V
+where
+    V: Copy
+        + std::ops::Add<Output = V>
+        + std::ops::Sub<Output = V>
+        + std::ops::Mul<f32, Output = V>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+fn bezier(
+    b0: Vector3<f32>,
+    b1: Vector3<f32>,
+    b2: Vector3<f32>,
+    b3: Vector3<f32>,
+    t: f32,
+) -> Vector3<f32> {
+    let s = 1.0 - t;
+    b0 * (s * s * s) + b1 * (3.0 * s * s * t) + b2 * (3.0 * s * t * t) + b3 * (t * t * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(time: f32, x: f32) -> Keyframe {
+        Keyframe {
+            time,
+            pose: CameraPose::look_at(Vector3::new(x, 0.0, 0.0), Vector3::new(x, 0.0, 10.0), 1.0),
+            tangent: None,
+        }
+    }
+
+    #[test]
+    /// キーフレームの時刻ではキーフレームの位置を通るかのテスト
+    fn passes_through_keyframes() {
+        for &interpolation in &[Interpolation::CatmullRom, Interpolation::Bezier] {
+            let mut path = CameraPath::new(interpolation);
+            path.add(key(0.0, 0.0));
+            path.add(key(2.0, 4.0));
+            path.add(key(1.0, 1.0));
+            for k in path.keyframes() {
+                let pose = path.sample(k.time).unwrap();
+                assert!((pose.position - k.pose.position).magnitude() < 1e-5);
+            }
+            let middle = path.sample(0.5).unwrap().position.x;
+            assert!(middle > 0.0 && middle < 1.0);
+        }
+    }
+
+    #[test]
+    /// 回転が球面線形補間されるかのテスト
+    fn slerps_orientation() {
+        let mut path = CameraPath::new(Interpolation::CatmullRom);
+        let origin = Vector3::new(0.0, 0.0, 0.0);
+        for &(time, target) in &[(0.0, Vector3::unit_z()), (1.0, Vector3::unit_x())] {
+            path.add(Keyframe {
+                time,
+                pose: CameraPose::look_at(origin, target, 1.0),
+                tangent: None,
+            });
+        }
+        let direction = path.sample(0.5).unwrap().direction();
+        let expected = Vector3::new(1.0, 0.0, 1.0).normalize();
+        assert!((direction - expected).magnitude() < 1e-5);
+        assert!((path.sample(0.0).unwrap().up() - Vector3::unit_y()).magnitude() < 1e-5);
+    }
+
+    #[test]
+    /// 保存して読み込むと同じパスになるかのテスト
+    fn text_round_trip() {
+        let mut path = CameraPath::turntable(Vector3::new(0.0, 1.0, 2.0), 3.0, 0.5, 4.0, 0.8);
+        path.interpolation = Interpolation::Bezier;
+        path.add(Keyframe {
+            tangent: Some(Vector3::new(1.0, 0.0, 0.0)),
+            ..key(5.0, 2.0)
+        });
+        let loaded = CameraPath::from_text(&path.to_text()).unwrap();
+        assert_eq!(loaded.interpolation, path.interpolation);
+        assert_eq!(loaded.keyframes().len(), path.keyframes().len());
+        for (a, b) in loaded.keyframes().iter().zip(path.keyframes()) {
+            assert_eq!(a.time, b.time);
+            assert_eq!(a.pose.position, b.pose.position);
+            assert!((a.pose.orientation - b.pose.orientation).magnitude() < 1e-6);
+            assert_eq!(a.pose.fov, b.pose.fov);
+            assert_eq!(a.tangent, b.tangent);
+        }
+        assert!(CameraPath::from_text("key 1 2 3").is_err());
+    }
+
+    #[test]
+    fn playback_uses_fixed_rate() {
+        let playback = Playback::new(
+            CameraPath::turntable(Vector3::unit_y(), 2.0, 0.0, 2.0, 1.0),
+            30.0,
+        );
+        assert_eq!(playback.frame_count(), 61);
+        let first = playback.pose(0).unwrap();
+        assert_eq!(playback.pose(61), Some(first));
+        let quarter = playback.pose(15).unwrap();
+        assert!((quarter.position - Vector3::new(0.0, 1.0, 2.0)).magnitude() < 0.05);
+    }
+}
@@ -0,0 +1,257 @@
+extern crate image;
+use glium::texture::RawImage2d;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// スーパーサンプリングしたスクリーンショットの倍率
+pub const SUPERSAMPLE: u32 = 2;
+
+/// OpenGLの読み出し結果(左下が原点)を、左上が原点の画像にする
+pub fn to_image(raw: RawImage2d<u8>) -> image::RgbaImage {
+    let image = image::RgbaImage::from_raw(raw.width, raw.height, raw.data.into_owned())
+        .expect("pixel data does not match the image size");
+    image::imageops::flip_vertical(&image)
+}
+
+/// ウィンドウに最後に表示したフレームを読み出す
+pub fn read_window(display: &glium::Display) -> image::RgbaImage {
+    to_image(display.read_front_buffer().unwrap())
+}
+
+/// 縦横を`factor`分の1に縮小する
+pub fn downsample(image: &image::RgbaImage, factor: u32) -> image::RgbaImage {
+    let (width, height) = image.dimensions();
+    image::imageops::resize(
+        image,
+        (width / factor).max(1),
+        (height / factor).max(1),
+        image::imageops::FilterType::Triangle,
+    )
+}
+
+/// `screenshot-20261019-130509-123.png`のような、時刻(UTC)の入ったファイル名
+pub fn screenshot_name(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs() as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let seconds_of_day = seconds.rem_euclid(86400);
+    format!(
+        "screenshot-{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}.png",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// 1970-01-01からの日数を年月日にする (Howard Hinnantのアルゴリズム)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// ウィンドウと関係なく、決まった大きさのテクスチャに描画する
+pub struct Offscreen {
+    color: glium::texture::Texture2d,
+    depth: glium::framebuffer::DepthRenderBuffer,
+}
+
+impl Offscreen {
+    pub fn new<F: glium::backend::Facade>(facade: &F, size: (u32, u32)) -> Offscreen {
+        let color = glium::texture::Texture2d::empty_with_format(
+            facade,
+            glium::texture::UncompressedFloatFormat::U8U8U8U8,
+            glium::texture::MipmapsOption::NoMipmap,
+            size.0,
+            size.1,
+        )
+        .unwrap();
+        let depth = glium::framebuffer::DepthRenderBuffer::new(
+            facade,
+            glium::texture::DepthFormat::I24,
+            size.0,
+            size.1,
+        )
+        .unwrap();
+        Offscreen { color, depth }
+    }
+
+    /// `draw`で描いた結果を画像として読み出す
+    pub fn render<F, D>(&self, facade: &F, draw: D) -> image::RgbaImage
+    where
+        F: glium::backend::Facade,
+        D: FnOnce(&mut glium::framebuffer::SimpleFrameBuffer),
+    {
+        let mut framebuffer = glium::framebuffer::SimpleFrameBuffer::with_depth_buffer(
+            facade,
+            &self.color,
+            &self.depth,
+        )
+        .unwrap();
+        draw(&mut framebuffer);
+        to_image(self.color.read())
+    }
+}
+
+/// フレームを連番のPNGに保存し、必要なら外部のエンコーダーにも流す
+pub struct Recorder {
+    directory: PathBuf,
+    fps: f32,
+    frame: usize,
+    encoder_command: Option<String>,
+    encoder: Option<Child>,
+}
+
+impl Recorder {
+    pub fn new(
+        directory: &Path,
+        fps: f32,
+        encoder_command: Option<String>,
+    ) -> io::Result<Recorder> {
+        std::fs::create_dir_all(directory)?;
+        Ok(Recorder {
+            directory: directory.to_path_buf(),
+            fps,
+            frame: 0,
+            encoder_command,
+            encoder: None,
+        })
+    }
+
+    /// 次に保存するフレームの番号
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn record(&mut self, image: &image::RgbaImage) -> io::Result<()> {
+        let path = self.directory.join(format!("frame_{:05}.png", self.frame));
+        image.save(&path).map_err(io::Error::other)?;
+
+        if self.encoder.is_none() {
+            if let Some(command) = &self.encoder_command {
+                self.encoder = Some(spawn_encoder(command, image.dimensions(), self.fps)?);
+            }
+        }
+        if let Some(stdin) = self.encoder.as_mut().and_then(|e| e.stdin.as_mut()) {
+            stdin.write_all(image.as_raw())?;
+        }
+
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// エンコーダーの入力を閉じて、終わるまで待つ
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(mut encoder) = self.encoder.take() {
+            drop(encoder.stdin.take());
+            let status = encoder.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("encoder exited with {}", status)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `{width}` `{height}` `{fps}`を置き換えてから空白で区切る
+fn encoder_args(command: &str, size: (u32, u32), fps: f32) -> Vec<String> {
+    command
+        .split_whitespace()
+        .map(|arg| {
+            arg.replace("{width}", &size.0.to_string())
+                .replace("{height}", &size.1.to_string())
+                .replace("{fps}", &fps.to_string())
+        })
+        .collect()
+}
+
+/// 生のRGBAのフレームを標準入力で受け取るエンコーダーを起動する
+fn spawn_encoder(command: &str, size: (u32, u32), fps: f32) -> io::Result<Child> {
+    let args = encoder_args(command, size, fps);
+    let (program, args) = args
+        .split_first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty encoder command"))?;
+    Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// 下の行から並んだデータが上下反転されるかのテスト
+    fn flips_rows() {
+        let raw = RawImage2d {
+            data: std::borrow::Cow::Owned(vec![1, 1, 1, 1, 2, 2, 2, 2]),
+            width: 1,
+            height: 2,
+            format: glium::texture::ClientFormat::U8U8U8U8,
+        };
+        let image = to_image(raw);
+        assert_eq!(image.get_pixel(0, 0).0, [2, 2, 2, 2]);
+        assert_eq!(image.get_pixel(0, 1).0, [1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn names_screenshots_by_utc_time() {
+        let time = UNIX_EPOCH + std::time::Duration::from_millis(1_792_415_109_042);
+        assert_eq!(screenshot_name(time), "screenshot-20261019-130509-042.png");
+        let leap_day = UNIX_EPOCH + std::time::Duration::from_secs(951868799);
+        assert_eq!(
+            screenshot_name(leap_day),
+            "screenshot-20000229-235959-000.png"
+        );
+    }
+
+    #[test]
+    fn downsamples_by_factor() {
+        let image = image::RgbaImage::from_pixel(8, 6, image::Rgba([200, 100, 50, 255]));
+        let small = downsample(&image, 2);
+        assert_eq!(small.dimensions(), (4, 3));
+        assert_eq!(small.get_pixel(1, 1).0, [200, 100, 50, 255]);
+    }
+
+    #[test]
+    fn substitutes_encoder_args() {
+        let args = encoder_args("ffmpeg -s {width}x{height} -r {fps} -i -", (640, 480), 30.0);
+        assert_eq!(args, vec!["ffmpeg", "-s", "640x480", "-r", "30", "-i", "-"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    /// 連番のファイル名で保存され、エンコーダーに全フレームが渡るかのテスト
+    fn records_numbered_frames() {
+        let directory = std::env::temp_dir().join("opengl-by-rust-recorder-test");
+        let _ = std::fs::remove_dir_all(&directory);
+        let raw_path = directory.join("frames.raw");
+        let command = format!("cp /dev/stdin {}", raw_path.display());
+
+        let mut recorder = Recorder::new(&directory, 30.0, Some(command)).unwrap();
+        let image = image::RgbaImage::from_pixel(4, 2, image::Rgba([10, 20, 30, 255]));
+        recorder.record(&image).unwrap();
+        recorder.record(&image).unwrap();
+        assert_eq!(recorder.frame(), 2);
+        recorder.finish().unwrap();
+
+        assert!(directory.join("frame_00000.png").exists());
+        assert!(directory.join("frame_00001.png").exists());
+        assert_eq!(std::fs::metadata(&raw_path).unwrap().len(), 2 * 4 * 2 * 4);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+extern crate cgmath;
+use crate::bvh::{closest_point_on_triangle, Aabb, Bvh};
+use cgmath::{InnerSpace, Vector3};
+
+/// 押し出しを繰り返す最大回数
+const MAX_ITERATIONS: usize = 8;
+/// この値より上を向いている面は床として扱う
+const MIN_GROUND_NORMAL_Y: f32 = 0.7;
+
+/// 球とシーンの三角形の衝突判定
+///
+/// 三角形はワールド空間で持つ。
+pub struct Collision {
+    world: Bvh,
+    radius: f32,
+}
+
+impl Collision {
+    pub fn new(world: Bvh, radius: f32) -> Collision {
+        Collision { world, radius }
+    }
+
+    /// 球を`from`から`delta`だけ動かし、面に当たったら面に沿って滑らせる
+    ///
+    /// 半径の半分ずつ進めてから押し出すので、薄い面もすり抜けない。
+    /// 動いた後の中心と、床に乗っているかを返す。
+    pub fn slide(&self, from: Vector3<f32>, delta: Vector3<f32>) -> (Vector3<f32>, bool) {
+        let steps = (delta.magnitude() / (self.radius * 0.5)).ceil().max(1.0) as usize;
+        let step = delta / steps as f32;
+
+        let mut position = from;
+        let mut grounded = false;
+        for _ in 0..steps {
+            let (resolved, on_ground) = self.resolve(position + step);
+            position = resolved;
+            grounded |= on_ground;
+        }
+        (position, grounded)
+    }
+
+    /// 三角形にめり込んでいる球を押し出す
+    pub fn resolve(&self, center: Vector3<f32>) -> (Vector3<f32>, bool) {
+        let r = Vector3::new(self.radius, self.radius, self.radius);
+        let mut position = center;
+        let mut grounded = false;
+
+        // 一番深くめり込んでいる三角形から押し出す。
+        // 順番を決めておかないと、隣の三角形の辺に押されて面に沿った方向にずれる
+        for _ in 0..MAX_ITERATIONS {
+            let query = Aabb {
+                min: position - r,
+                max: position + r,
+            };
+            let mut deepest: Option<(f32, Vector3<f32>)> = None;
+            for triangle in self.world.overlapping(&query) {
+                let [a, b, c] = self.world.triangle(triangle);
+                let closest = closest_point_on_triangle(position, a, b, c);
+                let offset = position - closest;
+                let distance = offset.magnitude();
+                if distance >= self.radius || deepest.is_some_and(|(d, _)| d <= distance) {
+                    continue;
+                }
+
+                // 中心が面の上にあるときは面の法線の方向に押し出す
+                let normal = if distance > 1e-6 {
+                    offset / distance
+                } else {
+                    let n = (b - a).cross(c - a);
+                    if n.magnitude2() == 0.0 {
+                        continue;
+                    }
+                    n.normalize()
+                };
+                deepest = Some((distance, normal));
+            }
+
+            match deepest {
+                Some((distance, normal)) => {
+                    position += normal * (self.radius - distance);
+                    grounded |= normal.y > MIN_GROUND_NORMAL_Y;
+                }
+                None => break,
+            }
+        }
+        (position, grounded)
+    }
+}
+
+/// x に立てた大きな壁 (テスト用)
+#[cfg(test)]
+pub(crate) fn wall(x: f32, radius: f32) -> Collision {
+    let positions = vec![
+        Vector3::new(x, -10.0, -10.0),
+        Vector3::new(x, 10.0, -10.0),
+        Vector3::new(x, 10.0, 10.0),
+        Vector3::new(x, -10.0, 10.0),
+    ];
+    Collision::new(Bvh::new(positions, &[0u16, 1, 2, 0, 2, 3]), radius)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// 壁の手前で止まり、壁に沿って滑るかのテスト
+    fn slides_along_wall() {
+        let collision = wall(0.0, 0.5);
+        let (position, grounded) =
+            collision.slide(Vector3::new(-2.0, 0.0, 0.0), Vector3::new(4.0, 0.0, 1.0));
+        assert!((position.x + 0.5).abs() < 1e-4);
+        assert!((position.z - 1.0).abs() < 1e-4);
+        assert!(!grounded);
+    }
+
+    #[test]
+    /// 一度に壁の厚さより大きく動いてもすり抜けないかのテスト
+    fn does_not_tunnel() {
+        let collision = wall(0.0, 0.5);
+        let (position, _) =
+            collision.slide(Vector3::new(-1.0, 0.0, 0.0), Vector3::new(100.0, 0.0, 0.0));
+        assert!(position.x < 0.0);
+    }
+}
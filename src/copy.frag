@@ -0,0 +1,11 @@
+#version 140
+
+in vec2 v_tex_coords;
+
+out vec4 color;
+
+uniform sampler2D u_input;
+
+void main() {
+	color = vec4(texture(u_input, v_tex_coords).rgb, 1.0);
+}
@@ -0,0 +1,277 @@
+extern crate cgmath;
+use crate::bvh::Aabb;
+use crate::camera::{self, CameraState};
+use crate::stats::DrawCounts;
+use cgmath::{Matrix4, SquareMatrix, Vector3, Vector4};
+use glium::Surface;
+use std::rc::Rc;
+
+/// 球を描くときの円の分割数
+const CIRCLE_SEGMENTS: usize = 32;
+
+pub const RED: [f32; 3] = [1.0, 0.0, 0.0];
+pub const GREEN: [f32; 3] = [0.0, 1.0, 0.0];
+pub const BLUE: [f32; 3] = [0.0, 0.0, 1.0];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LineVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+implement_vertex!(LineVertex, position, color);
+
+/// 1フレームの間に描きたい線を溜めておく (ワールド空間)
+#[derive(Default)]
+pub struct DebugLines {
+    vertices: Vec<LineVertex>,
+}
+
+impl DebugLines {
+    pub fn new() -> DebugLines {
+        DebugLines::default()
+    }
+
+    /// 毎フレームの始めに呼んで、前のフレームの線を消す
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    /// 2頂点ずつで1本の線になる
+    pub fn vertices(&self) -> &[LineVertex] {
+        &self.vertices
+    }
+
+    pub fn line(&mut self, a: Vector3<f32>, b: Vector3<f32>, color: [f32; 3]) {
+        self.vertices.push(LineVertex {
+            position: a.into(),
+            color,
+        });
+        self.vertices.push(LineVertex {
+            position: b.into(),
+            color,
+        });
+    }
+
+    /// 箱の12本の辺
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 3]) {
+        self.box_edges(&[0, 1, 2, 3, 4, 5, 6, 7].map(|i| aabb.corner(i)), color);
+    }
+
+    /// 3つの軸の周りの円
+    pub fn sphere(&mut self, center: Vector3<f32>, radius: f32, color: [f32; 3]) {
+        for axis in 0..3 {
+            let point = |i: usize| {
+                let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::PI * 2.0;
+                let (sin, cos) = angle.sin_cos();
+                let mut offset = Vector3::new(0.0, 0.0, 0.0);
+                offset[(axis + 1) % 3] = cos * radius;
+                offset[(axis + 2) % 3] = sin * radius;
+                center + offset
+            };
+            for i in 0..CIRCLE_SEGMENTS {
+                self.line(point(i), point(i + 1), color);
+            }
+        }
+    }
+
+    /// x, y, z軸をそれぞれ赤, 緑, 青で描く
+    pub fn axes(&mut self, origin: Vector3<f32>, length: f32) {
+        self.line(origin, origin + Vector3::new(length, 0.0, 0.0), RED);
+        self.line(origin, origin + Vector3::new(0.0, length, 0.0), GREEN);
+        self.line(origin, origin + Vector3::new(0.0, 0.0, length), BLUE);
+    }
+
+    /// カメラから見える範囲の四角錐台
+    ///
+    /// farまで描くと大きすぎるので、`distance`の深さで切る。
+    pub fn frustum(
+        &mut self,
+        view: [[f32; 4]; 4],
+        perspective: [[f32; 4]; 4],
+        distance: f32,
+        color: [f32; 3],
+    ) {
+        let inverse = match (Matrix4::from(perspective) * Matrix4::from(view)).invert() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+        let unproject = |x: f32, y: f32, z: f32| {
+            let p = inverse * Vector4::new(x, y, z, 1.0);
+            p.truncate() / p.w
+        };
+        // 近い面と遠い面の同じ角は、深さに比例して離れている
+        let t = (distance - camera::ZNEAR) / (camera::ZFAR - camera::ZNEAR);
+        let corner = |i: usize| {
+            let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+            let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+            let near = unproject(x, y, -1.0);
+            if i & 4 == 0 {
+                near
+            } else {
+                near + (unproject(x, y, 1.0) - near) * t
+            }
+        };
+        self.box_edges(&[0, 1, 2, 3, 4, 5, 6, 7].map(corner), color);
+    }
+
+    /// 高さ`y`の水平な格子。原点を通る線は少し明るくする
+    pub fn grid(&mut self, y: f32, half_lines: i32, spacing: f32, color: [f32; 3]) {
+        let extent = half_lines as f32 * spacing;
+        let bright = [color[0] * 1.5, color[1] * 1.5, color[2] * 1.5];
+        for i in -half_lines..=half_lines {
+            let offset = i as f32 * spacing;
+            let color = if i == 0 { bright } else { color };
+            self.line(
+                Vector3::new(offset, y, -extent),
+                Vector3::new(offset, y, extent),
+                color,
+            );
+            self.line(
+                Vector3::new(-extent, y, offset),
+                Vector3::new(extent, y, offset),
+                color,
+            );
+        }
+    }
+
+    /// ビット0がx、1がy、2がzの順に並んだ8つの角を結ぶ
+    fn box_edges(&mut self, corners: &[Vector3<f32>; 8], color: [f32; 3]) {
+        for i in 0..8 {
+            for bit in &[1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corners[i], corners[i | bit], color);
+                }
+            }
+        }
+    }
+}
+
+/// 線をまとめて1回で描く
+pub struct DebugDraw {
+    context: Rc<glium::backend::Context>,
+    program: glium::Program,
+}
+
+impl DebugDraw {
+    pub fn new<F: glium::backend::Facade>(facade: &F) -> DebugDraw {
+        let program = glium::Program::from_source(
+            facade,
+            include_str!("./line.vert"),
+            include_str!("./line.frag"),
+            None,
+        )
+        .unwrap();
+        DebugDraw {
+            context: facade.get_context().clone(),
+            program,
+        }
+    }
+
+    /// line.vertとline.fragのプログラム
+    pub fn program(&self) -> &glium::Program {
+        &self.program
+    }
+
+    pub fn draw<S: Surface>(
+        &self,
+        target: &mut S,
+        camera: &CameraState,
+        lines: &DebugLines,
+    ) -> DrawCounts {
+        if lines.vertices().is_empty() {
+            return DrawCounts::default();
+        }
+        let vertices = glium::VertexBuffer::new(&self.context, lines.vertices()).unwrap();
+        let uniforms = uniform! {
+            model: Into::<[[f32; 4]; 4]>::into(Matrix4::<f32>::identity()),
+            view: camera.get_view(),
+            perspective: camera.get_perspective(),
+        };
+        let params = glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        target
+            .draw(
+                &vertices,
+                glium::index::NoIndices(glium::index::PrimitiveType::LinesList),
+                &self.program,
+                &uniforms,
+                &params,
+            )
+            .unwrap();
+        DrawCounts {
+            draw_calls: 1,
+            triangles: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn length(v: &LineVertex, w: &LineVertex) -> f32 {
+        let d = Vector3::from(w.position) - Vector3::from(v.position);
+        (d.x * d.x + d.y * d.y + d.z * d.z).sqrt()
+    }
+
+    #[test]
+    /// 箱の辺が12本で、全て軸に平行かのテスト
+    fn draws_box_edges() {
+        let mut lines = DebugLines::new();
+        lines.aabb(
+            &Aabb {
+                min: Vector3::new(0.0, 0.0, 0.0),
+                max: Vector3::new(1.0, 2.0, 3.0),
+            },
+            RED,
+        );
+        let vertices = lines.vertices();
+        assert_eq!(vertices.len(), 24);
+        let mut total = 0.0;
+        for pair in vertices.chunks(2) {
+            total += length(&pair[0], &pair[1]);
+        }
+        assert!((total - 4.0 * (1.0 + 2.0 + 3.0)).abs() < 1e-5);
+
+        lines.clear();
+        assert!(lines.vertices().is_empty());
+    }
+
+    #[test]
+    fn sphere_points_are_on_the_surface() {
+        let mut lines = DebugLines::new();
+        let center = Vector3::new(1.0, 2.0, 3.0);
+        lines.sphere(center, 0.5, GREEN);
+        assert_eq!(lines.vertices().len(), 3 * CIRCLE_SEGMENTS * 2);
+        for v in lines.vertices() {
+            let d = Vector3::from(v.position) - center;
+            assert!(((d.x * d.x + d.y * d.y + d.z * d.z).sqrt() - 0.5).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    /// 視錐台の遠い面が、指定した距離で切られるかのテスト
+    fn cuts_frustum_at_distance() {
+        let mut camera = CameraState::new();
+        camera.set_position((0.0, 0.0, 0.0));
+        camera.set_direction((0.0, 0.0, 1.0));
+        camera.set_up((0.0, 1.0, 0.0));
+        let mut lines = DebugLines::new();
+        lines.frustum(camera.get_view(), camera.get_perspective(), 2.0, BLUE);
+        assert_eq!(lines.vertices().len(), 24);
+
+        let far_z = lines
+            .vertices()
+            .iter()
+            .map(|v| v.position[2])
+            .fold(f32::MIN, f32::max);
+        assert!((far_z - 2.0).abs() < 1e-3);
+    }
+}
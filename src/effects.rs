@@ -0,0 +1,587 @@
+extern crate cgmath;
+extern crate image;
+use crate::post::{self, EffectInput, PostEffect, ToneMap, ToneMapSettings};
+use cgmath::{Matrix4, SquareMatrix};
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+/// 設定ファイルが無いときのエフェクト
+pub const DEFAULT_CONFIG: &str = "tonemap\nfxaa\n";
+
+/// 設定ファイルの1行 (`bloom threshold=1.0 intensity=0.1`のような、名前と引数)
+#[derive(Clone, Debug, PartialEq)]
+pub struct EffectConfig {
+    pub name: String,
+    pub parameters: Vec<(String, String)>,
+}
+
+impl EffectConfig {
+    /// `keys`以外の引数があればエラーにする
+    fn check(&self, keys: &[&str]) -> Result<(), String> {
+        match self
+            .parameters
+            .iter()
+            .find(|(k, _)| !keys.contains(&k.as_str()))
+        {
+            Some((key, _)) => Err(format!("{}: unknown parameter `{}`", self.name, key)),
+            None => Ok(()),
+        }
+    }
+
+    fn text(&self, key: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn number(&self, key: &str, default: f32) -> Result<f32, String> {
+        match self.text(key) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("{}: invalid number `{}={}`", self.name, key, value)),
+            None => Ok(default),
+        }
+    }
+
+    /// 0以上の整数の引数
+    fn count(&self, key: &str, default: u32) -> Result<u32, String> {
+        match self.text(key) {
+            Some(value) => value.parse().map_err(|_| {
+                format!(
+                    "{}: `{}={}` must be a non-negative integer",
+                    self.name, key, value
+                )
+            }),
+            None => Ok(default),
+        }
+    }
+}
+
+/// リニアな明るさを扱うので、`tonemap`より前に掛けるエフェクト
+const HDR_EFFECTS: [&str; 2] = ["ssao", "bloom"];
+
+/// `tonemap`がちょうど1つあって、HDRのエフェクトがそれより前にあるか調べる
+fn check_order(configs: &[EffectConfig]) -> Result<(), String> {
+    let tonemaps: Vec<usize> = configs
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.name == "tonemap")
+        .map(|(i, _)| i)
+        .collect();
+    let tonemap = match tonemaps[..] {
+        [i] => i,
+        [] => return Err("post effects: `tonemap` is missing".to_string()),
+        _ => return Err("post effects: `tonemap` appears more than once".to_string()),
+    };
+    match configs[tonemap..]
+        .iter()
+        .find(|c| HDR_EFFECTS.contains(&c.name.as_str()))
+    {
+        Some(config) => Err(format!(
+            "post effects: `{}` must come before `tonemap`",
+            config.name
+        )),
+        None => Ok(()),
+    }
+}
+
+/// 1行に1つのエフェクトを、掛ける順に書く
+///
+/// ```text
+/// # 名前 引数=値 ...
+/// ssao radius=0.3
+/// bloom threshold=1.0 intensity=0.1
+/// tonemap curve=aces exposure=0
+/// lut contrast=1.1 saturation=1.2
+/// fxaa
+/// vignette strength=0.4
+/// ```
+pub fn parse_config(text: &str) -> io::Result<Vec<EffectConfig>> {
+    let mut configs = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap().to_string();
+        let parameters = words
+            .map(|word| match word.split_once('=') {
+                Some((key, value)) => Ok((key.to_string(), value.to_string())),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "post effects line {}: expected key=value, got `{}`",
+                        n + 1,
+                        word
+                    ),
+                )),
+            })
+            .collect::<io::Result<_>>()?;
+        configs.push(EffectConfig { name, parameters });
+    }
+    Ok(configs)
+}
+
+pub fn load_config(path: &Path) -> io::Result<Vec<EffectConfig>> {
+    parse_config(&std::fs::read_to_string(path)?)
+}
+
+/// 設定からエフェクトを作る
+///
+/// `tonemap`の引数はエフェクトではなく`settings`に入る (キーで変えられるようにするため)。
+pub fn create<F: glium::backend::Facade>(
+    facade: &F,
+    config: &EffectConfig,
+    settings: &mut ToneMapSettings,
+) -> Result<Box<dyn PostEffect>, String> {
+    match config.name.as_str() {
+        "tonemap" => {
+            config.check(&["curve", "exposure"])?;
+            if let Some(curve) = config.text("curve") {
+                settings.tone_mapping = curve.parse()?;
+            }
+            settings.exposure = config.number("exposure", settings.exposure)?;
+            Ok(Box::new(ToneMap::new(facade)))
+        }
+        "fxaa" => {
+            config.check(&["span"])?;
+            Ok(Box::new(Fxaa::new(facade, config.number("span", 8.0)?)))
+        }
+        "bloom" => {
+            config.check(&["threshold", "intensity", "iterations"])?;
+            Ok(Box::new(Bloom::new(
+                facade,
+                config.number("threshold", 1.0)?,
+                config.number("intensity", 0.1)?,
+                config.count("iterations", 3)?,
+            )))
+        }
+        "vignette" => {
+            config.check(&["strength", "radius", "softness"])?;
+            Ok(Box::new(Vignette::new(
+                facade,
+                config.number("strength", 0.4)?,
+                config.number("radius", 0.6)?,
+                config.number("softness", 0.5)?,
+            )))
+        }
+        "lut" => {
+            config.check(&["file", "contrast", "saturation", "warmth"])?;
+            let lut = match config.text("file") {
+                Some(file) => image::open(file)
+                    .map_err(|e| format!("lut: {}: {}", file, e))?
+                    .to_rgba8(),
+                None => grading_lut(
+                    16,
+                    config.number("contrast", 1.0)?,
+                    config.number("saturation", 1.0)?,
+                    config.number("warmth", 0.0)?,
+                ),
+            };
+            Ok(Box::new(ColorGrading::new(facade, &lut)?))
+        }
+        "ssao" => {
+            config.check(&["radius", "intensity", "bias"])?;
+            Ok(Box::new(Ssao::new(
+                facade,
+                config.number("radius", 0.3)?,
+                config.number("intensity", 1.0)?,
+                config.number("bias", 0.02)?,
+            )))
+        }
+        name => Err(format!("unknown post effect `{}`", name)),
+    }
+}
+
+/// 設定の順にエフェクトを作る (`tonemap`が無いか、HDRのエフェクトがその後にあればエラー)
+pub fn create_all<F: glium::backend::Facade>(
+    facade: &F,
+    configs: &[EffectConfig],
+    settings: &mut ToneMapSettings,
+) -> Result<Vec<Box<dyn PostEffect>>, String> {
+    check_order(configs)?;
+    configs
+        .iter()
+        .map(|config| create(facade, config, settings))
+        .collect()
+}
+
+/// 画面に出す色を扱うエフェクトのプログラム
+fn program<F: glium::backend::Facade>(facade: &F, fragment_shader: &str) -> glium::Program {
+    post::display_program(facade, include_str!("./fullscreen.vert"), fragment_shader)
+}
+
+/// `size`と大きさが違えば作り直す
+fn resize<F: glium::backend::Facade>(
+    facade: &F,
+    texture: &mut Option<glium::texture::Texture2d>,
+    size: (u32, u32),
+) {
+    if texture.as_ref().map(|t| t.dimensions()) != Some(size) {
+        *texture = Some(post::color_texture(facade, size));
+    }
+}
+
+/// 輝度の差が大きい縁をぼかすアンチエイリアス
+///
+/// トーンマッピングの後に掛ける。
+pub struct Fxaa {
+    program: glium::Program,
+    span: f32,
+}
+
+impl Fxaa {
+    pub fn new<F: glium::backend::Facade>(facade: &F, span: f32) -> Fxaa {
+        Fxaa {
+            program: program(facade, include_str!("./fxaa.frag")),
+            span,
+        }
+    }
+}
+
+impl PostEffect for Fxaa {
+    fn name(&self) -> &str {
+        "fxaa"
+    }
+
+    fn apply(&mut self, input: &EffectInput, output: &mut glium::framebuffer::SimpleFrameBuffer) {
+        let (width, height) = input.size();
+        let uniforms = uniform! {
+            u_input: post::linear(input.color),
+            u_texel: [1.0 / width as f32, 1.0 / height as f32],
+            u_span: self.span,
+        };
+        input.draw_fullscreen(output, &self.program, &uniforms);
+    }
+}
+
+/// 明るいところを周りににじませる
+///
+/// 半分の大きさで明るいところを抜き出し、縦横にぼかしてから足す。
+/// トーンマッピングの前に掛ける。
+pub struct Bloom {
+    context: Rc<glium::backend::Context>,
+    program: glium::Program,
+    threshold: f32,
+    intensity: f32,
+    iterations: u32,
+    bright: Option<glium::texture::Texture2d>,
+    blurred: Option<glium::texture::Texture2d>,
+}
+
+impl Bloom {
+    pub fn new<F: glium::backend::Facade>(
+        facade: &F,
+        threshold: f32,
+        intensity: f32,
+        iterations: u32,
+    ) -> Bloom {
+        Bloom {
+            context: facade.get_context().clone(),
+            program: program(facade, include_str!("./bloom.frag")),
+            threshold,
+            intensity,
+            iterations,
+            bright: None,
+            blurred: None,
+        }
+    }
+}
+
+impl PostEffect for Bloom {
+    fn name(&self) -> &str {
+        "bloom"
+    }
+
+    fn apply(&mut self, input: &EffectInput, output: &mut glium::framebuffer::SimpleFrameBuffer) {
+        let (width, height) = input.size();
+        let half = ((width / 2).max(1), (height / 2).max(1));
+        resize(&self.context, &mut self.bright, half);
+        resize(&self.context, &mut self.blurred, half);
+        let (bright, blurred) = (
+            self.bright.as_ref().unwrap(),
+            self.blurred.as_ref().unwrap(),
+        );
+        let texel = [1.0 / half.0 as f32, 1.0 / half.1 as f32];
+
+        // 0: 明るいところを抜き出す 1: u_directionの向きにぼかす 2: 元の色に足す
+        let pass = |pass: i32,
+                    source: &glium::texture::Texture2d,
+                    bloom: &glium::texture::Texture2d,
+                    direction: [f32; 2],
+                    target: &mut glium::framebuffer::SimpleFrameBuffer| {
+            let uniforms = uniform! {
+                u_pass: pass,
+                u_input: post::linear(source),
+                u_bloom: post::linear(bloom),
+                u_direction: [direction[0] * texel[0], direction[1] * texel[1]],
+                u_threshold: self.threshold,
+                u_intensity: self.intensity,
+            };
+            input.draw_fullscreen(target, &self.program, &uniforms);
+        };
+        let mut bright_target =
+            glium::framebuffer::SimpleFrameBuffer::new(&self.context, bright).unwrap();
+        let mut blurred_target =
+            glium::framebuffer::SimpleFrameBuffer::new(&self.context, blurred).unwrap();
+        pass(0, input.color, blurred, [0.0, 0.0], &mut bright_target);
+        for _ in 0..self.iterations {
+            pass(1, bright, bright, [1.0, 0.0], &mut blurred_target);
+            pass(1, blurred, blurred, [0.0, 1.0], &mut bright_target);
+        }
+        pass(2, input.color, bright, [0.0, 0.0], output);
+    }
+}
+
+/// 画面の端を暗くする
+pub struct Vignette {
+    program: glium::Program,
+    strength: f32,
+    radius: f32,
+    softness: f32,
+}
+
+impl Vignette {
+    pub fn new<F: glium::backend::Facade>(
+        facade: &F,
+        strength: f32,
+        radius: f32,
+        softness: f32,
+    ) -> Vignette {
+        Vignette {
+            program: program(facade, include_str!("./vignette.frag")),
+            strength,
+            radius,
+            softness,
+        }
+    }
+}
+
+impl PostEffect for Vignette {
+    fn name(&self) -> &str {
+        "vignette"
+    }
+
+    fn apply(&mut self, input: &EffectInput, output: &mut glium::framebuffer::SimpleFrameBuffer) {
+        let (width, height) = input.size();
+        let uniforms = uniform! {
+            u_input: post::linear(input.color),
+            u_aspect: width as f32 / height as f32,
+            u_strength: self.strength,
+            u_radius: self.radius,
+            u_softness: self.softness,
+        };
+        input.draw_fullscreen(output, &self.program, &uniforms);
+    }
+}
+
+/// 色調補正の3D LUTを、青ごとに`size`x`size`のマスを横に並べた画像にする
+///
+/// マスの中は左から右に赤、上から下に緑が0から1になる。何も変えなければ元の色のまま。
+pub fn grading_lut(size: u32, contrast: f32, saturation: f32, warmth: f32) -> image::RgbaImage {
+    let max = (size - 1) as f32;
+    image::RgbaImage::from_fn(size * size, size, |x, y| {
+        let mut c = [
+            (x % size) as f32 / max,
+            y as f32 / max,
+            (x / size) as f32 / max,
+        ];
+        // 真ん中の灰色を中心にコントラストを変える
+        for v in &mut c {
+            *v = (*v - 0.5) * contrast + 0.5;
+        }
+        let luma = 0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2];
+        for v in &mut c {
+            *v = luma + (*v - luma) * saturation;
+        }
+        c[0] += warmth * 0.1;
+        c[2] -= warmth * 0.1;
+        let to_u8 = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        image::Rgba([to_u8(c[0]), to_u8(c[1]), to_u8(c[2]), 255])
+    })
+}
+
+/// 3D LUTで色を置き換える色調補正
+///
+/// トーンマッピングの後に掛ける。
+pub struct ColorGrading {
+    program: glium::Program,
+    lut: glium::texture::Texture2d,
+}
+
+impl ColorGrading {
+    pub fn new<F: glium::backend::Facade>(
+        facade: &F,
+        lut: &image::RgbaImage,
+    ) -> Result<ColorGrading, String> {
+        let (width, height) = lut.dimensions();
+        if height < 2 || width != height * height {
+            return Err(format!(
+                "lut: expected a {}x{} image, got {}x{}",
+                height * height,
+                height,
+                width,
+                height
+            ));
+        }
+        // 画像の1行目がテクスチャのv=0になる (緑が0)
+        let raw = glium::texture::RawImage2d::from_raw_rgba(lut.as_raw().clone(), (width, height));
+        Ok(ColorGrading {
+            program: program(facade, include_str!("./lut.frag")),
+            lut: glium::texture::Texture2d::new(facade, raw).unwrap(),
+        })
+    }
+}
+
+impl PostEffect for ColorGrading {
+    fn name(&self) -> &str {
+        "lut"
+    }
+
+    fn apply(&mut self, input: &EffectInput, output: &mut glium::framebuffer::SimpleFrameBuffer) {
+        let uniforms = uniform! {
+            u_input: post::linear(input.color),
+            u_lut: post::linear(&self.lut),
+            u_lut_size: self.lut.height() as f32,
+        };
+        input.draw_fullscreen(output, &self.program, &uniforms);
+    }
+}
+
+/// 深度から周りの物に遮られている割合を求めて暗くする
+///
+/// トーンマッピングの前に掛ける。
+pub struct Ssao {
+    context: Rc<glium::backend::Context>,
+    program: glium::Program,
+    radius: f32,
+    intensity: f32,
+    bias: f32,
+    occlusion: Option<glium::texture::Texture2d>,
+}
+
+impl Ssao {
+    pub fn new<F: glium::backend::Facade>(
+        facade: &F,
+        radius: f32,
+        intensity: f32,
+        bias: f32,
+    ) -> Ssao {
+        Ssao {
+            context: facade.get_context().clone(),
+            program: program(facade, include_str!("./ssao.frag")),
+            radius,
+            intensity,
+            bias,
+            occlusion: None,
+        }
+    }
+}
+
+impl PostEffect for Ssao {
+    fn name(&self) -> &str {
+        "ssao"
+    }
+
+    fn apply(&mut self, input: &EffectInput, output: &mut glium::framebuffer::SimpleFrameBuffer) {
+        resize(&self.context, &mut self.occlusion, input.size());
+        let occlusion = self.occlusion.as_ref().unwrap();
+        let (width, height) = input.size();
+        let inverse = Matrix4::from(input.perspective)
+            .invert()
+            .unwrap_or_else(Matrix4::identity);
+
+        // 0: 遮られている割合を求める 1: ぼかして元の色に掛ける
+        let pass = |pass: i32,
+                    occlusion: &glium::texture::Texture2d,
+                    target: &mut glium::framebuffer::SimpleFrameBuffer| {
+            let uniforms = uniform! {
+                u_pass: pass,
+                u_input: post::linear(input.color),
+                u_occlusion: post::linear(occlusion),
+                u_depth: input.depth.sampled()
+                    .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
+                    .minify_filter(glium::uniforms::MinifySamplerFilter::Nearest)
+                    .wrap_function(glium::uniforms::SamplerWrapFunction::Clamp),
+                u_projection: input.perspective,
+                u_inverse_projection: Into::<[[f32; 4]; 4]>::into(inverse),
+                u_texel: [1.0 / width as f32, 1.0 / height as f32],
+                u_radius: self.radius,
+                u_intensity: self.intensity,
+                u_bias: self.bias,
+            };
+            input.draw_fullscreen(target, &self.program, &uniforms);
+        };
+        let mut occlusion_target =
+            glium::framebuffer::SimpleFrameBuffer::new(&self.context, occlusion).unwrap();
+        // 書き込み先と同じテクスチャを読まないように、まだ使わないu_occlusionには入力を渡す
+        pass(0, input.color, &mut occlusion_target);
+        pass(1, occlusion, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_config() {
+        let configs = parse_config(
+            "# comment\n\nssao radius=0.5\n  bloom threshold=2 intensity=0.2\ntonemap\n",
+        )
+        .unwrap();
+        assert_eq!(configs.len(), 3);
+        assert_eq!(configs[0].name, "ssao");
+        assert_eq!(configs[0].number("radius", 0.3), Ok(0.5));
+        assert_eq!(configs[0].number("bias", 0.02), Ok(0.02));
+        assert_eq!(configs[1].number("intensity", 0.1), Ok(0.2));
+        assert!(configs[1].check(&["threshold", "intensity"]).is_ok());
+        assert!(configs[1].check(&["threshold"]).is_err());
+        assert!(configs[2].parameters.is_empty());
+
+        assert!(parse_config("bloom threshold").is_err());
+        let config = &parse_config("fxaa span=wide").unwrap()[0];
+        assert!(config.number("span", 8.0).is_err());
+        assert_eq!(parse_config(DEFAULT_CONFIG).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn checks_effect_order() {
+        let check = |text: &str| check_order(&parse_config(text).unwrap());
+        assert!(check(DEFAULT_CONFIG).is_ok());
+        assert!(check("ssao\nbloom\ntonemap\nlut\nfxaa\nvignette").is_ok());
+        assert!(check("fxaa").is_err());
+        assert!(check("ssao").is_err());
+        assert!(check("tonemap\nbloom").is_err());
+        assert!(check("ssao\ntonemap\nssao").is_err());
+        assert!(check("tonemap\nfxaa\ntonemap").is_err());
+
+        let bloom = |iterations: &str| {
+            parse_config(&format!("bloom iterations={}", iterations)).unwrap()[0]
+                .count("iterations", 3)
+        };
+        assert_eq!(bloom("5"), Ok(5));
+        assert!(bloom("2.5").is_err());
+        assert!(bloom("-1").is_err());
+    }
+
+    #[test]
+    /// 何も変えないLUTが、元の色を指しているかのテスト
+    fn neutral_lut_is_identity() {
+        let lut = grading_lut(16, 1.0, 1.0, 0.0);
+        assert_eq!(lut.dimensions(), (256, 16));
+        let value = |i: u32| (i as f32 / 15.0 * 255.0).round() as u8;
+        for &(r, g, b) in &[(0, 0, 0), (15, 15, 15), (3, 7, 11), (15, 0, 8)] {
+            let pixel = lut.get_pixel(b * 16 + r, g);
+            assert_eq!(pixel.0, [value(r), value(g), value(b), 255]);
+        }
+        // 彩度を0にすると灰色になる
+        let gray = grading_lut(4, 1.0, 0.0, 0.0);
+        let pixel = gray.get_pixel(3, 0);
+        assert_eq!(pixel[0], pixel[1]);
+        assert_eq!(pixel[1], pixel[2]);
+    }
+}
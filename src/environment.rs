@@ -0,0 +1,665 @@
+extern crate cgmath;
+extern crate image;
+use crate::post::{self, ScreenVertex};
+use crate::stats::DrawCounts;
+use cgmath::{InnerSpace, Matrix3, Matrix4, SquareMatrix, Vector3};
+use glium::texture::CubeLayer;
+use glium::Surface;
+use std::f32::consts::PI;
+use std::io;
+use std::path::Path;
+
+/// OpenGLのキューブマップの面の順番
+pub const FACES: [CubeLayer; 6] = [
+    CubeLayer::PositiveX,
+    CubeLayer::NegativeX,
+    CubeLayer::PositiveY,
+    CubeLayer::NegativeY,
+    CubeLayer::PositiveZ,
+    CubeLayer::NegativeZ,
+];
+/// 6枚の画像から読み込むときのファイル名 (拡張子は何でもよい)
+pub const FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+/// 何も指定されなかったときに作る空の1面の大きさ
+const DEFAULT_SIZE: u32 = 128;
+/// 拡散光のキューブマップの1面の大きさ (ゆっくりしか変わらないので小さくてよい)
+const IRRADIANCE_SIZE: u32 = 32;
+/// 鏡面反射のキューブマップの一番細かい段の大きさと段数
+const PREFILTER_SIZE: u32 = 128;
+const PREFILTER_LEVELS: u32 = 6;
+/// 鏡面反射をぼかすときの1テクセルあたりのサンプル数
+const PREFILTER_SAMPLES: usize = 64;
+
+/// 面`face`の上の位置`(s, t)`(0から1、tは上から下)が指す向き
+pub fn face_direction(face: usize, s: f32, t: f32) -> Vector3<f32> {
+    let s = 2.0 * s - 1.0;
+    let t = 2.0 * t - 1.0;
+    let direction = match face {
+        0 => Vector3::new(1.0, -t, -s),
+        1 => Vector3::new(-1.0, -t, s),
+        2 => Vector3::new(s, 1.0, t),
+        3 => Vector3::new(s, -1.0, -t),
+        4 => Vector3::new(s, -t, 1.0),
+        _ => Vector3::new(-s, -t, -1.0),
+    };
+    direction.normalize()
+}
+
+/// `face_direction`の逆。向きがどの面のどこに当たるか
+pub fn direction_to_face(direction: Vector3<f32>) -> (usize, f32, f32) {
+    let abs = Vector3::new(direction.x.abs(), direction.y.abs(), direction.z.abs());
+    let (face, s, t, major) = if abs.x >= abs.y && abs.x >= abs.z {
+        if direction.x > 0.0 {
+            (0, -direction.z, -direction.y, abs.x)
+        } else {
+            (1, direction.z, -direction.y, abs.x)
+        }
+    } else if abs.y >= abs.z {
+        if direction.y > 0.0 {
+            (2, direction.x, direction.z, abs.y)
+        } else {
+            (3, direction.x, -direction.z, abs.y)
+        }
+    } else if direction.z > 0.0 {
+        (4, direction.x, -direction.y, abs.z)
+    } else {
+        (5, -direction.x, -direction.y, abs.z)
+    };
+    (face, (s / major + 1.0) * 0.5, (t / major + 1.0) * 0.5)
+}
+
+/// 浮動小数点のRGBの画像 (左上から1行ずつ)
+#[derive(Clone, Debug, PartialEq)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl HdrImage {
+    /// `.hdr`はそのまま、それ以外はsRGBとみなしてリニアに直して読み込む
+    pub fn load(path: &Path) -> io::Result<HdrImage> {
+        let invalid = |e: image::ImageError| io::Error::new(io::ErrorKind::InvalidData, e);
+        let is_hdr = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("hdr"));
+        if is_hdr {
+            let reader = io::BufReader::new(std::fs::File::open(path)?);
+            let decoder = image::codecs::hdr::HdrDecoder::new(reader).map_err(invalid)?;
+            let metadata = decoder.metadata();
+            let pixels = decoder.read_image_hdr().map_err(invalid)?;
+            Ok(HdrImage {
+                width: metadata.width,
+                height: metadata.height,
+                pixels: pixels.into_iter().map(|p| p.0).collect(),
+            })
+        } else {
+            let image = image::open(path).map_err(invalid)?.to_rgb8();
+            let to_linear = |c: u8| (c as f32 / 255.0).powf(2.2);
+            Ok(HdrImage {
+                width: image.width(),
+                height: image.height(),
+                pixels: image
+                    .pixels()
+                    .map(|p| [to_linear(p[0]), to_linear(p[1]), to_linear(p[2])])
+                    .collect(),
+            })
+        }
+    }
+
+    /// 0から1の座標で、周りの4ピクセルを混ぜて読む (横方向はつながっている)
+    pub fn sample(&self, u: f32, v: f32) -> [f32; 3] {
+        let x = u * self.width as f32 - 0.5;
+        let y = (v * self.height as f32 - 0.5).clamp(0.0, self.height as f32 - 1.0);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let pixel = |x: f32, y: f32| {
+            let x = (x as i64).rem_euclid(self.width as i64) as u32;
+            let y = (y as u32).min(self.height - 1);
+            self.pixels[(y * self.width + x) as usize]
+        };
+        let (a, b) = (pixel(x0, y0), pixel(x0 + 1.0, y0));
+        let (c, d) = (pixel(x0, y0 + 1.0), pixel(x0 + 1.0, y0 + 1.0));
+        let mut result = [0.0; 3];
+        for i in 0..3 {
+            let top = a[i] + (b[i] - a[i]) * fx;
+            let bottom = c[i] + (d[i] - c[i]) * fx;
+            result[i] = top + (bottom - top) * fy;
+        }
+        result
+    }
+}
+
+/// キューブマップの6面をCPUで持っておく (反射の計算にも使う)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Environment {
+    pub size: u32,
+    /// `FACES`の順に、左上から1行ずつ
+    pub faces: Vec<Vec<[f32; 3]>>,
+}
+
+impl Environment {
+    /// 各面の各ピクセルの向きから色を決める
+    pub fn from_fn<F: Fn(Vector3<f32>) -> [f32; 3]>(size: u32, color: F) -> Environment {
+        let faces = (0..6)
+            .map(|face| {
+                let mut pixels = Vec::with_capacity((size * size) as usize);
+                for y in 0..size {
+                    for x in 0..size {
+                        let s = (x as f32 + 0.5) / size as f32;
+                        let t = (y as f32 + 0.5) / size as f32;
+                        pixels.push(color(face_direction(face, s, t)));
+                    }
+                }
+                pixels
+            })
+            .collect();
+        Environment { size, faces }
+    }
+
+    /// 地平線から天頂へのグラデーションと、`sun`の向きにある太陽
+    pub fn sky(sun: Vector3<f32>) -> Environment {
+        let sun = sun.normalize();
+        Environment::from_fn(DEFAULT_SIZE, |direction| {
+            // リニアな明るさ
+            let zenith = [0.03, 0.09, 0.4];
+            let horizon = [0.45, 0.5, 0.6];
+            let ground = [0.05, 0.04, 0.035];
+            let (from, to, t) = if direction.y >= 0.0 {
+                (horizon, zenith, direction.y.sqrt())
+            } else {
+                (horizon, ground, (-direction.y * 4.0).min(1.0))
+            };
+            // 小さく明るい太陽と、その周りのぼんやりした光
+            let toward_sun = direction.dot(sun).max(0.0);
+            let glow = toward_sun.powf(512.0) * 8.0 + toward_sun.powf(16.0) * 0.3;
+            let mut color = [0.0; 3];
+            for i in 0..3 {
+                color[i] = from[i] + (to[i] - from[i]) * t + glow;
+            }
+            color
+        })
+    }
+
+    /// 正距円筒図法のパノラマから作る
+    pub fn from_equirectangular(image: &HdrImage, size: u32) -> Environment {
+        Environment::from_fn(size, |direction| {
+            // +zを画像の真ん中にして、右を向く(-x)と右に進む
+            let u = 0.5 + (-direction.x).atan2(direction.z) / (2.0 * PI);
+            let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+            image.sample(u, v)
+        })
+    }
+
+    /// 6枚の画像(`px.png`など)のあるディレクトリか、パノラマの画像を読み込む
+    pub fn load(path: &Path) -> io::Result<Environment> {
+        if !path.is_dir() {
+            let image = HdrImage::load(path)?;
+            return Ok(Environment::from_equirectangular(
+                &image,
+                (image.width / 4).max(1),
+            ));
+        }
+
+        let mut faces = Vec::with_capacity(6);
+        let mut size = None;
+        for name in &FACE_NAMES {
+            let file = std::fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .find(|file| file.file_stem().is_some_and(|stem| stem == *name))
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{}: no image named {}", path.display(), name),
+                    )
+                })?;
+            let image = HdrImage::load(&file)?;
+            if image.width != image.height || size.is_some_and(|s| s != image.width) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: faces must be squares of the same size", file.display()),
+                ));
+            }
+            size = Some(image.width);
+            faces.push(image.pixels);
+        }
+        Ok(Environment {
+            size: size.unwrap_or(0),
+            faces,
+        })
+    }
+
+    /// 向きの一番近いピクセルの色
+    pub fn sample(&self, direction: Vector3<f32>) -> [f32; 3] {
+        let (face, s, t) = direction_to_face(direction);
+        let x = ((s * self.size as f32) as u32).min(self.size - 1);
+        let y = ((t * self.size as f32) as u32).min(self.size - 1);
+        self.faces[face][(y * self.size + x) as usize]
+    }
+
+    /// 縦横を半分にする (2x2のピクセルの平均)
+    pub fn downsample(&self) -> Environment {
+        let size = (self.size / 2).max(1);
+        let step = self.size / size;
+        let faces = self
+            .faces
+            .iter()
+            .map(|pixels| {
+                let mut result = Vec::with_capacity((size * size) as usize);
+                for y in 0..size {
+                    for x in 0..size {
+                        let mut sum = [0.0; 3];
+                        for dy in 0..step {
+                            for dx in 0..step {
+                                let p =
+                                    pixels[((y * step + dy) * self.size + x * step + dx) as usize];
+                                for i in 0..3 {
+                                    sum[i] += p[i];
+                                }
+                            }
+                        }
+                        let n = (step * step) as f32;
+                        result.push([sum[0] / n, sum[1] / n, sum[2] / n]);
+                    }
+                }
+                result
+            })
+            .collect();
+        Environment { size, faces }
+    }
+
+    /// 拡散反射に使う、各向きの周りの半球から来る光の平均
+    ///
+    /// 3次までの球面調和関数に落としてから戻すので、テクセルごとに全方向を足さずに済む。
+    pub fn irradiance(&self, size: u32) -> Environment {
+        let mut coefficients = [[0.0f32; 3]; 9];
+        for (face, pixels) in self.faces.iter().enumerate() {
+            for y in 0..self.size {
+                for x in 0..self.size {
+                    let s = (x as f32 + 0.5) / self.size as f32;
+                    let t = (y as f32 + 0.5) / self.size as f32;
+                    // テクセルが球の上で占める立体角
+                    let (u, v) = (2.0 * s - 1.0, 2.0 * t - 1.0);
+                    let solid_angle =
+                        4.0 / (self.size * self.size) as f32 / (1.0 + u * u + v * v).powf(1.5);
+                    let basis = spherical_harmonics(face_direction(face, s, t));
+                    let color = pixels[(y * self.size + x) as usize];
+                    for (coefficient, b) in coefficients.iter_mut().zip(&basis) {
+                        for i in 0..3 {
+                            coefficient[i] += color[i] * b * solid_angle;
+                        }
+                    }
+                }
+            }
+        }
+        // cosで畳み込んでからπで割る (白い面ならそのまま掛けられる)
+        let bands = [
+            1.0,
+            2.0 / 3.0,
+            2.0 / 3.0,
+            2.0 / 3.0,
+            0.25,
+            0.25,
+            0.25,
+            0.25,
+            0.25,
+        ];
+        Environment::from_fn(size, |direction| {
+            let basis = spherical_harmonics(direction);
+            let mut color = [0.0; 3];
+            for ((coefficient, b), band) in coefficients.iter().zip(&basis).zip(&bands) {
+                for i in 0..3 {
+                    color[i] += coefficient[i] * b * band;
+                }
+            }
+            color.map(|c| c.max(0.0))
+        })
+    }
+
+    /// 鏡面反射に使う、ラフネスごとにぼかした景色
+    ///
+    /// `levels`段のミップマップになり、i段目はラフネス`i / (levels - 1)`のGGXで
+    /// 重み付けした平均になる。
+    pub fn prefiltered(&self, levels: u32) -> Vec<Environment> {
+        // ぼかすときに、サンプルの間隔に合った粗さの景色から読む
+        let mut sources = vec![self.clone()];
+        while sources.last().unwrap().size > 1 {
+            let next = sources.last().unwrap().downsample();
+            sources.push(next);
+        }
+        let mut result = vec![self.clone()];
+        for level in 1..levels {
+            let roughness = level as f32 / (levels - 1).max(1) as f32;
+            let size = (self.size >> level).max(1);
+            result.push(Environment::from_fn(size, |normal| {
+                prefilter(&sources, normal, roughness)
+            }));
+        }
+        result
+    }
+
+    /// GPUのキューブマップにする
+    pub fn to_cubemap<F: glium::backend::Facade>(&self, facade: &F) -> glium::texture::Cubemap {
+        cubemap_from_levels(facade, std::slice::from_ref(self))
+    }
+}
+
+/// 3次までの球面調和関数の基底
+fn spherical_harmonics(d: Vector3<f32>) -> [f32; 9] {
+    [
+        0.282095,
+        0.488603 * d.y,
+        0.488603 * d.z,
+        0.488603 * d.x,
+        1.092548 * d.x * d.y,
+        1.092548 * d.y * d.z,
+        0.315392 * (3.0 * d.z * d.z - 1.0),
+        1.092548 * d.x * d.z,
+        0.546274 * (d.x * d.x - d.y * d.y),
+    ]
+}
+
+/// GGXに沿って散らした向きから景色を読んで平均する
+///
+/// 視線と反射の向きが法線と同じとみなす (Karisの近似)。
+fn prefilter(sources: &[Environment], normal: Vector3<f32>, roughness: f32) -> [f32; 3] {
+    let up = if normal.z.abs() < 0.999 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_x()
+    };
+    let tangent = up.cross(normal).normalize();
+    let bitangent = normal.cross(tangent);
+    let a = roughness * roughness;
+    let texel_solid_angle = 4.0 * PI / (6.0 * (sources[0].size * sources[0].size) as f32);
+
+    let mut color = [0.0; 3];
+    let mut total = 0.0;
+    for i in 0..PREFILTER_SAMPLES {
+        // Hammersleyの点列
+        let u = i as f32 / PREFILTER_SAMPLES as f32;
+        let v = (i as u32).reverse_bits() as f32 / 4294967296.0;
+        let phi = 2.0 * PI * u;
+        let cos_theta = ((1.0 - v) / (1.0 + (a * a - 1.0) * v)).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let half = (tangent * phi.cos() + bitangent * phi.sin()) * sin_theta + normal * cos_theta;
+        let light = half * 2.0 * normal.dot(half) - normal;
+        let n_dot_l = normal.dot(light);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+        // サンプル1つが受け持つ立体角に近い大きさのテクセルの段を選ぶ
+        let n_dot_h = cos_theta;
+        let d = n_dot_h * n_dot_h * (a * a - 1.0) + 1.0;
+        let distribution = a * a / (PI * d * d);
+        let pdf = distribution / 4.0;
+        let sample_solid_angle = 1.0 / (PREFILTER_SAMPLES as f32 * pdf + 1e-4);
+        let level = (0.5 * (sample_solid_angle / texel_solid_angle).log2() + 1.0)
+            .clamp(0.0, (sources.len() - 1) as f32);
+        let sample = sources[level.round() as usize].sample(light);
+        for k in 0..3 {
+            color[k] += sample[k] * n_dot_l;
+        }
+        total += n_dot_l;
+    }
+    color.map(|c| c / total.max(1e-4))
+}
+
+/// 各段の6面をキューブマップのミップマップに書き込む
+///
+/// キューブマップの面には直接書き込めないので、面ごとのテクスチャからコピーする。
+fn cubemap_from_levels<F: glium::backend::Facade>(
+    facade: &F,
+    levels: &[Environment],
+) -> glium::texture::Cubemap {
+    let format = glium::texture::UncompressedFloatFormat::F16F16F16F16;
+    let mipmaps = if levels.len() > 1 {
+        glium::texture::MipmapsOption::EmptyMipmapsMax(levels.len() as u32 - 1)
+    } else {
+        glium::texture::MipmapsOption::NoMipmap
+    };
+    let cubemap =
+        glium::texture::Cubemap::empty_with_format(facade, format, mipmaps, levels[0].size)
+            .unwrap();
+    for (level, environment) in levels.iter().enumerate() {
+        let target = glium::BlitTarget {
+            left: 0,
+            bottom: 0,
+            width: environment.size as i32,
+            height: environment.size as i32,
+        };
+        let mipmap = cubemap.mipmap(level as u32).unwrap();
+        for (layer, pixels) in FACES.iter().zip(&environment.faces) {
+            let raw = glium::texture::RawImage2d {
+                data: std::borrow::Cow::Owned(pixels.iter().flatten().copied().collect()),
+                width: environment.size,
+                height: environment.size,
+                format: glium::texture::ClientFormat::F32F32F32,
+            };
+            let face = glium::texture::Texture2d::with_format(
+                facade,
+                raw,
+                format,
+                glium::texture::MipmapsOption::NoMipmap,
+            )
+            .unwrap();
+            let framebuffer =
+                glium::framebuffer::SimpleFrameBuffer::new(facade, mipmap.image(*layer)).unwrap();
+            face.as_surface().blit_whole_color_to(
+                &framebuffer,
+                &target,
+                glium::uniforms::MagnifySamplerFilter::Nearest,
+            );
+        }
+    }
+    cubemap
+}
+
+/// 物理ベースの塗り方で、景色から来る光に使うキューブマップ
+pub struct ImageBasedLight {
+    irradiance: glium::texture::Cubemap,
+    prefiltered: glium::texture::Cubemap,
+    levels: u32,
+}
+
+impl ImageBasedLight {
+    pub fn new<F: glium::backend::Facade>(
+        facade: &F,
+        environment: &Environment,
+    ) -> ImageBasedLight {
+        // 大きな景色をそのままぼかすと遅いので、先に縮める
+        let mut source = environment.clone();
+        while source.size > PREFILTER_SIZE {
+            source = source.downsample();
+        }
+        let levels = PREFILTER_LEVELS.min(32 - source.size.leading_zeros());
+        ImageBasedLight {
+            irradiance: source.irradiance(IRRADIANCE_SIZE).to_cubemap(facade),
+            prefiltered: cubemap_from_levels(facade, &source.prefiltered(levels)),
+            levels,
+        }
+    }
+
+    /// 法線の向きから来る拡散光
+    pub fn irradiance(&self) -> glium::uniforms::Sampler<'_, glium::texture::Cubemap> {
+        self.irradiance
+            .sampled()
+            .magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear)
+            .minify_filter(glium::uniforms::MinifySamplerFilter::Linear)
+    }
+
+    /// 反射の向きから来る光。ミップマップの段がラフネスになる
+    pub fn prefiltered(&self) -> glium::uniforms::Sampler<'_, glium::texture::Cubemap> {
+        self.prefiltered
+            .sampled()
+            .magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear)
+            .minify_filter(glium::uniforms::MinifySamplerFilter::LinearMipmapLinear)
+    }
+
+    /// ラフネス1に当たるミップマップの段
+    pub fn max_lod(&self) -> f32 {
+        (self.levels - 1) as f32
+    }
+}
+
+/// 何も描かれなかったところに、周りの景色を描く
+pub struct Skybox {
+    cubemap: glium::texture::Cubemap,
+    triangle: glium::VertexBuffer<ScreenVertex>,
+    program: glium::Program,
+}
+
+impl Skybox {
+    pub fn new<F: glium::backend::Facade>(facade: &F, environment: &Environment) -> Skybox {
+        let triangle = post::fullscreen_triangle(facade);
+        let program = glium::Program::from_source(
+            facade,
+            include_str!("./skybox.vert"),
+            include_str!("./skybox.frag"),
+            None,
+        )
+        .unwrap();
+        Skybox {
+            cubemap: environment.to_cubemap(facade),
+            triangle,
+            program,
+        }
+    }
+
+    /// 反射に使うためのキューブマップ
+    pub fn cubemap(&self) -> &glium::texture::Cubemap {
+        &self.cubemap
+    }
+
+    /// 不透明な物を描いた後に、深度が一番奥のままのところだけに描く
+    pub fn draw<S: Surface>(
+        &self,
+        target: &mut S,
+        view: [[f32; 4]; 4],
+        perspective: [[f32; 4]; 4],
+    ) -> DrawCounts {
+        // 景色は無限に遠いので、カメラの向きだけを使う
+        let rotation = Matrix4::from(Matrix3::from_cols(
+            Matrix4::from(view).x.truncate(),
+            Matrix4::from(view).y.truncate(),
+            Matrix4::from(view).z.truncate(),
+        ));
+        let inverse = (Matrix4::from(perspective) * rotation)
+            .invert()
+            .unwrap_or_else(Matrix4::identity);
+        let uniforms = uniform! {
+            u_inverse_view_projection: Into::<[[f32; 4]; 4]>::into(inverse),
+            u_environment: self.cubemap.sampled()
+                .magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear)
+                .minify_filter(glium::uniforms::MinifySamplerFilter::Linear),
+        };
+        let params = glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::DepthTest::IfLessOrEqual,
+                write: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        target
+            .draw(
+                &self.triangle,
+                glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+                &self.program,
+                &uniforms,
+                &params,
+            )
+            .unwrap();
+        DrawCounts {
+            draw_calls: 1,
+            triangles: 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// 面の上の位置から向きにして、同じ位置に戻るかのテスト
+    fn face_mapping_round_trips() {
+        for face in 0..6 {
+            for &(s, t) in &[(0.5, 0.5), (0.1, 0.8), (0.9, 0.25)] {
+                let (back, s2, t2) = direction_to_face(face_direction(face, s, t));
+                assert_eq!(back, face);
+                assert!((s - s2).abs() < 1e-5 && (t - t2).abs() < 1e-5);
+            }
+        }
+        // 面の真ん中はそれぞれの軸の向き
+        assert!((face_direction(3, 0.5, 0.5) - Vector3::new(0.0, -1.0, 0.0)).magnitude() < 1e-6);
+    }
+
+    #[test]
+    /// パノラマの真ん中が+z、上端が真上になるかのテスト
+    fn converts_equirectangular() {
+        let (width, height) = (8, 4);
+        let mut pixels = vec![[0.0; 3]; width * height];
+        for pixel in &mut pixels[..width] {
+            *pixel = [0.0, 1.0, 0.0];
+        }
+        pixels[height / 2 * width + width / 2] = [1.0, 0.0, 0.0];
+        let image = HdrImage {
+            width: width as u32,
+            height: height as u32,
+            pixels,
+        };
+        let environment = Environment::from_equirectangular(&image, 8);
+        assert_eq!(environment.faces.len(), 6);
+        assert_eq!(environment.sample(Vector3::new(0.0, 1.0, 0.0))[1], 1.0);
+        assert!(environment.sample(Vector3::new(0.0, -0.1, 1.0))[0] > 0.0);
+        assert_eq!(environment.sample(Vector3::new(0.0, -0.1, -1.0))[0], 0.0);
+    }
+
+    #[test]
+    /// どの向きも同じ明るさの景色なら、ぼかしても同じ明るさのままかのテスト
+    fn uniform_environment_stays_uniform() {
+        let environment = Environment::from_fn(16, |_| [0.5, 1.0, 2.0]);
+        let irradiance = environment.irradiance(4);
+        for pixels in &irradiance.faces {
+            for p in pixels {
+                assert!(
+                    (p[0] - 0.5).abs() < 0.01 && (p[2] - 2.0).abs() < 0.02,
+                    "{:?}",
+                    p
+                );
+            }
+        }
+        let levels = environment.prefiltered(4);
+        assert_eq!(
+            levels.iter().map(|l| l.size).collect::<Vec<_>>(),
+            vec![16, 8, 4, 2]
+        );
+        for p in levels[3].faces.iter().flatten() {
+            assert!((p[1] - 1.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    /// 上だけ明るい景色で、上を向いた面ほど明るくなるかのテスト
+    fn irradiance_follows_the_light() {
+        let environment = Environment::from_fn(16, |d| [d.y.max(0.0); 3]);
+        let irradiance = environment.irradiance(8);
+        let up = irradiance.sample(Vector3::new(0.0, 1.0, 0.0))[0];
+        let side = irradiance.sample(Vector3::new(1.0, 0.0, 0.0))[0];
+        let down = irradiance.sample(Vector3::new(0.0, -1.0, 0.0))[0];
+        assert!(up > side && side > down, "{} {} {}", up, side, down);
+        // 真上を向いた面はcosの重みの平均で2/3になる
+        assert!((up - 2.0 / 3.0).abs() < 0.05, "{}", up);
+    }
+
+    #[test]
+    fn sky_is_brighter_towards_the_sun() {
+        let sun = Vector3::new(-1.0, 0.4, 0.9);
+        let sky = Environment::sky(sun);
+        let towards = sky.sample(sun);
+        let away = sky.sample(-sun);
+        assert!(towards[0] > away[0] + 1.0);
+    }
+}
@@ -0,0 +1,51 @@
+#version 140
+
+in vec2 v_tex_coords;
+
+out vec4 color;
+
+uniform sampler2D u_input;
+// 1ピクセルの大きさ
+uniform vec2 u_texel;
+// ぼかす長さの上限 (ピクセル)
+uniform float u_span;
+
+const float reduce_min = 1.0 / 128.0;
+const float reduce_mul = 1.0 / 8.0;
+
+float luma(vec3 c) {
+	return dot(c, vec3(0.299, 0.587, 0.114));
+}
+
+void main() {
+	vec3 rgb_nw = texture(u_input, v_tex_coords + vec2(-1.0, 1.0) * u_texel).rgb;
+	vec3 rgb_ne = texture(u_input, v_tex_coords + vec2(1.0, 1.0) * u_texel).rgb;
+	vec3 rgb_sw = texture(u_input, v_tex_coords + vec2(-1.0, -1.0) * u_texel).rgb;
+	vec3 rgb_se = texture(u_input, v_tex_coords + vec2(1.0, -1.0) * u_texel).rgb;
+	vec3 rgb_m = texture(u_input, v_tex_coords).rgb;
+	float luma_nw = luma(rgb_nw);
+	float luma_ne = luma(rgb_ne);
+	float luma_sw = luma(rgb_sw);
+	float luma_se = luma(rgb_se);
+	float luma_m = luma(rgb_m);
+	float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+	float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+	// 縁に沿った向き
+	vec2 direction = vec2(
+		-((luma_nw + luma_ne) - (luma_sw + luma_se)),
+		(luma_nw + luma_sw) - (luma_ne + luma_se));
+	float reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * reduce_mul, reduce_min);
+	float scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+	direction = clamp(direction * scale, -u_span, u_span) * u_texel;
+
+	vec3 rgb_a = 0.5 * (
+		texture(u_input, v_tex_coords + direction * (1.0 / 3.0 - 0.5)).rgb +
+		texture(u_input, v_tex_coords + direction * (2.0 / 3.0 - 0.5)).rgb);
+	vec3 rgb_b = rgb_a * 0.5 + 0.25 * (
+		texture(u_input, v_tex_coords - direction * 0.5).rgb +
+		texture(u_input, v_tex_coords + direction * 0.5).rgb);
+	// 広く取りすぎて別の物の色が混ざったら狭い方を使う
+	float luma_b = luma(rgb_b);
+	color = vec4(luma_b < luma_min || luma_b > luma_max ? rgb_a : rgb_b, 1.0);
+}
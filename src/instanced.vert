@@ -0,0 +1,50 @@
+#version 150
+
+in vec3 position;
+in vec3 normal;
+// インスタンスごとの行列と色 (main.vertの`model`と`u_color`の代わり)
+in mat4 i_model;
+in vec3 i_color;
+
+out vec3 v_normal;
+out vec3 v_position;
+// モデル空間の法線 (法線を色にして表示するときに使う)
+out vec3 v_model_normal;
+// 光源から見た位置 (影を調べるのに使う)
+out vec4 v_light_space_position;
+// ビュー空間の位置 (反射の向きを求めるのに使う)
+out vec3 v_view_position;
+out vec3 v_color;
+#ifdef NORMAL_MAP
+in vec2 tex_coords;
+// xyzが接線で、wが従接線の向き
+in vec4 tangent;
+out vec2 v_tex_coords;
+out vec3 v_tangent;
+out vec3 v_bitangent;
+#endif
+
+uniform mat4 perspective;
+// カメラを表した行列
+uniform mat4 view;
+// 光源から見た正射影
+uniform mat4 u_light_space;
+
+void main() {
+	mat4 modelview = view * i_model;
+	v_normal = transpose(inverse(mat3(modelview))) * normal;
+#ifdef NORMAL_MAP
+	// 接線は面に沿った向きなので、位置と同じ行列で動かす
+	v_tex_coords = tex_coords;
+	v_tangent = mat3(modelview) * tangent.xyz;
+	// ビュー行列は鏡映を含むので、外積はモデル空間で取ってから動かす
+	v_bitangent = mat3(modelview) * (cross(normal, tangent.xyz) * tangent.w);
+#endif
+	vec4 view_position = modelview * vec4(position, 1.0);
+	v_view_position = view_position.xyz;
+	gl_Position = perspective * view_position;
+	v_position = gl_Position.xyz / gl_Position.w;
+	v_model_normal = normal;
+	v_light_space_position = u_light_space * i_model * vec4(position, 1.0);
+	v_color = i_color;
+}
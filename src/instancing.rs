@@ -0,0 +1,115 @@
+extern crate cgmath;
+use crate::bvh::Aabb;
+use cgmath::{Matrix4, Vector3};
+
+/// インスタンスごとの行列と色
+///
+/// instanced.vertでは`model`と`u_color`の代わりにこれを読む。
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Instance {
+    pub i_model: [[f32; 4]; 4],
+    pub i_color: [f32; 3],
+}
+
+implement_vertex!(Instance, i_model, i_color);
+
+impl Instance {
+    /// モデル空間の`bounds`がワールド空間でどこまで広がるか
+    pub fn bounds(&self, bounds: &Aabb) -> Aabb {
+        let model = Matrix4::from(self.i_model);
+        let mut world = Aabb::empty();
+        for i in 0..8 {
+            world.grow((model * bounds.corner(i).extend(1.0)).truncate());
+        }
+        world
+    }
+}
+
+/// 色相`hue` (0から1) の鮮やかな色
+fn hue_color(hue: f32) -> [f32; 3] {
+    let channel = |offset: f32| {
+        let x = ((hue + offset).fract() * 6.0 - 3.0).abs() - 1.0;
+        x.clamp(0.0, 1.0)
+    };
+    [channel(0.0), channel(2.0 / 3.0), channel(1.0 / 3.0)]
+}
+
+/// `model`の周りの地面に`spacing`間隔で並べた`count`個の複製
+///
+/// `model`の位置は空けて、近いマスから順に埋める。
+pub fn grid(model: [[f32; 4]; 4], count: usize, spacing: f32) -> Vec<Instance> {
+    let model = Matrix4::from(model);
+    let mut instances = Vec::with_capacity(count);
+    let mut ring = 1i32;
+    while instances.len() < count {
+        for z in -ring..=ring {
+            for x in -ring..=ring {
+                if instances.len() == count {
+                    break;
+                }
+                // 内側の輪はもう埋めてある
+                if x.abs() != ring && z.abs() != ring {
+                    continue;
+                }
+                let offset = Vector3::new(x as f32, 0.0, z as f32) * spacing;
+                // 黄金比ずつ色相をずらすと隣同士が似た色にならない
+                let hue = (instances.len() as f32 * 0.618_034).fract();
+                instances.push(Instance {
+                    i_model: (Matrix4::from_translation(offset) * model).into(),
+                    i_color: hue_color(hue),
+                });
+            }
+        }
+        ring += 1;
+    }
+    instances
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::SquareMatrix;
+
+    fn position(instance: &Instance) -> [f32; 3] {
+        let m = instance.i_model;
+        [m[3][0], m[3][1], m[3][2]]
+    }
+
+    /// 元の位置から何マス目の輪にあるか
+    fn ring(instance: &Instance, spacing: f32) -> f32 {
+        let [x, _, z] = position(instance);
+        x.abs().max(z.abs()) / spacing
+    }
+
+    #[test]
+    fn fills_rings_around_the_model() {
+        let model = Matrix4::<f32>::identity().into();
+        let instances = grid(model, 10, 2.0);
+        assert_eq!(instances.len(), 10);
+        // 最初の8個は元の位置を囲む1周目
+        for instance in &instances[..8] {
+            assert_eq!(position(instance)[1], 0.0);
+            assert_eq!(ring(instance, 2.0), 1.0);
+        }
+        assert_eq!(ring(&instances[8], 2.0), 2.0);
+        // 同じ位置に2つ置かない
+        for (i, a) in instances.iter().enumerate() {
+            for b in &instances[i + 1..] {
+                assert_ne!(position(a), position(b));
+            }
+        }
+        assert!(grid(model, 0, 2.0).is_empty());
+    }
+
+    #[test]
+    fn transforms_bounds() {
+        let bounds = Aabb {
+            min: Vector3::new(-1.0, 0.0, -1.0),
+            max: Vector3::new(1.0, 1.0, 1.0),
+        };
+        let instance = grid(Matrix4::<f32>::identity().into(), 1, 3.0)[0];
+        let world = instance.bounds(&bounds);
+        assert_eq!(world.min, Vector3::new(-4.0, 0.0, -4.0));
+        assert_eq!(world.max, Vector3::new(-2.0, 1.0, -2.0));
+    }
+}
@@ -0,0 +1,15 @@
+#version 140
+
+in vec3 position;
+in vec3 color;
+
+out vec3 v_color;
+
+uniform mat4 perspective;
+uniform mat4 view;
+uniform mat4 model;
+
+void main() {
+	gl_Position = perspective * view * model * vec4(position, 1.0);
+	v_color = color;
+}
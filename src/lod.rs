@@ -0,0 +1,587 @@
+extern crate cgmath;
+use crate::bvh::Bvh;
+use crate::mesh::{self, MeshVertex};
+use crate::teapot;
+use cgmath::{InnerSpace, Vector3};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+/// 縁の辺を動かしにくくするための重み
+const BOUNDARY_WEIGHT: f64 = 100.0;
+/// これより三角形が少なくなったら、それ以上は簡略化しない
+const MIN_TRIANGLES: usize = 16;
+
+/// 頂点と法線が同じ添字の三角形のメッシュ
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    /// 同じ位置の頂点を1つにまとめ、潰れた三角形を除いて作る (`mesh::weld`)
+    pub fn welded(positions: &[[f32; 3]], normals: &[[f32; 3]], indices: &[u32]) -> Mesh {
+        let welded = mesh::weld(positions, normals, indices, None);
+        Mesh {
+            positions: welded.positions,
+            normals: welded.normals,
+            indices: welded.indices,
+        }
+    }
+
+    /// teapotの配列から作る
+    pub fn teapot() -> Mesh {
+        let positions: Vec<[f32; 3]> = teapot::VERTICES.iter().map(|v| v.position.into()).collect();
+        let normals: Vec<[f32; 3]> = teapot::NORMALS.iter().map(|n| n.normal.into()).collect();
+        let indices: Vec<u32> = teapot::INDICES.iter().map(|&i| i as u32).collect();
+        Mesh::welded(&positions, &normals, &indices)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// 頂点バッファにする形
+    pub fn vertices(&self) -> Vec<MeshVertex> {
+        self.positions
+            .iter()
+            .zip(&self.normals)
+            .map(|(&p, &n)| MeshVertex::new(p, n))
+            .collect()
+    }
+}
+
+fn normalize_or_zero(v: Vector3<f32>) -> Vector3<f32> {
+    if v.magnitude2() > 0.0 {
+        v.normalize()
+    } else {
+        v
+    }
+}
+
+/// 平面からの距離の2乗の和を表す4x4の対称行列 (上三角の10個)
+#[derive(Copy, Clone, Debug, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    /// 単位法線`n`を持ち、`p`を通る平面
+    fn plane(n: Vector3<f64>, p: Vector3<f64>, weight: f64) -> Quadric {
+        let d = -n.dot(p);
+        let (a, b, c) = (n.x, n.y, n.z);
+        Quadric(
+            [
+                a * a,
+                a * b,
+                a * c,
+                a * d,
+                b * b,
+                b * c,
+                b * d,
+                c * c,
+                c * d,
+                d * d,
+            ]
+            .map(|x| x * weight),
+        )
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut sum = self.0;
+        for (s, o) in sum.iter_mut().zip(other.0.iter()) {
+            *s += o;
+        }
+        Quadric(sum)
+    }
+
+    fn error(&self, p: Vector3<f64>) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x, p.y, p.z);
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+
+    /// 誤差が一番小さくなる位置 (平面が平行で決まらなければNone)
+    fn minimum(&self) -> Option<Vector3<f64>> {
+        let q = &self.0;
+        let m = cgmath::Matrix3::new(q[0], q[1], q[2], q[1], q[4], q[5], q[2], q[5], q[7]);
+        use cgmath::SquareMatrix;
+        if m.determinant().abs() < 1e-12 {
+            return None;
+        }
+        m.invert()
+            .map(|inverse| inverse * -Vector3::new(q[3], q[6], q[8]))
+    }
+}
+
+/// 辺を1点に潰す候補
+struct Collapse {
+    cost: f64,
+    a: usize,
+    b: usize,
+    /// 候補を作ったときの`versions`
+    versions: (u32, u32),
+    position: Vector3<f64>,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Collapse) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    /// BinaryHeapから誤差の小さい順に取り出す
+    fn cmp(&self, other: &Collapse) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+struct Simplifier {
+    positions: Vec<Vector3<f64>>,
+    normals: Vec<Vector3<f32>>,
+    quadrics: Vec<Quadric>,
+    /// 頂点を含む三角形 (消えた三角形も残っていることがある)
+    vertex_faces: Vec<Vec<usize>>,
+    /// 頂点が動くたびに増やして、古い候補を捨てる
+    versions: Vec<u32>,
+    removed: Vec<bool>,
+    faces: Vec<[usize; 3]>,
+    face_removed: Vec<bool>,
+    triangles: usize,
+}
+
+impl Simplifier {
+    fn new(mesh: &Mesh) -> Simplifier {
+        let positions: Vec<Vector3<f64>> = mesh
+            .positions
+            .iter()
+            .map(|p| Vector3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            .collect();
+        let faces: Vec<[usize; 3]> = mesh
+            .indices
+            .chunks(3)
+            .map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+            .collect();
+        let mut quadrics = vec![Quadric::default(); positions.len()];
+        let mut vertex_faces = vec![Vec::new(); positions.len()];
+        let mut edge_faces: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (f, face) in faces.iter().enumerate() {
+            let [p0, p1, p2] = face.map(|i| positions[i]);
+            let n = (p1 - p0).cross(p2 - p0);
+            if n.magnitude2() > 0.0 {
+                let q = Quadric::plane(n.normalize(), p0, 1.0);
+                for &i in face {
+                    quadrics[i] = quadrics[i].add(&q);
+                }
+            }
+            for k in 0..3 {
+                vertex_faces[face[k]].push(f);
+                let (a, b) = (face[k], face[(k + 1) % 3]);
+                edge_faces.entry((a.min(b), a.max(b))).or_default().push(f);
+            }
+        }
+        // 縁の辺には、面に垂直で辺を通る平面を足して形を保つ
+        for (&(a, b), edge) in &edge_faces {
+            if edge.len() != 1 {
+                continue;
+            }
+            let [p0, p1, p2] = faces[edge[0]].map(|i| positions[i]);
+            let face_normal = (p1 - p0).cross(p2 - p0);
+            let direction = positions[b] - positions[a];
+            let n = direction.cross(face_normal);
+            if n.magnitude2() > 0.0 {
+                let weight = BOUNDARY_WEIGHT * direction.magnitude2();
+                let q = Quadric::plane(n.normalize(), positions[a], weight);
+                quadrics[a] = quadrics[a].add(&q);
+                quadrics[b] = quadrics[b].add(&q);
+            }
+        }
+        Simplifier {
+            normals: mesh.normals.iter().map(|&n| n.into()).collect(),
+            versions: vec![0; positions.len()],
+            removed: vec![false; positions.len()],
+            face_removed: vec![false; faces.len()],
+            triangles: faces.len(),
+            positions,
+            quadrics,
+            vertex_faces,
+            faces,
+        }
+    }
+
+    fn neighbors(&self, v: usize) -> Vec<usize> {
+        let mut neighbors: Vec<usize> = self.vertex_faces[v]
+            .iter()
+            .filter(|&&f| !self.face_removed[f])
+            .flat_map(|&f| self.faces[f].iter().copied())
+            .filter(|&u| u != v)
+            .collect();
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        neighbors
+    }
+
+    fn collapse(&self, a: usize, b: usize) -> Collapse {
+        let q = self.quadrics[a].add(&self.quadrics[b]);
+        let midpoint = (self.positions[a] + self.positions[b]) * 0.5;
+        let mut candidates = vec![self.positions[a], self.positions[b], midpoint];
+        candidates.extend(q.minimum());
+        let (position, cost) = candidates
+            .into_iter()
+            .map(|p| (p, q.error(p)))
+            .min_by(|x, y| x.1.partial_cmp(&y.1).unwrap_or(Ordering::Equal))
+            .unwrap();
+        Collapse {
+            cost: cost.max(0.0),
+            a,
+            b,
+            versions: (self.versions[a], self.versions[b]),
+            position,
+        }
+    }
+
+    /// 潰しても面が裏返らず、穴や重なった面ができないか
+    fn can_collapse(&self, collapse: &Collapse) -> bool {
+        let (a, b) = (collapse.a, collapse.b);
+        // 辺の両側の頂点以外に共通の隣がある (トンネルを潰す) ときは潰さない
+        let shared_faces = self.vertex_faces[a]
+            .iter()
+            .filter(|&&f| !self.face_removed[f] && self.faces[f].contains(&b))
+            .count();
+        let neighbors_b = self.neighbors(b);
+        let shared_neighbors = self
+            .neighbors(a)
+            .iter()
+            .filter(|u| neighbors_b.contains(u))
+            .count();
+        if shared_neighbors != shared_faces {
+            return false;
+        }
+        for &v in &[a, b] {
+            for &f in &self.vertex_faces[v] {
+                let face = self.faces[f];
+                if self.face_removed[f] || (face.contains(&a) && face.contains(&b)) {
+                    continue;
+                }
+                let before = face.map(|i| self.positions[i]);
+                let after = face.map(|i| {
+                    if i == v {
+                        collapse.position
+                    } else {
+                        self.positions[i]
+                    }
+                });
+                let normal = |p: [Vector3<f64>; 3]| (p[1] - p[0]).cross(p[2] - p[0]);
+                if normal(before).dot(normal(after)) <= 0.0 {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// `b`を`a`にまとめる
+    fn apply(&mut self, collapse: &Collapse) {
+        let (a, b) = (collapse.a, collapse.b);
+        self.positions[a] = collapse.position;
+        self.normals[a] = normalize_or_zero(self.normals[a] + self.normals[b]);
+        self.quadrics[a] = self.quadrics[a].add(&self.quadrics[b]);
+        self.removed[b] = true;
+        self.versions[a] += 1;
+        for f in std::mem::take(&mut self.vertex_faces[b]) {
+            if self.face_removed[f] {
+                continue;
+            }
+            if self.faces[f].contains(&a) {
+                self.face_removed[f] = true;
+                self.triangles -= 1;
+            } else {
+                for i in self.faces[f].iter_mut() {
+                    if *i == b {
+                        *i = a;
+                    }
+                }
+                self.vertex_faces[a].push(f);
+            }
+        }
+        let face_removed = &self.face_removed;
+        self.vertex_faces[a].retain(|&f| !face_removed[f]);
+    }
+
+    fn run(&mut self, target: usize) {
+        let mut heap = BinaryHeap::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            for k in 0..3 {
+                let (a, b) = (face[k], face[(k + 1) % 3]);
+                // 両隣の三角形から同じ辺が2回出てくるので片方だけ使う
+                if a < b || !self.has_edge(f, b, a) {
+                    heap.push(self.collapse(a, b));
+                }
+            }
+        }
+        while self.triangles > target {
+            let collapse = match heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            let (a, b) = (collapse.a, collapse.b);
+            if self.removed[a]
+                || self.removed[b]
+                || collapse.versions != (self.versions[a], self.versions[b])
+                || !self.can_collapse(&collapse)
+            {
+                continue;
+            }
+            self.apply(&collapse);
+            for u in self.neighbors(a) {
+                heap.push(self.collapse(a, u));
+            }
+        }
+    }
+
+    /// `f`以外に`a`から`b`への辺を持つ三角形があるか
+    fn has_edge(&self, f: usize, a: usize, b: usize) -> bool {
+        self.vertex_faces[a].iter().any(|&g| {
+            let face = self.faces[g];
+            g != f && (0..3).any(|k| face[k] == a && face[(k + 1) % 3] == b)
+        })
+    }
+
+    /// 残った頂点だけを詰めたメッシュ
+    fn mesh(&self) -> Mesh {
+        let mut remap = vec![u32::MAX; self.positions.len()];
+        let mut mesh = Mesh {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            indices: Vec::with_capacity(self.triangles * 3),
+        };
+        for (f, face) in self.faces.iter().enumerate() {
+            if self.face_removed[f] {
+                continue;
+            }
+            for &i in face {
+                if remap[i] == u32::MAX {
+                    remap[i] = mesh.positions.len() as u32;
+                    let p = self.positions[i];
+                    mesh.positions.push([p.x as f32, p.y as f32, p.z as f32]);
+                    mesh.normals.push(self.normals[i].into());
+                }
+                mesh.indices.push(remap[i]);
+            }
+        }
+        mesh
+    }
+}
+
+/// 辺を潰して、三角形を`target`個以下に減らす (quadric error metrics)
+///
+/// それ以上潰すと面が裏返るときは、`target`より多く残ることがある。
+pub fn simplify(mesh: &Mesh, target: usize) -> Mesh {
+    let mut simplifier = Simplifier::new(mesh);
+    simplifier.run(target);
+    simplifier.mesh()
+}
+
+/// `original`の頂点から`simplified`の面までの一番遠い距離
+pub fn distance_error(original: &Mesh, simplified: &Mesh) -> f32 {
+    let bvh = Bvh::new(
+        simplified.positions.iter().map(|&p| p.into()).collect(),
+        &simplified.indices,
+    );
+    original
+        .positions
+        .iter()
+        .filter_map(|&p| bvh.closest_point(p.into()))
+        .map(|closest| closest.distance)
+        .fold(0.0, f32::max)
+}
+
+/// 細かさの段階の1つ
+pub struct Lod {
+    pub mesh: Mesh,
+    /// 元のメッシュからずれた距離 (モデル空間)
+    pub error: f32,
+}
+
+/// 元のメッシュから始めて、三角形を半分ずつにした`levels`段階まで
+///
+/// 三角形がそれ以上減らなくなったら、そこで打ち切る。
+pub fn lod_chain(mesh: &Mesh, levels: usize) -> Vec<Lod> {
+    let mut chain = vec![Lod {
+        mesh: mesh.clone(),
+        error: 0.0,
+    }];
+    while chain.len() < levels {
+        let previous = &chain.last().unwrap().mesh;
+        let target = previous.triangle_count() / 2;
+        if target < MIN_TRIANGLES {
+            break;
+        }
+        let simplified = simplify(previous, target);
+        if simplified.triangle_count() >= previous.triangle_count() {
+            break;
+        }
+        let error = distance_error(mesh, &simplified);
+        chain.push(Lod {
+            mesh: simplified,
+            error,
+        });
+    }
+    chain
+}
+
+/// 距離`distance`にある長さ1が、高さ`viewport_height`の画面で何ピクセルになるか
+pub fn pixels_per_unit(perspective: &[[f32; 4]; 4], viewport_height: u32, distance: f32) -> f32 {
+    // perspective[1][1]は視野角の半分のtanの逆数
+    perspective[1][1] * viewport_height as f32 * 0.5 / distance.max(1e-4)
+}
+
+/// ずれが`max_pixels`ピクセル以内に収まる一番粗い段階
+///
+/// `errors`は細かい順に並んだ、ワールド空間でのずれ。
+pub fn select(errors: &[f32], pixels_per_unit: f32, max_pixels: f32) -> usize {
+    errors
+        .iter()
+        .rposition(|&error| error * pixels_per_unit <= max_pixels)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 縦横`n`マスの、少し波打った格子
+    fn grid(n: usize) -> Mesh {
+        let mut mesh = Mesh {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            indices: Vec::new(),
+        };
+        for z in 0..=n {
+            for x in 0..=n {
+                let (u, v) = (x as f32 / n as f32, z as f32 / n as f32);
+                mesh.positions.push([u, 0.01 * (u * 6.0).sin(), v]);
+                mesh.normals.push([0.0, 1.0, 0.0]);
+            }
+        }
+        let index = |x: usize, z: usize| (z * (n + 1) + x) as u32;
+        for z in 0..n {
+            for x in 0..n {
+                let (a, b) = (index(x, z), index(x + 1, z));
+                let (c, d) = (index(x, z + 1), index(x + 1, z + 1));
+                mesh.indices.extend_from_slice(&[a, c, b, b, c, d]);
+            }
+        }
+        mesh
+    }
+
+    #[test]
+    fn welds_teapot_seams() {
+        let mesh = Mesh::teapot();
+        assert!(mesh.positions.len() < teapot::VERTICES.len());
+        assert!(mesh.triangle_count() <= teapot::INDICES.len() / 3);
+        for n in &mesh.normals {
+            let length = Vector3::from(*n).magnitude();
+            assert!(length == 0.0 || (length - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn simplifies_to_target() {
+        let mesh = grid(16);
+        let simplified = simplify(&mesh, 64);
+        assert!(simplified.triangle_count() <= 64);
+        assert!(simplified.triangle_count() > 32);
+        // 平らに近いので、ずれは波の高さ程度に収まる
+        assert!(distance_error(&mesh, &simplified) < 0.02);
+        // 四隅は縁の重みで残る
+        for corner in &[[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]] {
+            assert!(simplified
+                .positions
+                .iter()
+                .any(|p| (p[0] - corner[0]).abs() < 1e-3 && (p[2] - corner[1]).abs() < 1e-3));
+        }
+    }
+
+    #[test]
+    fn builds_teapot_chain() {
+        let mesh = Mesh::teapot();
+        let chain = lod_chain(&mesh, 4);
+        assert_eq!(chain.len(), 4);
+        let size = mesh
+            .positions
+            .iter()
+            .map(|p| Vector3::from(*p).magnitude())
+            .fold(0.0, f32::max);
+        for pair in chain.windows(2) {
+            assert!(pair[1].mesh.triangle_count() <= pair[0].mesh.triangle_count() / 2);
+            assert!(pair[1].error >= pair[0].error);
+        }
+        // 半分にしてもほとんどずれず、1/8にしても大きさの1割もずれない
+        assert!(chain[1].error < size * 0.01);
+        assert!(chain[3].error < size * 0.1);
+    }
+
+    #[test]
+    fn selects_by_projected_size() {
+        let perspective = cgmath::perspective(cgmath::Deg(90.0), 1.0, 0.1, 100.0).into();
+        // tan(45°) = 1なので、距離1で長さ1が画面の高さの半分になる
+        assert!((pixels_per_unit(&perspective, 1000, 1.0) - 500.0).abs() < 1e-3);
+        let errors = [0.0, 0.01, 0.1];
+        assert_eq!(
+            select(&errors, pixels_per_unit(&perspective, 1000, 1.0), 1.0),
+            0
+        );
+        assert_eq!(
+            select(&errors, pixels_per_unit(&perspective, 1000, 10.0), 1.0),
+            1
+        );
+        assert_eq!(
+            select(&errors, pixels_per_unit(&perspective, 1000, 100.0), 1.0),
+            2
+        );
+    }
+
+    #[test]
+    fn measures_distance_to_triangles() {
+        let triangle = Mesh {
+            positions: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+            normals: vec![[0.0, 1.0, 0.0]; 3],
+            indices: vec![0, 2, 1],
+        };
+        let points = |positions: Vec<[f32; 3]>| Mesh {
+            normals: vec![[0.0, 1.0, 0.0]; positions.len()],
+            positions,
+            indices: Vec::new(),
+        };
+        // 面の上と、辺の延長の先
+        assert_eq!(
+            distance_error(&points(vec![[0.2, 2.0, 0.2]]), &triangle),
+            2.0
+        );
+        assert_eq!(
+            distance_error(&points(vec![[0.2, 2.0, 0.2], [-3.0, 0.0, 0.0]]), &triangle),
+            3.0
+        );
+    }
+}
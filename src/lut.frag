@@ -0,0 +1,28 @@
+#version 140
+
+in vec2 v_tex_coords;
+
+out vec4 color;
+
+uniform sampler2D u_input;
+// 青ごとのマスを横に並べた3D LUT
+uniform sampler2D u_lut;
+// 1辺のマスの数
+uniform float u_lut_size;
+
+// 青が`blue`番目のマスの中の、赤と緑の位置
+vec3 lookup(vec2 red_green, float blue) {
+	float u = (blue * u_lut_size + red_green.x * (u_lut_size - 1.0) + 0.5) / (u_lut_size * u_lut_size);
+	float v = (red_green.y * (u_lut_size - 1.0) + 0.5) / u_lut_size;
+	return texture(u_lut, vec2(u, v)).rgb;
+}
+
+void main() {
+	vec3 c = clamp(texture(u_input, v_tex_coords).rgb, 0.0, 1.0);
+	// 青はマスをまたぐので、隣の2マスを自分で混ぜる
+	float blue = c.b * (u_lut_size - 1.0);
+	float blue0 = floor(blue);
+	float blue1 = min(blue0 + 1.0, u_lut_size - 1.0);
+	vec3 graded = mix(lookup(c.rg, blue0), lookup(c.rg, blue1), blue - blue0);
+	color = vec4(graded, 1.0);
+}
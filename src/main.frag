@@ -1,22 +1,62 @@
 #version 140
+// `lit()`と`surface_normal()`、それが使う入力はsurface.glslにある
 
-in vec3 v_normal;
 in vec3 v_position;
+in vec3 v_model_normal;
+in vec3 v_view_position;
+// 物の色
+in vec3 v_color;
 
 out vec4 color;
 
+// ビュー空間の光が来る向き
 uniform vec3 u_light;
+// 周りの景色と、それが映り込む割合
+uniform samplerCube u_environment;
+uniform float u_reflectivity;
+// ビュー空間からワールド空間の向きに戻すのに使う
+uniform mat4 view;
+// マウスで選択されているか
+uniform bool u_selected;
+// 0: 光を当てる 1: 法線を色にする 2: 深度を表示する 3: ワイヤーフレーム
+uniform int u_mode;
+// 深度を距離に戻すための、描画する範囲の手前と奥
+uniform float u_near;
+uniform float u_far;
+// 深度の表示で真っ黒になる距離
+uniform float u_depth_distance;
 
-const vec3 ambient_color = vec3(0.2, 0.0, 0.0);
-const vec3 diffuse_color = vec3(0.6, 0.0, 0.0);
+const float ambient = 0.3;
 const vec3 specular_color = vec3(1.0, 1.0, 1.0);
 
 void main() {
-	float diffuse = max(dot(normalize(v_normal), normalize(u_light)), 0.0);
+	if (u_mode == 1) {
+		color = vec4(normalize(v_model_normal) * 0.5 + 0.5, 1.0);
+		return;
+	}
+	if (u_mode == 2) {
+		float z = gl_FragCoord.z * 2.0 - 1.0;
+		float distance = 2.0 * u_near * u_far / (u_far + u_near - z * (u_far - u_near));
+		color = vec4(vec3(1.0 - clamp(distance / u_depth_distance, 0.0, 1.0)), 1.0);
+		return;
+	}
+	if (u_mode == 3) {
+		color = vec4(1.0, 1.0, 1.0, 1.0);
+		return;
+	}
+
+	vec3 normal = surface_normal();
+	float diffuse = max(dot(normal, normalize(u_light)), 0.0);
 
 	vec3 camera_dir = normalize(-v_position);
 	vec3 half_directon = normalize(normalize(u_light) + camera_dir);
-	float specular = pow(max(dot(half_directon, normalize(v_normal)), 0.0), 16.0);
+	float specular = pow(max(dot(half_directon, normal), 0.0), 16.0);
+
+	float shadow = lit();
+	// 景色はワールド空間の向きで引く
+	vec3 reflected = reflect(normalize(v_view_position), normal);
+	vec3 reflection = texture(u_environment, transpose(mat3(view)) * reflected).rgb;
 
-	color = vec4(ambient_color * diffuse * diffuse_color + specular * specular_color, 1.0);
+	vec3 selected_color = u_selected ? vec3(0.3, 0.3, 0.0) : vec3(0.0);
+	color = vec4(ambient * v_color + shadow * (diffuse * v_color + specular * specular_color) + u_reflectivity * reflection + selected_color, 1.0);
 }
@@ -1,5 +1,6 @@
 #[macro_use]
 extern crate glium;
+extern crate cgmath;
 extern crate image;
 
 #[path = "./teapot.rs"]
@@ -8,102 +9,709 @@ mod teapot;
 #[path = "./camera.rs"]
 mod camera;
 
+#[path = "./ray.rs"]
+mod ray;
+
+#[path = "./bvh.rs"]
+mod bvh;
+
+#[path = "./collision.rs"]
+mod collision;
+
+#[path = "./camera_path.rs"]
+mod camera_path;
+
+#[path = "./options.rs"]
+mod options;
+
+#[path = "./scene.rs"]
+mod scene;
+
+#[path = "./capture.rs"]
+mod capture;
+
+#[path = "./timing.rs"]
+mod timing;
+
+#[path = "./stats.rs"]
+mod stats;
+
+#[path = "./overlay.rs"]
+mod overlay;
+
+#[path = "./debug_draw.rs"]
+mod debug_draw;
+
+#[path = "./shadow.rs"]
+mod shadow;
+
+#[path = "./environment.rs"]
+mod environment;
+
+#[path = "./material.rs"]
+mod material;
+
+#[path = "./post.rs"]
+mod post;
+
+#[path = "./effects.rs"]
+mod effects;
+
+#[path = "./instancing.rs"]
+mod instancing;
+
+#[path = "./lod.rs"]
+mod lod;
+
+#[path = "./bezier.rs"]
+mod bezier;
+
+#[path = "./primitives.rs"]
+mod primitives;
+
+#[path = "./mesh.rs"]
+mod mesh;
+
+#[path = "./tangents.rs"]
+mod tangents;
+
+#[path = "./mesh_file.rs"]
+mod mesh_file;
+
+/// ティーポット (`mesh`が無ければteapot.rsのもの) を`path`に書き出す
+fn export(
+    path: &std::path::Path,
+    options: &options::Options,
+    mesh: Option<&(Vec<mesh::MeshVertex>, Vec<u32>)>,
+) {
+    let format = match mesh_file::Format::from_path(path) {
+        Some(mesh_file::Format::Ply) if options.export_ascii => mesh_file::Format::PlyAscii,
+        Some(format) => format,
+        None => unreachable!("--export checks the extension"),
+    };
+    let (mut vertices, mut indices) = match mesh {
+        Some((vertices, indices)) => (vertices.clone(), indices.clone()),
+        None => (
+            mesh::MeshVertex::from_arrays(&teapot::VERTICES, &teapot::NORMALS),
+            teapot::INDICES.iter().map(|&i| i as u32).collect(),
+        ),
+    };
+    if options.export_lod > 0 {
+        let positions: Vec<[f32; 3]> = vertices.iter().map(|v| v.position).collect();
+        let normals: Vec<[f32; 3]> = vertices.iter().map(|v| v.normal).collect();
+        let chain = lod::lod_chain(
+            &lod::Mesh::welded(&positions, &normals, &indices),
+            options.export_lod + 1,
+        );
+        if chain.len() <= options.export_lod {
+            println!("lod: only {} levels, exporting the last one", chain.len());
+        }
+        let level = &chain.last().unwrap().mesh;
+        vertices = level.vertices();
+        indices = level.indices.clone();
+    }
+    if let Err(e) = mesh_file::save(path, format, &vertices, &indices) {
+        eprintln!("failed to save {}: {}", path.display(), e);
+        std::process::exit(1);
+    }
+    println!(
+        "exported {} triangles to {}",
+        indices.len() / 3,
+        path.display()
+    );
+}
+
 fn main() {
+    use cgmath::SquareMatrix;
     #[allow(unused_imports)]
     use glium::{glutin, Surface};
 
+    let options = options::Options::from_args().unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(2);
+    });
+
+    // ファイルが指定されていればそれを、形が指定されていればそれを、
+    // 分割数が指定されていればベジエパッチから作ったものを置く
+    // (生成するものはteapot.rsのティーポットと同じくらいの大きさにする)
+    let mut teapot_mesh = match (&options.mesh, options.shape, options.teapot_subdivisions) {
+        (Some(path), _, _) => {
+            let mesh = mesh_file::load(path).unwrap_or_else(|e| {
+                eprintln!("failed to load {}: {}", path.display(), e);
+                std::process::exit(1);
+            });
+            Some((mesh.vertices(), mesh.indices))
+        }
+        (None, Some(shape), _) => {
+            let primitive = shape.primitive(40.0);
+            Some((primitive.vertices(), primitive.indices))
+        }
+        (None, None, Some(n)) => {
+            let tessellation = bezier::teapot(n);
+            Some((tessellation.vertices(), tessellation.indices))
+        }
+        (None, None, None) => None,
+    };
+    // 法線マップを貼れるように、UVから接線を求めておく
+    if let Some((vertices, indices)) = &mut teapot_mesh {
+        tangents::generate(vertices, indices);
+    }
+    // 書き出すだけならウィンドウは開かない
+    if let Some(path) = &options.export {
+        export(path, &options, teapot_mesh.as_ref());
+        return;
+    }
+
     let event_loop = glutin::event_loop::EventLoop::new();
     let mut wb = glutin::window::WindowBuilder::new();
     wb.window.title = "example".to_string();
     wb.window.min_inner_size = Some(glutin::dpi::Size::Physical(
         glutin::dpi::PhysicalSize::<u32>::new(1500, 1500),
     ));
-    let cb = glutin::ContextBuilder::new().with_depth_buffer(24);
-    let display = glium::Display::new(wb, cb, &event_loop).unwrap();
-
-    let positions = glium::VertexBuffer::new(&display, &teapot::VERTICES).unwrap();
-    let normals = glium::VertexBuffer::new(&display, &teapot::NORMALS).unwrap();
-    let indices = glium::IndexBuffer::new(
-        &display,
-        glium::index::PrimitiveType::TrianglesList,
-        &teapot::INDICES,
-    )
-    .unwrap();
-
-    // main.vertを読み込む
-    let vertex_shader_src = include_str!("./main.vert");
-    // main.fragを読み込む
-    let fragment_shader_src = include_str!("./main.frag");
-
-    let program =
-        glium::Program::from_source(&display, &vertex_shader_src, fragment_shader_src, None)
-            .unwrap();
+    // 指定したサンプル数で作れなければ半分にしてやり直す
+    let mut samples = options.msaa;
+    let display = loop {
+        let mut cb = glutin::ContextBuilder::new()
+            .with_depth_buffer(24)
+            .with_vsync(options.vsync);
+        if samples > 1 {
+            cb = cb.with_multisampling(samples as u16);
+        }
+        match glium::Display::new(wb.clone(), cb, &event_loop) {
+            Err(e) if samples > 1 => {
+                println!(
+                    "msaa: cannot create a window with {} samples ({}), trying {}",
+                    samples,
+                    e,
+                    samples / 2
+                );
+                samples /= 2;
+            }
+            result => break result.unwrap(),
+        }
+    };
+
+    let (teapot_positions, teapot_indices): (Vec<cgmath::Vector3<f32>>, Vec<u32>) =
+        match &teapot_mesh {
+            Some((vertices, indices)) => (
+                vertices.iter().map(|v| v.position.into()).collect(),
+                indices.clone(),
+            ),
+            None => (
+                teapot::VERTICES.iter().map(|v| v.position.into()).collect(),
+                teapot::INDICES.iter().map(|&i| i as u32).collect(),
+            ),
+        };
+
+    // マウスで選択するための、モデル空間のBVH
+    let teapot_bvh = bvh::Bvh::new(teapot_positions.clone(), &teapot_indices);
+
+    // 頂点位置を変更するための行列
+    let model = [
+        [0.01, 0.0, 0.0, 0.0],
+        [0.0, 0.01, 0.0, 0.0],
+        [0.0, 0.0, 0.01, 0.0],
+        [0.0, 0.0, 2.0, 1.0f32],
+    ];
+    let model_matrix = cgmath::Matrix4::from(model);
+
+    // 指定が無ければ、光の向きに太陽のある空を作る
+    let environment = match &options.environment {
+        Some(path) => environment::Environment::load(path).unwrap_or_else(|e| {
+            eprintln!("failed to load {}: {}", path.display(), e);
+            std::process::exit(1);
+        }),
+        None => environment::Environment::sky(scene::DEFAULT_LIGHT.into()),
+    };
+    let mut scene = scene::Scene::new(&display, model, &options.shadow, &environment);
+    if let Some((vertices, indices)) = &teapot_mesh {
+        println!("teapot: {} triangles", indices.len() / 3);
+        scene.set_teapot(vertices, indices);
+    }
+    if let Some(path) = &options.normal_map {
+        // teapot.rsのティーポットにはUVも接線も無い
+        if teapot_mesh.is_none() {
+            eprintln!("--normal-map needs --mesh, --shape or --teapot-subdivisions; ignoring it");
+        } else {
+            let image = image::open(path).unwrap_or_else(|e| {
+                eprintln!("failed to load {}: {}", path.display(), e);
+                std::process::exit(1);
+            });
+            scene.set_normal_map(&image.to_rgba8());
+        }
+    }
+    // 負荷を確かめるために、ティーポットの周りに複製を並べる
+    let bounds = scene.bounds();
+    let spacing = (bounds.max.x - bounds.min.x).max(bounds.max.z - bounds.min.z) * 1.5;
+    let instances = instancing::grid(model, options.instances, spacing);
+    scene.set_instances(&instances);
+    let rings = ((options.instances as f32 + 1.0).sqrt() / 2.0).ceil();
+    scene.set_ground(3.0f32.max((rings + 1.0) * spacing));
+    // 選択したときに囲む箱
+    let teapot_bounds = scene.bounds();
+    let ground = teapot_bounds.min.y;
 
     let mut camera = camera::CameraState::new();
     camera.set_position((2.0, -1.0, 0.0));
     camera.set_direction((-2.0, 1.0, 1.0));
+    camera.set_up((0.0, 1.0, 0.0));
+
+    // カメラがぶつかるように、ワールド空間の三角形を渡す
+    let world_bvh = bvh::Bvh::new(
+        teapot_positions
+            .iter()
+            .map(|&p| (model_matrix * p.extend(1.0)).truncate())
+            .collect(),
+        &teapot_indices,
+    );
+    camera.set_collision(Some(collision::Collision::new(world_bvh, 0.05)));
+    // ティーポットの底の高さを地面にする
+    camera.set_ground(Some(ground));
+
+    // カメラパスが指定されていれば、その通りにカメラを動かす
+    let playback = if let Some(path) = &options.camera_path {
+        Some(camera_path::CameraPath::load(path).unwrap_or_else(|e| {
+            eprintln!("failed to load {}: {}", path.display(), e);
+            std::process::exit(1);
+        }))
+    } else if options.turntable {
+        Some(camera_path::CameraPath::turntable(
+            cgmath::Vector3::new(0.0, 0.15, 2.0),
+            1.0,
+            0.4,
+            8.0,
+            std::f32::consts::PI / 3.0,
+        ))
+    } else {
+        None
+    }
+    .map(|path| {
+        println!(
+            "playing camera path: {} keyframes, {:.1} s",
+            path.keyframes().len(),
+            path.duration()
+        );
+        camera_path::Playback::new(path, options.record_fps)
+    });
+    if let (Some(playback), Some(path)) = (&playback, &options.save_camera_path) {
+        if let Err(e) = playback.path().save(path) {
+            eprintln!("failed to save {}: {}", path.display(), e);
+        }
+    }
+    let playback_start = std::time::Instant::now();
+
+    let mut recorder = options.record.as_ref().map(|directory| {
+        capture::Recorder::new(directory, options.record_fps, options.encoder.clone())
+            .unwrap_or_else(|e| {
+                eprintln!("failed to create {}: {}", directory.display(), e);
+                std::process::exit(1);
+            })
+    });
+    let record_frames = options
+        .record_frames
+        .or_else(|| playback.as_ref().map(|p| p.frame_count()))
+        .unwrap_or(300);
+    let offscreen = options
+        .offscreen
+        .map(|size| capture::Offscreen::new(&display, size));
+
+    let mut cursor_position = (0.0f64, 0.0f64);
+    let mut modifiers = glutin::event::ModifiersState::empty();
+    // 格子と座標軸を描くか
+    let mut show_grid = true;
+    // クリックで当たった位置 (ワールド空間)
+    let mut selected_point: Option<cgmath::Vector3<f32>> = None;
+    // 止めた時点のカメラの視錐台 (view, perspective)
+    let mut frozen_frustum = None;
+    // `material::TEAPOT_MATERIALS`の何番目をティーポットに付けているか
+    let mut teapot_material = 0;
+    // 次に描いたフレームを保存する (trueならスーパーサンプリングする)
+    let mut screenshot: Option<bool> = None;
+
+    let mut fixed_step = timing::FixedStep::new(options.tick_rate);
+    let target_fps = if options.vsync {
+        None
+    } else {
+        Some(options.fps)
+    };
+    let mut pacer = timing::FramePacer::new(target_fps, std::time::Instant::now());
+    let mut last_update = std::time::Instant::now();
+
+    // 直近の1000フレームの統計を画面に出す (録画中は画像に入らないように隠す)
+    let mut frame_stats = match &options.stats_csv {
+        Some(path) => stats::FrameStats::with_csv(1000, path).unwrap_or_else(|e| {
+            eprintln!("failed to create {}: {}", path.display(), e);
+            std::process::exit(1);
+        }),
+        None => stats::FrameStats::new(1000),
+    };
+    let mut overlay = overlay::Overlay::new(&display);
+    let effect_configs = match &options.post {
+        Some(path) => effects::load_config(path).unwrap_or_else(|e| {
+            eprintln!("failed to load {}: {}", path.display(), e);
+            std::process::exit(1);
+        }),
+        None => effects::parse_config(effects::DEFAULT_CONFIG).unwrap(),
+    };
+    let create_post_process = || {
+        let mut post_process = post::PostProcess::new(&display, options.tone_map);
+        post_process.set_samples(options.msaa);
+        post_process.effects =
+            effects::create_all(&display, &effect_configs, &mut post_process.settings)
+                .unwrap_or_else(|message| {
+                    eprintln!("{}", message);
+                    std::process::exit(1);
+                });
+        post_process
+    };
+    let mut post_process = create_post_process();
+    // 録画とスーパーサンプリングは画面と大きさが違うので、毎フレーム作り直さないようにバッファを別に持つ
+    let mut offscreen_post_process = create_post_process();
+    println!("post effects: {}", post_process.names().join(", "));
+    let mut show_stats = recorder.is_none();
+    let mut last_frame = std::time::Instant::now();
 
     event_loop.run(move |event, _, control_flow| {
-        camera.update();
+        // 録画中は入力を無視し、1フレームごとに決まった量だけシミュレーションを進める
+        let recording = recorder.is_some();
         match event {
             glutin::event::Event::WindowEvent { event, .. } => match event {
                 glutin::event::WindowEvent::CloseRequested => {
                     *control_flow = glutin::event_loop::ControlFlow::Exit;
-                    return;
+                }
+                _ if recording => (),
+                glutin::event::WindowEvent::ModifiersChanged(state) => {
+                    modifiers = state;
+                }
+                glutin::event::WindowEvent::KeyboardInput {
+                    input:
+                        glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(glutin::event::VirtualKeyCode::F12),
+                            ..
+                        },
+                    ..
+                } => {
+                    screenshot = Some(modifiers.shift());
+                }
+                glutin::event::WindowEvent::KeyboardInput {
+                    input:
+                        glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(glutin::event::VirtualKeyCode::F3),
+                            ..
+                        },
+                    ..
+                } => {
+                    show_stats = !show_stats;
+                }
+                glutin::event::WindowEvent::KeyboardInput {
+                    input:
+                        glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode:
+                                Some(
+                                    key @ (glutin::event::VirtualKeyCode::T
+                                    | glutin::event::VirtualKeyCode::LBracket
+                                    | glutin::event::VirtualKeyCode::RBracket),
+                                ),
+                            ..
+                        },
+                    ..
+                } => {
+                    let settings = &mut post_process.settings;
+                    match key {
+                        glutin::event::VirtualKeyCode::T => {
+                            settings.tone_mapping = settings.tone_mapping.next()
+                        }
+                        glutin::event::VirtualKeyCode::LBracket => settings.exposure -= 0.5,
+                        _ => settings.exposure += 0.5,
+                    }
+                    println!(
+                        "tone mapping: {:?}, exposure {:+.1}",
+                        settings.tone_mapping, settings.exposure
+                    );
+                }
+                glutin::event::WindowEvent::KeyboardInput {
+                    input:
+                        glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode:
+                                Some(
+                                    key @ (glutin::event::VirtualKeyCode::F5
+                                    | glutin::event::VirtualKeyCode::F6
+                                    | glutin::event::VirtualKeyCode::F7),
+                                ),
+                            ..
+                        },
+                    ..
+                } => {
+                    match key {
+                        glutin::event::VirtualKeyCode::F5 => {
+                            scene.debug.wireframe = !scene.debug.wireframe
+                        }
+                        glutin::event::VirtualKeyCode::F6 => {
+                            scene.debug.normals = !scene.debug.normals
+                        }
+                        _ => scene.debug.shading = scene.debug.shading.next(),
+                    }
+                    println!("debug view: {:?}", scene.debug);
+                }
+                glutin::event::WindowEvent::KeyboardInput {
+                    input:
+                        glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(glutin::event::VirtualKeyCode::F8),
+                            ..
+                        },
+                    ..
+                } => {
+                    frozen_frustum = match frozen_frustum {
+                        Some(_) => None,
+                        None => Some((camera.get_view(), camera.get_perspective())),
+                    };
+                }
+                glutin::event::WindowEvent::KeyboardInput {
+                    input:
+                        glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(glutin::event::VirtualKeyCode::G),
+                            ..
+                        },
+                    ..
+                } => {
+                    show_grid = !show_grid;
+                }
+                glutin::event::WindowEvent::KeyboardInput {
+                    input:
+                        glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(glutin::event::VirtualKeyCode::M),
+                            ..
+                        },
+                    ..
+                } => {
+                    teapot_material = (teapot_material + 1) % material::TEAPOT_MATERIALS.len();
+                    scene.teapot_material = material::TEAPOT_MATERIALS[teapot_material];
+                    println!("material: {:?}", scene.teapot_material);
+                }
+                glutin::event::WindowEvent::KeyboardInput {
+                    input:
+                        glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(glutin::event::VirtualKeyCode::L),
+                            ..
+                        },
+                    ..
+                } => {
+                    // 自動 → 0 → 1 → … → 自動
+                    scene.lod = match scene.lod {
+                        None => Some(0),
+                        Some(level) if level + 1 < scene.lod_levels() => Some(level + 1),
+                        Some(_) => None,
+                    };
+                    match scene.lod {
+                        Some(level) => println!("lod: {}", level),
+                        None => println!("lod: auto"),
+                    }
+                }
+                glutin::event::WindowEvent::KeyboardInput {
+                    input:
+                        glutin::event::KeyboardInput {
+                            state: glutin::event::ElementState::Pressed,
+                            virtual_keycode: Some(glutin::event::VirtualKeyCode::C),
+                            ..
+                        },
+                    ..
+                } => {
+                    let mode = camera.cycle_move_mode();
+                    println!("camera mode: {:?} at {:?}", mode, camera.get_position());
                 }
                 glutin::event::WindowEvent::KeyboardInput { .. } => {
                     camera.process_input(&event);
                 }
-                glutin::event::WindowEvent::CursorMoved { .. } => {
+                glutin::event::WindowEvent::CursorMoved { position, .. } => {
+                    cursor_position = (position.x, position.y);
                     camera.process_cursor(&event);
                 }
-                _ => return,
-            },
-            glutin::event::Event::NewEvents(cause) => match cause {
-                glutin::event::StartCause::ResumeTimeReached { .. } => (),
-                glutin::event::StartCause::Init => (),
-                _ => return,
+                glutin::event::WindowEvent::MouseInput {
+                    state: glutin::event::ElementState::Pressed,
+                    button: glutin::event::MouseButton::Left,
+                    ..
+                } => {
+                    // クリックした位置からレイを飛ばして、モデル空間で交差を調べる
+                    let size = display.gl_window().window().inner_size();
+                    let hit = ray::Ray::from_screen(
+                        cursor_position,
+                        (size.width, size.height),
+                        camera.get_view(),
+                        camera.get_perspective(),
+                    )
+                    .and_then(|ray| {
+                        let inverse = model_matrix.invert()?;
+                        teapot_bvh.intersect(&ray.transform(&inverse))
+                    });
+                    scene.selected = hit.is_some();
+                    match hit {
+                        Some(hit) => {
+                            let point = (model_matrix * hit.point.extend(1.0)).truncate();
+                            selected_point = Some(point);
+                            println!(
+                                "selected teapot: triangle {} at ({:.3}, {:.3}, {:.3})",
+                                hit.triangle, point.x, point.y, point.z
+                            );
+                        }
+                        None => {
+                            selected_point = None;
+                            println!("nothing selected");
+                        }
+                    }
+                }
+                _ => (),
             },
-            _ => return,
-        }
+            glutin::event::Event::MainEventsCleared => {
+                // 描く時間になったら、経過した時間の分だけ決まった間隔で動かしてから描画を頼む
+                let now = std::time::Instant::now();
+                if recording {
+                    display.gl_window().window().request_redraw();
+                } else if pacer.should_draw(now) {
+                    let elapsed = (now - last_update).as_secs_f32();
+                    last_update = now;
+                    for _ in 0..fixed_step.advance(elapsed) {
+                        camera.update(fixed_step.step());
+                    }
+                    camera.set_interpolation(fixed_step.alpha());
+                    display.gl_window().window().request_redraw();
+                }
+                *control_flow = match pacer.next_frame() {
+                    Some(next_frame) if !recording => {
+                        glutin::event_loop::ControlFlow::WaitUntil(next_frame)
+                    }
+                    _ => glutin::event_loop::ControlFlow::Poll,
+                };
+            }
+            glutin::event::Event::RedrawRequested(_) => {
+                // 録画中はフレーム番号、そうでなければ経過時間でカメラパスを進める
+                let frame = match &recorder {
+                    Some(recorder) => {
+                        camera.update(1.0 / options.record_fps);
+                        recorder.frame()
+                    }
+                    None => (playback_start.elapsed().as_secs_f32() * options.record_fps) as usize,
+                };
+                if let Some(playback) = &playback {
+                    if let Some(pose) = playback.pose(frame) {
+                        pose.apply(&mut camera);
+                    }
+                }
 
-        let next_frame_time =
-            std::time::Instant::now() + std::time::Duration::from_nanos(16_666_667);
-        *control_flow = glutin::event_loop::ControlFlow::WaitUntil(next_frame_time);
-
-        let mut target = display.draw();
-        target.clear_color_and_depth((0.0, 0., 1.0, 1.0), 1.0);
-
-        let uniforms = uniform! {
-            model: [
-                [0.01, 0.0, 0.0, 0.0],
-                [0.0, 0.01, 0.0, 0.0],
-                [0.0, 0.0, 0.01, 0.0],
-                [0.0, 0.0, 2.0, 1.0f32],
-            ],
-            view: camera.get_view(),
-            u_light: [-1.0, 0.4, 0.9f32],
-            perspective: camera.get_perspective(),
-        };
-        let params = glium::DrawParameters {
-            depth: glium::Depth {
-                test: glium::DepthTest::IfLess,
-                write: true,
-                ..Default::default()
-            },
-            backface_culling: glium::draw_parameters::BackfaceCullingMode::CullClockwise,
-            ..Default::default()
-        };
+                // 補助線は毎フレーム作り直す
+                scene.lines.clear();
+                if show_grid {
+                    // 地面の面と重ならないように少し浮かせる
+                    scene.lines.grid(ground + 0.001, 10, 0.25, [0.4, 0.4, 0.4]);
+                    scene.lines.axes(cgmath::Vector3::new(0.0, 0.0, 0.0), 0.5);
+                }
+                if let Some(point) = selected_point {
+                    scene.lines.aabb(&teapot_bounds, [1.0, 1.0, 0.0]);
+                    scene.lines.sphere(point, 0.02, [1.0, 1.0, 1.0]);
+                }
+                if let Some((view, perspective)) = frozen_frustum {
+                    scene.lines.frustum(view, perspective, 1.0, [1.0, 0.5, 0.0]);
+                }
 
-        target
-            .draw(
-                (&positions, &normals),
-                &indices,
-                &program,
-                &uniforms,
-                &params,
-            )
-            .unwrap();
-        target.finish().unwrap();
+                // 法線や深度の表示は色をそのまま見たい
+                post_process.passthrough = scene.debug.shading != scene::Shading::Lit;
+                offscreen_post_process.passthrough = post_process.passthrough;
+                offscreen_post_process.settings = post_process.settings;
+                // 画面外に録画している間は、ウィンドウには描かずに録画する大きさで1回だけ描く
+                let mut recorded = None;
+                let counts = match (&offscreen, recorder.is_some()) {
+                    (Some(offscreen), true) => {
+                        let mut counts = None;
+                        recorded = Some(offscreen.render(&display, |fb| {
+                            counts = Some(
+                                offscreen_post_process
+                                    .render(fb, &camera, |hdr| scene.draw(hdr, &camera)),
+                            );
+                        }));
+                        counts.unwrap()
+                    }
+                    _ => {
+                        let mut target = display.draw();
+                        let counts =
+                            post_process.render(&mut target, &camera, |fb| scene.draw(fb, &camera));
+                        if show_stats {
+                            overlay.stats(&frame_stats);
+                            overlay.draw(&display, &mut target);
+                        }
+                        target.finish().unwrap();
+                        counts
+                    }
+                };
+
+                let now = std::time::Instant::now();
+                let frame_time = (now - last_frame).as_secs_f32() * 1000.0;
+                last_frame = now;
+                if let Err(e) = frame_stats.record(frame_time, counts) {
+                    eprintln!("failed to write frame stats: {}", e);
+                }
+
+                if let Some(supersample) = screenshot.take() {
+                    let image = if supersample {
+                        let (width, height) = display.get_framebuffer_dimensions();
+                        let size = (width * capture::SUPERSAMPLE, height * capture::SUPERSAMPLE);
+                        let offscreen = capture::Offscreen::new(&display, size);
+                        let image = offscreen.render(&display, |fb| {
+                            offscreen_post_process
+                                .render(fb, &camera, |hdr| scene.draw(hdr, &camera));
+                        });
+                        capture::downsample(&image, capture::SUPERSAMPLE)
+                    } else if let Some(image) = &recorded {
+                        // ウィンドウには描いていないので、録画した画像を保存する
+                        image.clone()
+                    } else {
+                        capture::read_window(&display)
+                    };
+                    let name = capture::screenshot_name(std::time::SystemTime::now());
+                    match image.save(&name) {
+                        Ok(()) => println!("saved {}", name),
+                        Err(e) => eprintln!("failed to save {}: {}", name, e),
+                    }
+                }
+
+                if let Some(r) = recorder.as_mut() {
+                    let image = match recorded {
+                        Some(image) => image,
+                        None => capture::read_window(&display),
+                    };
+                    let mut finished = r.frame() + 1 >= record_frames;
+                    if let Err(e) = r.record(&image) {
+                        eprintln!("failed to record frame {}: {}", r.frame(), e);
+                        finished = true;
+                    }
+                    if finished {
+                        let frames = r.frame();
+                        if let Err(e) = recorder.take().unwrap().finish() {
+                            eprintln!("failed to finish recording: {}", e);
+                        }
+                        println!("recorded {} frames", frames);
+                        *control_flow = glutin::event_loop::ControlFlow::Exit;
+                    }
+                }
+            }
+            glutin::event::Event::LoopDestroyed => {
+                if let Some(summary) = frame_stats.summary() {
+                    println!(
+                        "frame time: min {:.2} ms, avg {:.2} ms, max {:.2} ms, 1% low {:.1} fps",
+                        summary.min, summary.avg, summary.max, summary.one_percent_low
+                    );
+                }
+                if let Err(e) = frame_stats.flush() {
+                    eprintln!("failed to write frame stats: {}", e);
+                }
+            }
+            _ => (),
+        }
     });
 }
@@ -0,0 +1,91 @@
+/// 物の塗り方と、その係数
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Material {
+    /// main.fragの光の当て方 (Blinn-Phong)
+    BlinnPhong {
+        color: [f32; 3],
+        /// 周りの景色が映り込む割合
+        reflectivity: f32,
+    },
+    /// pbr.fragのメタリック・ラフネスの物理ベースの塗り方
+    Pbr {
+        base_color: [f32; 3],
+        /// 0なら誘電体、1なら金属
+        metallic: f32,
+        /// 0なら鏡、1ならざらざら
+        roughness: f32,
+    },
+}
+
+impl Material {
+    /// 係数を全て足したときの元の色
+    pub fn color(&self) -> [f32; 3] {
+        match *self {
+            Material::BlinnPhong { color, .. } => color,
+            Material::Pbr { base_color, .. } => base_color,
+        }
+    }
+
+    pub fn is_pbr(&self) -> bool {
+        matches!(self, Material::Pbr { .. })
+    }
+
+    /// BlinnPhongの`reflectivity`、Pbrの`metallic`と`roughness`の順 (使わないものは0)
+    pub fn parameters(&self) -> (f32, f32, f32) {
+        match *self {
+            Material::BlinnPhong { reflectivity, .. } => (reflectivity, 0.0, 0.0),
+            Material::Pbr {
+                metallic,
+                roughness,
+                ..
+            } => (0.0, metallic, roughness.clamp(0.04, 1.0)),
+        }
+    }
+}
+
+/// ティーポットに順に切り替えて付ける材質
+pub const TEAPOT_MATERIALS: [Material; 4] = [
+    Material::BlinnPhong {
+        color: [0.6, 0.0, 0.0],
+        reflectivity: 0.15,
+    },
+    Material::Pbr {
+        base_color: [0.6, 0.0, 0.0],
+        metallic: 0.0,
+        roughness: 0.35,
+    },
+    Material::Pbr {
+        base_color: [1.0, 0.77, 0.34],
+        metallic: 1.0,
+        roughness: 0.25,
+    },
+    Material::Pbr {
+        base_color: [0.95, 0.93, 0.88],
+        metallic: 1.0,
+        roughness: 0.7,
+    },
+];
+
+pub const GROUND: Material = Material::BlinnPhong {
+    color: [0.25, 0.25, 0.25],
+    reflectivity: 0.0,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// ラフネスが0にならないかのテスト (GGXが0で割ってしまう)
+    fn clamps_roughness() {
+        let mirror = Material::Pbr {
+            base_color: [1.0; 3],
+            metallic: 1.0,
+            roughness: 0.0,
+        };
+        assert!(mirror.is_pbr());
+        assert_eq!(mirror.parameters(), (0.0, 1.0, 0.04));
+        assert_eq!(GROUND.parameters().0, 0.0);
+        assert_eq!(TEAPOT_MATERIALS[0].color(), [0.6, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,345 @@
+extern crate cgmath;
+use crate::teapot;
+use cgmath::{InnerSpace, Vector3};
+use glium::index::{IndexBufferAny, PrimitiveType};
+use glium::vertex::{AttributeType, VertexBufferAny, VertexFormat};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// 位置・法線・UV・接線・色をまとめた頂点
+///
+/// 使わない属性は`Attributes`で外してから頂点バッファにする。
+/// 詰めたときと同じ並びになるように、フィールドは宣言の順に置く。
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(C)]
+pub struct MeshVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub tex_coords: [f32; 2],
+    /// xyzが接線で、wが従接線の向き (0なら計算していない)
+    pub tangent: [f32; 4],
+    pub color: [f32; 4],
+}
+
+implement_vertex!(MeshVertex, position, normal, tex_coords, tangent, color);
+
+impl MeshVertex {
+    /// UVと接線が無く、色が白の頂点
+    pub fn new(position: [f32; 3], normal: [f32; 3]) -> MeshVertex {
+        MeshVertex {
+            position,
+            normal,
+            tex_coords: [0.0, 0.0],
+            tangent: [0.0; 4],
+            color: [1.0; 4],
+        }
+    }
+
+    /// teapot.rsと同じ形の、位置と法線で別々の配列から作る
+    pub fn from_arrays(
+        positions: &[teapot::Vertex],
+        normals: &[teapot::Normal],
+    ) -> Vec<MeshVertex> {
+        positions
+            .iter()
+            .zip(normals)
+            .map(|(p, n)| MeshVertex::new(p.position.into(), n.normal.into()))
+            .collect()
+    }
+}
+
+/// 頂点バッファに入れる属性 (位置は必ず入れる)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Attributes {
+    pub normal: bool,
+    pub tex_coords: bool,
+    pub tangent: bool,
+    pub color: bool,
+}
+
+impl Attributes {
+    pub const ALL: Attributes = Attributes {
+        normal: true,
+        tex_coords: true,
+        tangent: true,
+        color: true,
+    };
+
+    /// 属性の名前と、floatいくつ分か
+    fn fields(self) -> Vec<(&'static str, usize)> {
+        let mut fields = vec![("position", 3)];
+        if self.normal {
+            fields.push(("normal", 3));
+        }
+        if self.tex_coords {
+            fields.push(("tex_coords", 2));
+        }
+        if self.tangent {
+            fields.push(("tangent", 4));
+        }
+        if self.color {
+            fields.push(("color", 4));
+        }
+        fields
+    }
+}
+
+/// `attributes`の属性だけを詰めて並べたfloatの列と、その並び方
+///
+/// 1頂点の大きさはfloat4つの倍数に揃える。
+fn pack(vertices: &[MeshVertex], attributes: Attributes) -> (Vec<f32>, VertexFormat, usize) {
+    let fields = attributes.fields();
+    let mut format = Vec::with_capacity(fields.len());
+    let mut offset = 0;
+    for &(name, size) in &fields {
+        let ty = match size {
+            2 => AttributeType::F32F32,
+            3 => AttributeType::F32F32F32,
+            _ => AttributeType::F32F32F32F32,
+        };
+        format.push((Cow::Borrowed(name), offset * 4, ty, false));
+        offset += size;
+    }
+    let stride = offset.div_ceil(4) * 4;
+    let mut data = Vec::with_capacity(vertices.len() * stride);
+    for vertex in vertices {
+        for &(name, _) in &fields {
+            match name {
+                "position" => data.extend_from_slice(&vertex.position),
+                "normal" => data.extend_from_slice(&vertex.normal),
+                "tex_coords" => data.extend_from_slice(&vertex.tex_coords),
+                "tangent" => data.extend_from_slice(&vertex.tangent),
+                _ => data.extend_from_slice(&vertex.color),
+            }
+        }
+        data.resize(data.len() + stride - offset, 0.0);
+    }
+    (data, Cow::Owned(format), stride)
+}
+
+/// 1頂点がfloat4つの`N`倍の頂点バッファ
+fn raw_buffer<F: glium::backend::Facade, const N: usize>(
+    facade: &F,
+    data: &[f32],
+    format: VertexFormat,
+) -> VertexBufferAny {
+    let vertices: Vec<[[f32; 4]; N]> = data
+        .chunks_exact(N * 4)
+        .map(|chunk| {
+            let mut vertex = [[0.0; 4]; N];
+            for (i, v) in vertex.iter_mut().enumerate() {
+                v.copy_from_slice(&chunk[i * 4..i * 4 + 4]);
+            }
+            vertex
+        })
+        .collect();
+    // formatの範囲はpackで1頂点の大きさに収めている
+    unsafe {
+        glium::VertexBuffer::new_raw(facade, &vertices, format, N * 16)
+            .unwrap()
+            .into()
+    }
+}
+
+/// `attributes`の属性だけを交互に並べた頂点バッファ
+///
+/// 属性の組ごとに頂点の型を作らなくても、シェーダーが読む名前の属性だけを渡せる。
+pub fn vertex_buffer<F: glium::backend::Facade>(
+    facade: &F,
+    vertices: &[MeshVertex],
+    attributes: Attributes,
+) -> VertexBufferAny {
+    if attributes == Attributes::ALL {
+        return glium::VertexBuffer::new(facade, vertices).unwrap().into();
+    }
+    let (data, format, stride) = pack(vertices, attributes);
+    match stride / 4 {
+        1 => raw_buffer::<F, 1>(facade, &data, format),
+        2 => raw_buffer::<F, 2>(facade, &data, format),
+        3 => raw_buffer::<F, 3>(facade, &data, format),
+        _ => raw_buffer::<F, 4>(facade, &data, format),
+    }
+}
+
+/// 三角形の添字 (全部が16ビットに収まるなら16ビットにする)
+#[derive(Clone, Debug, PartialEq)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    /// `indices`を、一番大きい添字が収まる一番小さい型にする
+    pub fn new(indices: &[u32]) -> Indices {
+        if indices.iter().all(|&i| i <= u16::MAX as u32) {
+            Indices::U16(indices.iter().map(|&i| i as u16).collect())
+        } else {
+            Indices::U32(indices.to_vec())
+        }
+    }
+
+    pub fn index_buffer<F: glium::backend::Facade>(&self, facade: &F) -> IndexBufferAny {
+        match self {
+            Indices::U16(indices) => {
+                glium::IndexBuffer::new(facade, PrimitiveType::TrianglesList, indices)
+                    .unwrap()
+                    .into()
+            }
+            Indices::U32(indices) => {
+                glium::IndexBuffer::new(facade, PrimitiveType::TrianglesList, indices)
+                    .unwrap()
+                    .into()
+            }
+        }
+    }
+}
+
+/// 同じ位置の頂点をまとめたメッシュ
+pub struct Welded {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+}
+
+/// 同じ位置の頂点を1つにまとめ、潰れた三角形を除く
+///
+/// まとめた頂点の法線は平均する。`crease_angle`を渡すと、法線がそれより大きく曲がる角は
+/// まとめずに分けて、角張ったところを残す (`None`なら法線に関係なくまとめる)。
+/// 使われていない頂点は含めない。
+pub fn weld(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    indices: &[u32],
+    crease_angle: Option<f32>,
+) -> Welded {
+    let min_cos = crease_angle.map(f32::cos);
+    // 位置ごとに、(最初にまとめた角の法線, 頂点の番号)
+    let mut groups: HashMap<[u32; 3], Vec<(Vector3<f32>, u32)>> = HashMap::new();
+    let mut welded = Welded {
+        positions: Vec::new(),
+        normals: Vec::new(),
+        indices: Vec::with_capacity(indices.len()),
+    };
+    let mut sums: Vec<Vector3<f32>> = Vec::new();
+    for triangle in indices.chunks(3) {
+        let corners: Vec<u32> = triangle
+            .iter()
+            .map(|&i| {
+                let p = positions[i as usize];
+                let n = Vector3::from(normals[i as usize]);
+                let group = groups.entry(p.map(f32::to_bits)).or_default();
+                let found = group.iter().find(|(first, _)| match min_cos {
+                    Some(min_cos) => first.dot(n) >= min_cos * first.magnitude() * n.magnitude(),
+                    None => true,
+                });
+                let index = match found {
+                    Some(&(_, index)) => index,
+                    None => {
+                        welded.positions.push(p);
+                        sums.push(Vector3::new(0.0, 0.0, 0.0));
+                        let index = welded.positions.len() as u32 - 1;
+                        group.push((n, index));
+                        index
+                    }
+                };
+                sums[index as usize] += n;
+                index
+            })
+            .collect();
+        if corners[0] != corners[1] && corners[1] != corners[2] && corners[2] != corners[0] {
+            welded.indices.extend(corners);
+        }
+    }
+    welded.normals = sums
+        .iter()
+        .map(|&n| {
+            if n.magnitude2() > 0.0 {
+                n.normalize().into()
+            } else {
+                n.into()
+            }
+        })
+        .collect();
+    welded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glium::vertex::Vertex;
+
+    fn vertex() -> MeshVertex {
+        MeshVertex {
+            position: [1.0, 2.0, 3.0],
+            normal: [0.0, 1.0, 0.0],
+            tex_coords: [0.25, 0.75],
+            tangent: [1.0, 0.0, 0.0, -1.0],
+            color: [0.5, 0.5, 0.5, 1.0],
+        }
+    }
+
+    #[test]
+    fn packs_selected_attributes() {
+        let attributes = Attributes {
+            normal: true,
+            tex_coords: true,
+            ..Attributes::default()
+        };
+        let (data, format, stride) = pack(&[vertex(), vertex()], attributes);
+        // 3 + 3 + 2 = 8はそのまま
+        assert_eq!(stride, 8);
+        assert_eq!(data.len(), 16);
+        assert_eq!(&data[..8], &[1.0, 2.0, 3.0, 0.0, 1.0, 0.0, 0.25, 0.75]);
+        let names: Vec<(&str, usize)> = format.iter().map(|f| (&*f.0, f.1)).collect();
+        assert_eq!(
+            names,
+            vec![("position", 0), ("normal", 12), ("tex_coords", 24)]
+        );
+
+        // 位置だけなら1つ分を埋めて4つにする
+        let (data, _, stride) = pack(&[vertex()], Attributes::default());
+        assert_eq!(stride, 4);
+        assert_eq!(data, vec![1.0, 2.0, 3.0, 0.0]);
+    }
+
+    #[test]
+    fn matches_struct_layout() {
+        // 全部の属性を詰めたものは、構造体そのままと同じ並びになる
+        let (data, format, stride) = pack(&[vertex()], Attributes::ALL);
+        assert_eq!(stride * 4, std::mem::size_of::<MeshVertex>());
+        let offsets: Vec<usize> = format.iter().map(|f| f.1).collect();
+        let expected: Vec<usize> = MeshVertex::build_bindings().iter().map(|f| f.1).collect();
+        assert_eq!(offsets, expected);
+        assert_eq!(data[12..], vertex().color);
+
+        let vertices = MeshVertex::from_arrays(&teapot::VERTICES, &teapot::NORMALS);
+        assert_eq!(vertices.len(), teapot::VERTICES.len());
+        assert_eq!(
+            vertices[1].position,
+            <[f32; 3]>::from(teapot::VERTICES[1].position)
+        );
+        assert_eq!(vertices[1].color, [1.0; 4]);
+    }
+
+    #[test]
+    fn widens_indices_past_u16() {
+        let indices = [0, 1, 65535];
+        assert_eq!(Indices::new(&indices), Indices::U16(vec![0, 1, 65535]));
+        // 頂点の数ではなく添字そのもので決めるので、範囲外の添字も切り詰めない
+        assert_eq!(
+            Indices::new(&[0, 1, 65536]),
+            Indices::U32(vec![0, 1, 65536])
+        );
+
+        // 7万個の頂点の後ろの方を指す添字は16ビットに収まらない
+        let plane = crate::primitives::plane(1.0, 264);
+        let vertex_count = plane.positions.len();
+        assert!(vertex_count > 70_000);
+        let last = *plane.indices.iter().max().unwrap();
+        assert_eq!(last as usize, vertex_count - 1);
+        match Indices::new(&plane.indices) {
+            Indices::U32(indices) => assert_eq!(indices, plane.indices),
+            Indices::U16(_) => panic!("{} vertices do not fit in u16", vertex_count),
+        }
+    }
+}
@@ -0,0 +1,992 @@
+extern crate cgmath;
+use crate::mesh::{self, MeshVertex};
+use crate::teapot;
+use cgmath::{InnerSpace, Vector3};
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// STLの面の法線がこれより大きく曲がる角は、頂点をまとめずに角張ったまま残す
+const STL_CREASE_ANGLE: f32 = std::f32::consts::PI / 3.0;
+
+/// 書き出すファイルの形式
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Obj,
+    /// リトルエンディアンのバイナリのPLY
+    Ply,
+    PlyAscii,
+    /// バイナリのSTL (位置と面の法線だけ)
+    Stl,
+}
+
+impl Format {
+    /// 拡張子から決める (PLYはバイナリにする)
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "obj" => Some(Format::Obj),
+            "ply" => Some(Format::Ply),
+            "stl" => Some(Format::Stl),
+            _ => None,
+        }
+    }
+}
+
+/// ファイルから読んだメッシュ (teapot.rsと同じく、位置と法線を別々の配列に持つ)
+#[derive(Clone)]
+pub struct MeshData {
+    pub positions: Vec<teapot::Vertex>,
+    pub normals: Vec<teapot::Normal>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub colors: Vec<[f32; 4]>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    fn new() -> MeshData {
+        MeshData {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            tex_coords: Vec::new(),
+            colors: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, position: [f32; 3], normal: [f32; 3], tex_coords: [f32; 2]) -> u32 {
+        self.positions.push(teapot::Vertex {
+            position: position.into(),
+        });
+        self.normals.push(teapot::Normal {
+            normal: normal.into(),
+        });
+        self.tex_coords.push(tex_coords);
+        self.colors.push([1.0; 4]);
+        self.positions.len() as u32 - 1
+    }
+
+    /// `missing`の頂点の法線を、その頂点を使う面の法線を面積で重み付けして平均したものにする
+    fn smooth_normals(&mut self, missing: &[usize]) {
+        if missing.is_empty() {
+            return;
+        }
+        let mut sums = vec![Vector3::new(0.0, 0.0, 0.0); self.positions.len()];
+        for t in self.indices.chunks_exact(3) {
+            let p = [0, 1, 2].map(|k| Vector3::from(self.positions[t[k] as usize].position));
+            let normal = (p[1] - p[0]).cross(p[2] - p[0]);
+            for &i in t {
+                sums[i as usize] += normal;
+            }
+        }
+        for &i in missing {
+            if sums[i].magnitude2() > 0.0 {
+                self.normals[i].normal = sums[i].normalize().into();
+            }
+        }
+    }
+
+    /// UVと色付きの頂点
+    pub fn vertices(&self) -> Vec<MeshVertex> {
+        (0..self.positions.len())
+            .map(|i| MeshVertex {
+                tex_coords: self.tex_coords[i],
+                color: self.colors[i],
+                ..MeshVertex::new(
+                    self.positions[i].position.into(),
+                    self.normals[i].normal.into(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// 位置・UV・法線を頂点ごとに1行ずつ書き、面は同じ番号で3つとも指す
+pub fn write_obj<W: Write>(w: &mut W, vertices: &[MeshVertex], indices: &[u32]) -> io::Result<()> {
+    writeln!(w, "# opengl-by-rust")?;
+    for v in vertices {
+        let p = v.position;
+        writeln!(w, "v {} {} {}", p[0], p[1], p[2])?;
+    }
+    for v in vertices {
+        writeln!(w, "vt {} {}", v.tex_coords[0], v.tex_coords[1])?;
+    }
+    for v in vertices {
+        let n = v.normal;
+        writeln!(w, "vn {} {} {}", n[0], n[1], n[2])?;
+    }
+    // OBJの番号は1から
+    for t in indices.chunks_exact(3) {
+        let (a, b, c) = (t[0] + 1, t[1] + 1, t[2] + 1);
+        writeln!(w, "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}", a, b, c)?;
+    }
+    Ok(())
+}
+
+/// 色を0から255のバイトにする
+fn color_byte(c: f32) -> u8 {
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// 位置・法線・UV・色を持つ頂点と、三角形の面を書く
+///
+/// `ascii`でなければリトルエンディアンのバイナリにする。
+pub fn write_ply<W: Write>(
+    w: &mut W,
+    vertices: &[MeshVertex],
+    indices: &[u32],
+    ascii: bool,
+) -> io::Result<()> {
+    let format = if ascii {
+        "ascii"
+    } else {
+        "binary_little_endian"
+    };
+    writeln!(w, "ply")?;
+    writeln!(w, "format {} 1.0", format)?;
+    writeln!(w, "comment opengl-by-rust")?;
+    writeln!(w, "element vertex {}", vertices.len())?;
+    for name in &["x", "y", "z", "nx", "ny", "nz", "s", "t"] {
+        writeln!(w, "property float {}", name)?;
+    }
+    for name in &["red", "green", "blue", "alpha"] {
+        writeln!(w, "property uchar {}", name)?;
+    }
+    writeln!(w, "element face {}", indices.len() / 3)?;
+    writeln!(w, "property list uchar uint vertex_indices")?;
+    writeln!(w, "end_header")?;
+
+    for v in vertices {
+        let floats = [
+            v.position[0],
+            v.position[1],
+            v.position[2],
+            v.normal[0],
+            v.normal[1],
+            v.normal[2],
+            v.tex_coords[0],
+            v.tex_coords[1],
+        ];
+        let color = v.color.map(color_byte);
+        if ascii {
+            let floats: Vec<String> = floats.iter().map(f32::to_string).collect();
+            let color: Vec<String> = color.iter().map(u8::to_string).collect();
+            writeln!(w, "{} {}", floats.join(" "), color.join(" "))?;
+        } else {
+            for f in &floats {
+                w.write_all(&f.to_le_bytes())?;
+            }
+            w.write_all(&color)?;
+        }
+    }
+    for t in indices.chunks_exact(3) {
+        if ascii {
+            writeln!(w, "3 {} {} {}", t[0], t[1], t[2])?;
+        } else {
+            w.write_all(&[3])?;
+            for i in t {
+                w.write_all(&i.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 三角形ごとに面の法線と3つの位置を書く (頂点の法線・UV・色は残らない)
+pub fn write_stl<W: Write>(w: &mut W, vertices: &[MeshVertex], indices: &[u32]) -> io::Result<()> {
+    // 先頭が`solid`だとテキストのSTLと間違えられるので避ける
+    let mut header = [0u8; 80];
+    let name = b"binary STL from opengl-by-rust";
+    header[..name.len()].copy_from_slice(name);
+    w.write_all(&header)?;
+    w.write_all(&(indices.len() as u32 / 3).to_le_bytes())?;
+    for t in indices.chunks_exact(3) {
+        let p = [0, 1, 2].map(|k| Vector3::from(vertices[t[k] as usize].position));
+        let normal = (p[1] - p[0]).cross(p[2] - p[0]);
+        let normal = if normal.magnitude2() > 0.0 {
+            normal.normalize()
+        } else {
+            normal
+        };
+        for v in std::iter::once(normal).chain(p) {
+            for f in &[v.x, v.y, v.z] {
+                w.write_all(&f.to_le_bytes())?;
+            }
+        }
+        // 属性のバイト数 (使わない)
+        w.write_all(&0u16.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// `format`で`path`に書き出す
+pub fn save(
+    path: &Path,
+    format: Format,
+    vertices: &[MeshVertex],
+    indices: &[u32],
+) -> io::Result<()> {
+    let mut w = BufWriter::new(std::fs::File::create(path)?);
+    match format {
+        Format::Obj => write_obj(&mut w, vertices, indices)?,
+        Format::Ply => write_ply(&mut w, vertices, indices, false)?,
+        Format::PlyAscii => write_ply(&mut w, vertices, indices, true)?,
+        Format::Stl => write_stl(&mut w, vertices, indices)?,
+    }
+    w.flush()
+}
+
+/// `words`の先頭`N`個の数
+fn numbers<'a, I: Iterator<Item = &'a str>, const N: usize>(
+    mut words: I,
+) -> Result<[f32; N], String> {
+    let mut values = [0.0; N];
+    for value in values.iter_mut() {
+        let word = words.next().ok_or(format!("expected {} numbers", N))?;
+        *value = word.parse().map_err(|e| format!("{}: {}", word, e))?;
+    }
+    Ok(values)
+}
+
+/// OBJの番号 (負なら後ろから数える) を0から始まる添字にする
+fn resolve(index: &str, count: usize) -> Result<usize, String> {
+    let i: i64 = index.parse().map_err(|e| format!("{}: {}", index, e))?;
+    let resolved = if i < 0 { count as i64 + i } else { i - 1 };
+    if (0..count as i64).contains(&resolved) {
+        Ok(resolved as usize)
+    } else {
+        Err(format!("index {} out of range", i))
+    }
+}
+
+/// OBJの`v`・`vt`・`vn`・`f`を読む
+///
+/// 多角形は扇形に三角形に分ける。位置・UV・法線の組が同じ角は1つの頂点にまとめる。
+/// 法線が無い角には、その頂点を使う面の法線を面積で重み付けして平均したものを入れる。
+/// それ以外の行 (`o`・`g`・`usemtl`など) は読み飛ばす。
+pub fn read_obj(text: &str) -> io::Result<MeshData> {
+    let invalid = |line: usize, message: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("obj line {}: {}", line + 1, message),
+        )
+    };
+
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut tex_coords: Vec<[f32; 2]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut mesh = MeshData::new();
+    let mut corners: HashMap<(usize, Option<usize>, Option<usize>), u32> = HashMap::new();
+    let mut without_normals = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => positions.push(numbers(words).map_err(|e| invalid(n, e))?),
+            Some("vt") => tex_coords.push(numbers(words).map_err(|e| invalid(n, e))?),
+            Some("vn") => normals.push(numbers(words).map_err(|e| invalid(n, e))?),
+            Some("f") => {
+                let mut face = Vec::new();
+                for word in words {
+                    let mut parts = word.split('/');
+                    let p = resolve(parts.next().unwrap_or(""), positions.len());
+                    let p = p.map_err(|e| invalid(n, e))?;
+                    let mut optional = |count: usize| match parts.next() {
+                        Some("") | None => Ok(None),
+                        Some(i) => resolve(i, count).map(Some).map_err(|e| invalid(n, e)),
+                    };
+                    let t = optional(tex_coords.len())?;
+                    let normal = optional(normals.len())?;
+                    let index = *corners.entry((p, t, normal)).or_insert_with(|| {
+                        if normal.is_none() {
+                            without_normals.push(mesh.positions.len());
+                        }
+                        mesh.push(
+                            positions[p],
+                            normal.map_or([0.0; 3], |i| normals[i]),
+                            t.map_or([0.0; 2], |i| tex_coords[i]),
+                        )
+                    });
+                    face.push(index);
+                }
+                if face.len() < 3 {
+                    return Err(invalid(n, "a face needs at least 3 vertices".to_string()));
+                }
+                for i in 1..face.len() - 1 {
+                    mesh.indices
+                        .extend_from_slice(&[face[0], face[i], face[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    mesh.smooth_normals(&without_normals);
+    Ok(mesh)
+}
+
+/// PLYの数の型
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        match name {
+            "char" | "int8" => Some(Scalar::I8),
+            "uchar" | "uint8" => Some(Scalar::U8),
+            "short" | "int16" => Some(Scalar::I16),
+            "ushort" | "uint16" => Some(Scalar::U16),
+            "int" | "int32" => Some(Scalar::I32),
+            "uint" | "uint32" => Some(Scalar::U32),
+            "float" | "float32" => Some(Scalar::F32),
+            "double" | "float64" => Some(Scalar::F64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    /// 色の値をこれで割って0から1にする (小数ならそのまま)
+    fn color_scale(self) -> f64 {
+        match self {
+            Scalar::U8 => 255.0,
+            Scalar::U16 => 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+/// PLYの要素の1つの値 (`list`なら個数の型も持つ)
+struct Property {
+    name: String,
+    list: Option<Scalar>,
+    scalar: Scalar,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// PLYの本体から数を順に取り出す
+enum Values<'a> {
+    Text(std::str::SplitWhitespace<'a>),
+    Binary {
+        bytes: &'a [u8],
+        position: usize,
+        big_endian: bool,
+    },
+}
+
+impl Values<'_> {
+    fn scalar(&mut self, scalar: Scalar) -> io::Result<f64> {
+        let truncated = || io::Error::new(io::ErrorKind::UnexpectedEof, "file is truncated");
+        match self {
+            Values::Text(words) => {
+                let word = words.next().ok_or_else(truncated)?;
+                word.parse().map_err(|e| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", word, e))
+                })
+            }
+            Values::Binary {
+                bytes,
+                position,
+                big_endian,
+            } => {
+                let size = scalar.size();
+                let b = bytes
+                    .get(*position..*position + size)
+                    .ok_or_else(truncated)?;
+                *position += size;
+                // 大きい方から並べ直して、小さい方からの並びとして読む
+                let mut buffer = [0u8; 8];
+                buffer[..size].copy_from_slice(b);
+                if *big_endian {
+                    buffer[..size].reverse();
+                }
+                let (b2, b4) = (
+                    [buffer[0], buffer[1]],
+                    [buffer[0], buffer[1], buffer[2], buffer[3]],
+                );
+                Ok(match scalar {
+                    Scalar::I8 => buffer[0] as i8 as f64,
+                    Scalar::U8 => buffer[0] as f64,
+                    Scalar::I16 => i16::from_le_bytes(b2) as f64,
+                    Scalar::U16 => u16::from_le_bytes(b2) as f64,
+                    Scalar::I32 => i32::from_le_bytes(b4) as f64,
+                    Scalar::U32 => u32::from_le_bytes(b4) as f64,
+                    Scalar::F32 => f32::from_le_bytes(b4) as f64,
+                    Scalar::F64 => f64::from_le_bytes(buffer),
+                })
+            }
+        }
+    }
+
+    /// 1つの値 (`list`ならその中身を全部)
+    fn property(&mut self, property: &Property) -> io::Result<Vec<f64>> {
+        let count = match property.list {
+            Some(count) => self.scalar(count)? as usize,
+            None => 1,
+        };
+        (0..count).map(|_| self.scalar(property.scalar)).collect()
+    }
+}
+
+/// テキストとバイナリ (どちらのエンディアンも) のPLYを読む
+///
+/// `vertex`の位置・法線・UV・色と、`face`の多角形を扇形に分けた三角形を使い、
+/// それ以外の要素は読み飛ばす。法線が無ければ面の法線から求める。
+pub fn read_ply(bytes: &[u8]) -> io::Result<MeshData> {
+    let invalid =
+        |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("ply: {}", message));
+    if !bytes.starts_with(b"ply") {
+        return Err(invalid("missing the `ply` line".to_string()));
+    }
+    let end = bytes
+        .windows(10)
+        .position(|w| w == b"end_header")
+        .ok_or_else(|| invalid("missing `end_header`".to_string()))?;
+    let body = bytes[end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |i| end + i + 1);
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in String::from_utf8_lossy(&bytes[..end]).lines().skip(1) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let scalar = |name: &str| {
+            Scalar::parse(name).ok_or_else(|| invalid(format!("unknown type `{}`", name)))
+        };
+        let last = elements.last_mut();
+        match words.as_slice() {
+            ["format", name, _] => format = Some(name.to_string()),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|e| invalid(format!("element {}: {}", name, e)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let element =
+                    last.ok_or_else(|| invalid("property before any element".to_string()))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    list: Some(scalar(count)?),
+                    scalar: scalar(item)?,
+                });
+            }
+            ["property", ty, name] => {
+                let element =
+                    last.ok_or_else(|| invalid("property before any element".to_string()))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    list: None,
+                    scalar: scalar(ty)?,
+                });
+            }
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            _ => return Err(invalid(format!("unexpected header line `{}`", line))),
+        }
+    }
+    let body = &bytes[body..];
+    let mut values = match format.as_deref() {
+        Some("ascii") => Values::Text(
+            std::str::from_utf8(body)
+                .map_err(|e| invalid(e.to_string()))?
+                .split_whitespace(),
+        ),
+        Some("binary_little_endian") | Some("binary_big_endian") => Values::Binary {
+            bytes: body,
+            position: 0,
+            big_endian: format.as_deref() == Some("binary_big_endian"),
+        },
+        Some(other) => return Err(invalid(format!("unknown format `{}`", other))),
+        None => return Err(invalid("missing `format`".to_string())),
+    };
+
+    let mut mesh = MeshData::new();
+    let mut has_normals = false;
+    for element in &elements {
+        for i in 0..element.count {
+            // 切れたファイルでは、どの要素のどこで切れたかを示す
+            let at = |e: io::Error| {
+                io::Error::new(
+                    e.kind(),
+                    format!("ply: {} {} of {}: {}", element.name, i, element.count, e),
+                )
+            };
+            let mut position = [0.0; 3];
+            let mut normal = [0.0; 3];
+            let mut tex_coords = [0.0; 2];
+            let mut color = [1.0; 4];
+            for property in &element.properties {
+                let value = values.property(property).map_err(at)?;
+                let first = value.first().copied().unwrap_or(0.0);
+                match (element.name.as_str(), property.name.as_str()) {
+                    ("vertex", "x") => position[0] = first as f32,
+                    ("vertex", "y") => position[1] = first as f32,
+                    ("vertex", "z") => position[2] = first as f32,
+                    ("vertex", "nx") => {
+                        has_normals = true;
+                        normal[0] = first as f32;
+                    }
+                    ("vertex", "ny") => normal[1] = first as f32,
+                    ("vertex", "nz") => normal[2] = first as f32,
+                    ("vertex", "s") | ("vertex", "u") | ("vertex", "texture_u") => {
+                        tex_coords[0] = first as f32
+                    }
+                    ("vertex", "t") | ("vertex", "v") | ("vertex", "texture_v") => {
+                        tex_coords[1] = first as f32
+                    }
+                    ("vertex", channel @ ("red" | "green" | "blue" | "alpha")) => {
+                        let c = ["red", "green", "blue", "alpha"]
+                            .iter()
+                            .position(|&name| name == channel)
+                            .unwrap();
+                        color[c] = (first / property.scalar.color_scale()) as f32;
+                    }
+                    ("face", "vertex_indices") | ("face", "vertex_index") => {
+                        if value.len() < 3 {
+                            return Err(invalid(format!(
+                                "face {} has {} vertices",
+                                i,
+                                value.len()
+                            )));
+                        }
+                        // 負の数や小数、u32に収まらない数を切り捨てて読まないようにする
+                        if let Some(v) = value
+                            .iter()
+                            .find(|&&v| v < 0.0 || v.fract() != 0.0 || v > u32::MAX as f64)
+                        {
+                            return Err(invalid(format!("face {} refers to vertex {}", i, v)));
+                        }
+                        let face: Vec<u32> = value.iter().map(|&v| v as u32).collect();
+                        for k in 1..face.len() - 1 {
+                            mesh.indices
+                                .extend_from_slice(&[face[0], face[k], face[k + 1]]);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if element.name == "vertex" {
+                let index = mesh.push(position, normal, tex_coords) as usize;
+                mesh.colors[index] = color;
+            }
+        }
+    }
+    if let Some(&i) = mesh
+        .indices
+        .iter()
+        .find(|&&i| i as usize >= mesh.positions.len())
+    {
+        return Err(invalid(format!(
+            "a face refers to vertex {} of {}",
+            i,
+            mesh.positions.len()
+        )));
+    }
+    if !has_normals {
+        let missing: Vec<usize> = (0..mesh.positions.len()).collect();
+        mesh.smooth_normals(&missing);
+    }
+    Ok(mesh)
+}
+
+/// テキストのSTLの三角形
+fn read_stl_text(text: &str) -> io::Result<Vec<[[f32; 3]; 3]>> {
+    let invalid = |line: usize, message: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("stl line {}: {}", line + 1, message),
+        )
+    };
+    let mut triangles = Vec::new();
+    let mut facet: Option<Vec<[f32; 3]>> = None;
+    for (n, line) in text.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        match (words.next(), &mut facet) {
+            (Some("facet"), None) => facet = Some(Vec::new()),
+            (Some("vertex"), Some(vertices)) => {
+                vertices.push(numbers(words).map_err(|e| invalid(n, e))?)
+            }
+            (Some("endfacet"), Some(vertices)) => {
+                if vertices.len() != 3 {
+                    return Err(invalid(
+                        n,
+                        format!("a facet has {} vertices", vertices.len()),
+                    ));
+                }
+                triangles.push([vertices[0], vertices[1], vertices[2]]);
+                facet = None;
+            }
+            (Some("facet"), Some(_)) | (Some("vertex"), None) | (Some("endfacet"), None) => {
+                return Err(invalid(
+                    n,
+                    "unexpected `facet`, `vertex` or `endfacet`".to_string(),
+                ))
+            }
+            _ => {}
+        }
+    }
+    if facet.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "stl: file ends inside a facet",
+        ));
+    }
+    Ok(triangles)
+}
+
+/// テキストとバイナリのSTLを読み、同じ位置の頂点を1つにまとめる
+///
+/// ファイルの面の法線は使わずに位置から求め直し、まとめた頂点の法線は周りの面の平均にする。
+/// 大きさが三角形の数と合うものはバイナリとして読み、合わなければ`solid`で始まるものをテキストとして読む。
+pub fn read_stl(bytes: &[u8]) -> io::Result<MeshData> {
+    let count = bytes
+        .get(80..84)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+    let expected = count.map(|count| 84 + 50 * count as u64);
+    // バイナリのヘッダーも`solid`で始まることがあるので、0のバイトが無い文字列だけをテキストとみなす
+    let text =
+        bytes.starts_with(b"solid") && !bytes.contains(&0) && std::str::from_utf8(bytes).is_ok();
+    let triangles = match (count, expected) {
+        // テキストでなければ、後ろに余ったバイトは無視する
+        (Some(count), Some(expected))
+            if bytes.len() as u64 == expected || (bytes.len() as u64 > expected && !text) =>
+        {
+            (0..count)
+                .map(|i| {
+                    let float = |k: usize| {
+                        let at = 84 + i * 50 + 12 + k * 4;
+                        f32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+                    };
+                    [0, 1, 2].map(|v| [float(v * 3), float(v * 3 + 1), float(v * 3 + 2)])
+                })
+                .collect()
+        }
+        _ if text => read_stl_text(std::str::from_utf8(bytes).unwrap())?,
+        (Some(count), Some(expected)) => {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "stl: the header says {} triangles ({} bytes) but the file has {} bytes",
+                    count,
+                    expected,
+                    bytes.len()
+                ),
+            ))
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "stl: the file has {} bytes, shorter than the 84 byte header",
+                    bytes.len()
+                ),
+            ))
+        }
+    };
+
+    let positions: Vec<[f32; 3]> = triangles.iter().flatten().copied().collect();
+    let normals: Vec<[f32; 3]> = triangles
+        .iter()
+        .flat_map(|t| {
+            let p = t.map(Vector3::from);
+            let normal = (p[1] - p[0]).cross(p[2] - p[0]);
+            let normal: [f32; 3] = if normal.magnitude2() > 0.0 {
+                normal.normalize().into()
+            } else {
+                [0.0; 3]
+            };
+            [normal; 3]
+        })
+        .collect();
+    let indices: Vec<u32> = (0..positions.len() as u32).collect();
+    let welded = mesh::weld(&positions, &normals, &indices, Some(STL_CREASE_ANGLE));
+    let mut mesh = MeshData::new();
+    for (&p, &n) in welded.positions.iter().zip(&welded.normals) {
+        mesh.push(p, n, [0.0; 2]);
+    }
+    mesh.indices = welded.indices;
+    Ok(mesh)
+}
+
+/// 拡張子から形式を決めて読む
+pub fn load(path: &Path) -> io::Result<MeshData> {
+    match Format::from_path(path) {
+        Some(Format::Obj) => read_obj(&std::fs::read_to_string(path)?),
+        Some(Format::Ply) | Some(Format::PlyAscii) => read_ply(&std::fs::read(path)?),
+        Some(Format::Stl) => read_stl(&std::fs::read(path)?),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: expected an .obj, .ply or .stl file", path.display()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::{self, Shape};
+    use std::convert::TryInto;
+
+    /// 三角形の角ごとの頂点 (添字の振り方が違っても比べられる)
+    fn corners(vertices: &[MeshVertex], indices: &[u32]) -> Vec<MeshVertex> {
+        indices.iter().map(|&i| vertices[i as usize]).collect()
+    }
+
+    #[test]
+    fn round_trips_obj() {
+        let torus = Shape::Torus.primitive(1.0);
+        let teapot_vertices = MeshVertex::from_arrays(&teapot::VERTICES, &teapot::NORMALS);
+        let teapot_indices: Vec<u32> = teapot::INDICES.iter().map(|&i| i as u32).collect();
+        let meshes = [
+            (torus.vertices(), torus.indices.clone()),
+            (teapot_vertices, teapot_indices),
+        ];
+        for (vertices, indices) in &meshes {
+            let mut bytes = Vec::new();
+            write_obj(&mut bytes, vertices, indices).unwrap();
+            let mesh = read_obj(std::str::from_utf8(&bytes).unwrap()).unwrap();
+            assert_eq!(mesh.indices.len(), indices.len());
+            assert_eq!(
+                corners(&mesh.vertices(), &mesh.indices),
+                corners(vertices, indices)
+            );
+        }
+    }
+
+    #[test]
+    fn reads_obj_polygons() {
+        // 法線の無い四角形を、後ろから数えた番号で指す
+        let text = "o quad\nv 0 0 0\nv 1 0 0\nv 1 0 -1\nv 0 0 -1\nvt 0 0\nf -4/1 -3/1 -2/1 -1/1\n";
+        let mesh = read_obj(text).unwrap();
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        for n in &mesh.normals {
+            assert_eq!(<[f32; 3]>::from(n.normal), [0.0, 1.0, 0.0]);
+        }
+
+        let error = read_obj("v 0 0 0\nf 1 2 3\n").err().unwrap();
+        assert_eq!(error.to_string(), "obj line 2: index 2 out of range");
+        assert!(read_obj("v 0 0\n").is_err());
+        assert!(read_obj("v 0 0 0\nf 1 1\n").is_err());
+    }
+
+    #[test]
+    fn writes_ply() {
+        let cube = Shape::Cube.primitive(1.0);
+        let vertices = cube.vertices();
+
+        let mut text = Vec::new();
+        write_ply(&mut text, &vertices, &cube.indices, true).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let (header, body) = text.split_at(text.find("end_header\n").unwrap() + 11);
+        assert!(header.starts_with("ply\nformat ascii 1.0\n"));
+        assert!(header.contains("element vertex 24\n"));
+        assert!(header.contains("element face 12\n"));
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines.len(), 24 + 12);
+        let first: Vec<f32> = lines[0]
+            .split_whitespace()
+            .map(|w| w.parse().unwrap())
+            .collect();
+        assert_eq!(first[..3], vertices[0].position);
+        assert_eq!(first[8..], [255.0; 4]);
+        assert_eq!(lines[24], "3 0 1 2");
+
+        // 頂点は4バイトのfloat8つと色4バイト、面は個数1バイトと添字3つ
+        let mut binary = Vec::new();
+        write_ply(&mut binary, &vertices, &cube.indices, false).unwrap();
+        let header_length = header.replace("ascii", "binary_little_endian").len();
+        assert_eq!(binary.len(), header_length + 24 * 36 + 12 * 13);
+        let x = f32::from_le_bytes(binary[header_length..header_length + 4].try_into().unwrap());
+        assert_eq!(x, vertices[0].position[0]);
+    }
+
+    #[test]
+    fn writes_binary_stl() {
+        let plane = primitives::plane(1.0, 2);
+        let mut bytes = Vec::new();
+        write_stl(&mut bytes, &plane.vertices(), &plane.indices).unwrap();
+        assert!(!bytes.starts_with(b"solid"));
+        assert_eq!(bytes.len(), 84 + 8 * 50);
+        assert_eq!(u32::from_le_bytes(bytes[80..84].try_into().unwrap()), 8);
+        let float =
+            |i: usize| f32::from_le_bytes(bytes[84 + i * 4..88 + i * 4].try_into().unwrap());
+        // 上を向いた面
+        assert_eq!([float(0), float(1), float(2)], [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn round_trips_ply() {
+        let cube = Shape::Cube.primitive(1.0);
+        let mut vertices = cube.vertices();
+        // 色はバイトで書くので、255分の1の倍数なら元に戻る
+        for (i, v) in vertices.iter_mut().enumerate() {
+            v.color = [i as f32 / 255.0, 1.0, 0.0, 128.0 / 255.0];
+        }
+        for &ascii in &[true, false] {
+            let mut bytes = Vec::new();
+            write_ply(&mut bytes, &vertices, &cube.indices, ascii).unwrap();
+            let mesh = read_ply(&bytes).unwrap();
+            assert_eq!(mesh.vertices(), vertices);
+            assert_eq!(mesh.indices, cube.indices);
+        }
+    }
+
+    #[test]
+    fn reads_big_endian_ply() {
+        let mut bytes = b"ply\nformat binary_big_endian 1.0\nelement vertex 4\n\
+            property float x\nproperty float y\nproperty float z\nproperty uchar red\n\
+            element face 1\nproperty list uchar int vertex_indices\nend_header\n"
+            .to_vec();
+        for p in &[
+            [0.0f32, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ] {
+            for f in p {
+                bytes.extend_from_slice(&f.to_be_bytes());
+            }
+            bytes.push(255);
+        }
+        bytes.push(4);
+        for i in 0..4i32 {
+            bytes.extend_from_slice(&i.to_be_bytes());
+        }
+        let mesh = read_ply(&bytes).unwrap();
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        let vertices = mesh.vertices();
+        assert_eq!(vertices[2].position, [1.0, 1.0, 0.0]);
+        assert_eq!(vertices[2].color, [1.0; 4]);
+        // 法線が無いので面から求める
+        assert_eq!(vertices[2].normal, [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn welds_stl() {
+        let sphere = primitives::icosphere(1.0, 1);
+        let mut bytes = Vec::new();
+        write_stl(&mut bytes, &sphere.vertices(), &sphere.indices).unwrap();
+        let mesh = read_stl(&bytes).unwrap();
+        assert_eq!(mesh.positions.len(), sphere.positions.len());
+        assert_eq!(mesh.indices.len(), sphere.indices.len());
+        let original: Vec<[f32; 3]> = corners(&sphere.vertices(), &sphere.indices)
+            .iter()
+            .map(|v| v.position)
+            .collect();
+        let vertices = mesh.vertices();
+        let read: Vec<[f32; 3]> = corners(&vertices, &mesh.indices)
+            .iter()
+            .map(|v| v.position)
+            .collect();
+        assert_eq!(read, original);
+        for v in &vertices {
+            let (p, n) = (Vector3::from(v.position), Vector3::from(v.normal));
+            assert!(p.normalize().dot(n) > 0.98);
+        }
+
+        let text = "solid quad\n\
+            facet normal 0 0 1\n outer loop\n vertex 0 0 0\n vertex 1 0 0\n vertex 1 1 0\n endloop\nendfacet\n\
+            facet normal 0 0 1\n outer loop\n vertex 0 0 0\n vertex 1 1 0\n vertex 0 1 0\n endloop\nendfacet\n\
+            endsolid quad\n";
+        let mesh = read_stl(text.as_bytes()).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(<[f32; 3]>::from(mesh.normals[3].normal), [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    /// STLの立方体の角が、面ごとの法線のまま残るかのテスト
+    fn keeps_stl_creases() {
+        let cube = Shape::Cube.primitive(1.0);
+        let mut bytes = Vec::new();
+        write_stl(&mut bytes, &cube.vertices(), &cube.indices).unwrap();
+        let mesh = read_stl(&bytes).unwrap();
+        // 8つの角がそれぞれ3つの面に分かれる
+        assert_eq!(mesh.positions.len(), 24);
+        for v in mesh.vertices() {
+            let n = v.normal;
+            assert_eq!(n.iter().filter(|c| c.abs() == 1.0).count(), 1, "{:?}", v);
+            assert_eq!(n.iter().filter(|&&c| c == 0.0).count(), 2, "{:?}", v);
+        }
+    }
+
+    #[test]
+    /// 頂点の番号として使えない数をエラーにするかのテスト
+    fn rejects_bad_ply_indices() {
+        let header = "ply\nformat ascii 1.0\nelement vertex 3\n\
+            property float x\nproperty float y\nproperty float z\n\
+            element face 1\nproperty list uchar double vertex_indices\nend_header\n\
+            0 0 0\n1 0 0\n0 1 0\n";
+        assert!(read_ply(format!("{}3 0 1 2\n", header).as_bytes()).is_ok());
+        for face in &["3 0 1.5 2", "3 0 -1 2", "3 0 1 4294967296", "3 0 1 3"] {
+            let error = read_ply(format!("{}{}\n", header, face).as_bytes())
+                .err()
+                .unwrap();
+            assert!(error.to_string().contains("refers to vertex"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn reports_truncated_files() {
+        let cube = Shape::Cube.primitive(1.0);
+        let mut ply = Vec::new();
+        write_ply(&mut ply, &cube.vertices(), &cube.indices, false).unwrap();
+        let error = read_ply(&ply[..ply.len() - 5]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(error.to_string(), "ply: face 11 of 12: file is truncated");
+        let error = read_ply(&ply[..ply.len() - 12 * 13 - 30]).err().unwrap();
+        assert_eq!(error.to_string(), "ply: vertex 23 of 24: file is truncated");
+
+        let plane = primitives::plane(1.0, 2);
+        let mut stl = Vec::new();
+        write_stl(&mut stl, &plane.vertices(), &plane.indices).unwrap();
+        let error = read_stl(&stl[..400]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(
+            error.to_string(),
+            "stl: the header says 8 triangles (484 bytes) but the file has 400 bytes"
+        );
+        // 先頭が`solid`でもテキストでなければバイナリとして扱う
+        stl[..5].copy_from_slice(b"solid");
+        assert_eq!(read_stl(&stl).unwrap().indices.len(), 24);
+        assert_eq!(
+            read_stl(&stl[..400]).err().unwrap().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert!(read_stl(&stl[..50]).is_err());
+        assert!(read_stl(b"solid x\nfacet normal 0 0 1\n vertex 0 0 0\n").is_err());
+    }
+
+    #[test]
+    fn picks_format_from_extension() {
+        assert_eq!(
+            Format::from_path(Path::new("a/teapot.OBJ")),
+            Some(Format::Obj)
+        );
+        assert_eq!(Format::from_path(Path::new("scan.ply")), Some(Format::Ply));
+        assert_eq!(Format::from_path(Path::new("part.stl")), Some(Format::Stl));
+        assert_eq!(Format::from_path(Path::new("teapot")), None);
+    }
+}
@@ -0,0 +1,379 @@
+use crate::mesh_file::Format;
+use crate::post::ToneMapSettings;
+use crate::primitives::Shape;
+use crate::shadow::ShadowSettings;
+use std::path::PathBuf;
+
+/// コマンドライン引数
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    /// 再生するカメラパスのファイル
+    pub camera_path: Option<PathBuf>,
+    /// ティーポットの周りを回るカメラパスを再生する
+    pub turntable: bool,
+    /// 再生するカメラパスを保存する先
+    pub save_camera_path: Option<PathBuf>,
+    /// 連番のPNGを保存するディレクトリ
+    pub record: Option<PathBuf>,
+    /// 録画するフレーム数 (指定が無ければカメラパスの長さ)
+    pub record_frames: Option<usize>,
+    /// 録画のフレームレート
+    pub record_fps: f32,
+    /// 画面外のテクスチャに描いて録画する大きさ
+    pub offscreen: Option<(u32, u32)>,
+    /// 生のRGBAのフレームを標準入力に流すコマンド
+    pub encoder: Option<String>,
+    /// 目標のフレームレート (垂直同期を使うときは無視する)
+    pub fps: f32,
+    /// 垂直同期に合わせて描く
+    pub vsync: bool,
+    /// 1秒あたりのシミュレーションの更新回数
+    pub tick_rate: f32,
+    /// 毎フレームの時間と描画の数を書き出すCSV
+    pub stats_csv: Option<PathBuf>,
+    pub shadow: ShadowSettings,
+    /// 周りの景色 (6枚の画像のディレクトリかパノラマの画像)
+    pub environment: Option<PathBuf>,
+    pub tone_map: ToneMapSettings,
+    /// 画面に掛けるエフェクトの設定ファイル
+    pub post: Option<PathBuf>,
+    /// マルチサンプルのサンプル数 (1ならしない)
+    pub msaa: u32,
+    /// ティーポットの周りに並べる複製の数
+    pub instances: usize,
+    /// ベジエパッチから作るティーポットの分割数 (指定が無ければteapot.rsを使う)
+    pub teapot_subdivisions: Option<u32>,
+    /// ティーポットの代わりに置く形
+    pub shape: Option<Shape>,
+    /// ティーポットの代わりに置くメッシュのファイル
+    pub mesh: Option<PathBuf>,
+    /// ティーポットを書き出すファイル (書き出したら終わる)
+    pub export: Option<PathBuf>,
+    /// 書き出す細かさの段階 (0なら元のまま)
+    pub export_lod: usize,
+    /// PLYをテキストで書き出す
+    pub export_ascii: bool,
+    /// ティーポットに貼る法線マップ (接線空間)
+    pub normal_map: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            camera_path: None,
+            turntable: false,
+            save_camera_path: None,
+            record: None,
+            record_frames: None,
+            record_fps: 60.0,
+            offscreen: None,
+            encoder: None,
+            fps: 60.0,
+            vsync: false,
+            tick_rate: 120.0,
+            stats_csv: None,
+            shadow: ShadowSettings::default(),
+            environment: None,
+            tone_map: ToneMapSettings::default(),
+            post: None,
+            msaa: 4,
+            instances: 0,
+            teapot_subdivisions: None,
+            shape: None,
+            mesh: None,
+            export: None,
+            export_lod: 0,
+            export_ascii: false,
+            normal_map: None,
+        }
+    }
+}
+
+pub const USAGE: &str = "usage: opengl-by-rust [options]
+  --camera-path <file>        play back a saved camera path
+  --turntable                 play back a turntable around the teapot
+  --save-camera-path <file>   save the played back camera path
+  --record <dir>              save every frame as a numbered PNG and exit
+  --record-frames <n>         number of frames to record
+  --record-fps <fps>          simulated frame rate of the recording (default 60)
+  --offscreen <w>x<h>         record from an offscreen target of this size;
+                              the window is not drawn while recording
+  --encoder <command>         pipe raw RGBA frames to this command;
+                              {width}, {height} and {fps} are substituted
+  --fps <fps>                 target frame rate of the viewer (default 60)
+  --vsync                     draw in sync with the display instead of --fps
+  --tick-rate <hz>            simulation updates per second (default 120)
+  --stats-csv <file>          write frame times and draw counts of every frame
+  --shadow-resolution <n>     width and height of the shadow map (default 2048)
+  --shadow-bias <bias>        depth bias against shadow acne (default 0.002)
+  --environment <path>        skybox: a directory with px, nx, py, ny, pz and nz
+                              images, or an equirectangular panorama (.hdr)
+  --tone-mapping <curve>      clamp, reinhard or aces (default aces)
+  --exposure <stops>          brighten or darken the scene by 2^stops (default 0)
+  --post <file>               post effects to apply, one per line in order
+                              (default: tonemap, fxaa)
+  --msaa <samples>            multisample anti-aliasing: 1, 2, 4, 8 or 16
+                              (default 4)
+  --instances <n>             draw n more teapots around the teapot in one
+                              instanced draw call
+  --teapot-subdivisions <n>   build the teapot from its Bezier patches, each
+                              split into n x n quads
+  --shape <name>              replace the teapot with plane, cube, sphere,
+                              icosphere, cylinder, cone, torus or capsule
+  --mesh <file>               replace the teapot with a mesh from an .obj, .ply
+                              or .stl file
+  --export <file>             write the teapot (or --shape, --mesh) to an .obj,
+                              .ply or .stl file and exit
+  --export-lod <n>            export the n-th simplified level instead (default 0)
+  --export-ascii              write .ply files as text instead of binary
+  --normal-map <image>        tangent-space normal map for the teapot; needs
+                              UVs, so use with --mesh, --shape or
+                              --teapot-subdivisions";
+
+impl Options {
+    pub fn from_args() -> Result<Options, String> {
+        Options::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--camera-path" => options.camera_path = Some(value()?.into()),
+                "--turntable" => options.turntable = true,
+                "--save-camera-path" => options.save_camera_path = Some(value()?.into()),
+                "--record" => options.record = Some(value()?.into()),
+                "--record-frames" => options.record_frames = Some(parse_positive(&arg, &value()?)?),
+                "--record-fps" => options.record_fps = parse_positive_f32(&arg, &value()?)?,
+                "--offscreen" => options.offscreen = Some(parse_size(&arg, &value()?)?),
+                "--encoder" => options.encoder = Some(value()?),
+                "--fps" => options.fps = parse_positive_f32(&arg, &value()?)?,
+                "--vsync" => options.vsync = true,
+                "--tick-rate" => options.tick_rate = parse_positive_f32(&arg, &value()?)?,
+                "--stats-csv" => options.stats_csv = Some(value()?.into()),
+                "--shadow-resolution" => {
+                    options.shadow.resolution = parse_positive(&arg, &value()?)?
+                }
+                "--shadow-bias" => {
+                    options.shadow.bias = parse_finite(&arg, &value()?)?;
+                    if options.shadow.bias < 0.0 {
+                        return Err(format!("{}: must not be negative", arg));
+                    }
+                }
+                "--environment" => options.environment = Some(value()?.into()),
+                "--tone-mapping" => {
+                    options.tone_map.tone_mapping =
+                        value()?.parse().map_err(|e| format!("{}: {}", arg, e))?
+                }
+                "--exposure" => options.tone_map.exposure = parse_finite(&arg, &value()?)?,
+                "--post" => options.post = Some(value()?.into()),
+                "--msaa" => {
+                    options.msaa = parse_number(&arg, &value()?)?;
+                    if !options.msaa.is_power_of_two() || options.msaa > 16 {
+                        return Err(format!("{}: expected 1, 2, 4, 8 or 16", arg));
+                    }
+                }
+                "--instances" => options.instances = parse_number(&arg, &value()?)?,
+                "--teapot-subdivisions" => {
+                    let n = parse_number(&arg, &value()?)?;
+                    if n == 0 {
+                        return Err(format!("{}: must be at least 1", arg));
+                    }
+                    options.teapot_subdivisions = Some(n);
+                }
+                "--shape" => {
+                    options.shape = Some(value()?.parse().map_err(|e| format!("{}: {}", arg, e))?)
+                }
+                "--mesh" => options.mesh = Some(value()?.into()),
+                "--export" => {
+                    let path = PathBuf::from(value()?);
+                    if Format::from_path(&path).is_none() {
+                        return Err(format!("{}: expected an .obj, .ply or .stl file", arg));
+                    }
+                    options.export = Some(path);
+                }
+                "--export-lod" => options.export_lod = parse_number(&arg, &value()?)?,
+                "--export-ascii" => options.export_ascii = true,
+                "--normal-map" => options.normal_map = Some(value()?.into()),
+                "--help" | "-h" => return Err(USAGE.to_string()),
+                _ => return Err(format!("unknown option: {}\n{}", arg, USAGE)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{}: invalid number `{}`", arg, value))
+}
+
+/// 0より大きい数 (NaNも通さない)
+fn parse_positive<T: std::str::FromStr + PartialOrd + Default>(
+    arg: &str,
+    value: &str,
+) -> Result<T, String> {
+    let number: T = parse_number(arg, value)?;
+    if number > T::default() {
+        Ok(number)
+    } else {
+        Err(format!("{}: must be greater than 0, got `{}`", arg, value))
+    }
+}
+
+/// 有限な小数 (NaNや無限大は通さない)
+fn parse_finite(arg: &str, value: &str) -> Result<f32, String> {
+    let number: f32 = parse_number(arg, value)?;
+    if number.is_finite() {
+        Ok(number)
+    } else {
+        Err(format!("{}: must be a finite number, got `{}`", arg, value))
+    }
+}
+
+/// 0より大きい有限な小数
+fn parse_positive_f32(arg: &str, value: &str) -> Result<f32, String> {
+    let number = parse_finite(arg, value)?;
+    if number > 0.0 {
+        Ok(number)
+    } else {
+        Err(format!("{}: must be greater than 0, got `{}`", arg, value))
+    }
+}
+
+/// `1920x1080`のような大きさ
+fn parse_size(arg: &str, value: &str) -> Result<(u32, u32), String> {
+    let mut parts = value.splitn(2, 'x');
+    match (parts.next(), parts.next()) {
+        (Some(w), Some(h)) => Ok((parse_positive(arg, w)?, parse_positive(arg, h)?)),
+        _ => Err(format!(
+            "{}: expected <width>x<height>, got `{}`",
+            arg, value
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post::ToneMapping;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn parses_flags() {
+        let options = parse(&["--turntable", "--save-camera-path", "out.path"]).unwrap();
+        assert!(options.turntable);
+        assert_eq!(options.save_camera_path, Some(PathBuf::from("out.path")));
+        assert!(parse(&["--camera-path"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+
+        let options = parse(&["--record", "out", "--offscreen", "640x480"]).unwrap();
+        assert_eq!(options.offscreen, Some((640, 480)));
+        assert_eq!(options.record_fps, 60.0);
+        assert_eq!(
+            parse(&["--record-frames", "90"]).unwrap().record_frames,
+            Some(90)
+        );
+        assert!(parse(&["--record-frames", "0"]).is_err());
+        assert!(parse(&["--offscreen", "640"]).is_err());
+        assert!(parse(&["--record-fps", "fast"]).is_err());
+        assert!(parse(&["--record-fps", "0"]).is_err());
+        assert!(parse(&["--offscreen", "0x0"]).is_err());
+        assert!(parse(&["--offscreen", "640x0"]).is_err());
+
+        assert_eq!(parse(&["--fps", "30"]).unwrap().fps, 30.0);
+        assert!(parse(&["--fps", "0"]).is_err());
+        assert!(parse(&["--fps", "-60"]).is_err());
+        assert!(parse(&["--fps", "NaN"]).is_err());
+        assert!(parse(&["--fps", "inf"]).is_err());
+        assert!(parse(&["--record-fps", "infinity"]).is_err());
+        assert_eq!(parse(&["--tick-rate", "240"]).unwrap().tick_rate, 240.0);
+        assert!(parse(&["--tick-rate", "0"]).is_err());
+        assert!(parse(&["--tick-rate", "inf"]).is_err());
+
+        let options = parse(&["--stats-csv", "frames.csv"]).unwrap();
+        assert_eq!(options.stats_csv, Some(PathBuf::from("frames.csv")));
+
+        let options = parse(&["--shadow-resolution", "512", "--shadow-bias", "0.01"]).unwrap();
+        assert_eq!(options.shadow.resolution, 512);
+        assert_eq!(options.shadow.bias, 0.01);
+        assert!(parse(&["--shadow-resolution", "0"]).is_err());
+        assert_eq!(parse(&["--shadow-bias", "0"]).unwrap().shadow.bias, 0.0);
+        assert!(parse(&["--shadow-bias", "-0.01"]).is_err());
+        assert!(parse(&["--shadow-bias", "inf"]).is_err());
+        assert!(parse(&["--shadow-bias", "NaN"]).is_err());
+
+        let options = parse(&["--environment", "sky.hdr"]).unwrap();
+        assert_eq!(options.environment, Some(PathBuf::from("sky.hdr")));
+
+        let options = parse(&["--tone-mapping", "reinhard", "--exposure", "-1.5"]).unwrap();
+        assert_eq!(options.tone_map.tone_mapping, ToneMapping::Reinhard);
+        assert_eq!(options.tone_map.exposure, -1.5);
+        assert!(parse(&["--tone-mapping", "filmic"]).is_err());
+        assert!(parse(&["--exposure", "inf"]).is_err());
+        assert!(parse(&["--exposure", "NaN"]).is_err());
+
+        let options = parse(&["--post", "effects.txt"]).unwrap();
+        assert_eq!(options.post, Some(PathBuf::from("effects.txt")));
+
+        assert_eq!(parse(&[]).unwrap().msaa, 4);
+        assert_eq!(parse(&["--msaa", "8"]).unwrap().msaa, 8);
+        assert!(parse(&["--msaa", "3"]).is_err());
+        assert!(parse(&["--msaa", "0"]).is_err());
+
+        assert_eq!(parse(&["--instances", "1000"]).unwrap().instances, 1000);
+
+        assert_eq!(parse(&[]).unwrap().teapot_subdivisions, None);
+        let options = parse(&["--teapot-subdivisions", "16"]).unwrap();
+        assert_eq!(options.teapot_subdivisions, Some(16));
+        assert!(parse(&["--teapot-subdivisions", "0"]).is_err());
+
+        assert_eq!(
+            parse(&["--shape", "capsule"]).unwrap().shape,
+            Some(Shape::Capsule)
+        );
+        assert!(parse(&["--shape", "teapot"]).is_err());
+
+        let options = parse(&["--mesh", "scan.obj"]).unwrap();
+        assert_eq!(options.mesh, Some(PathBuf::from("scan.obj")));
+
+        let options = parse(&["--normal-map", "bumps.png"]).unwrap();
+        assert_eq!(options.normal_map, Some(PathBuf::from("bumps.png")));
+    }
+
+    #[test]
+    /// 書き出しの引数を読めるかのテスト
+    fn parses_export_flags() {
+        let options = parse(&[
+            "--export",
+            "torus.ply",
+            "--export-lod",
+            "2",
+            "--export-ascii",
+        ])
+        .unwrap();
+        assert_eq!(options.export, Some(PathBuf::from("torus.ply")));
+        assert_eq!(options.export_lod, 2);
+        assert!(options.export_ascii);
+
+        let options = parse(&["--export", "teapot.STL"]).unwrap();
+        assert_eq!(options.export, Some(PathBuf::from("teapot.STL")));
+        assert_eq!(options.export_lod, 0);
+        assert!(!options.export_ascii);
+    }
+
+    #[test]
+    /// 書き出しの引数の間違いを弾くかのテスト
+    fn rejects_bad_export_flags() {
+        assert!(parse(&["--export"]).is_err());
+        assert!(parse(&["--export", "teapot.fbx"]).is_err());
+        assert!(parse(&["--export", "teapot"]).is_err());
+        assert!(parse(&["--export-lod", "-1"]).is_err());
+        assert!(parse(&["--export-lod", "two"]).is_err());
+    }
+}
@@ -0,0 +1,13 @@
+#version 140
+
+in vec2 v_tex_coords;
+in vec4 v_color;
+
+out vec4 color;
+
+// 文字を並べたテクスチャ
+uniform sampler2D u_font;
+
+void main() {
+	color = v_color * vec4(1.0, 1.0, 1.0, texture(u_font, v_tex_coords).a);
+}
@@ -0,0 +1,307 @@
+extern crate image;
+use crate::post;
+use crate::stats::FrameStats;
+use glium::Surface;
+
+/// 1文字の大きさ (ドット)
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+/// 文字の間を1ドット空けたアトラスの1マスの大きさ
+const CELL_WIDTH: u32 = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: u32 = GLYPH_HEIGHT + 1;
+/// アトラスには空白(0x20)から0x7fまでを16文字ずつ並べる
+const COLUMNS: u32 = 16;
+const ROWS: u32 = 6;
+/// 全部塗りつぶした文字。四角を描くのに使う
+const BLOCK: char = '\x7f';
+/// 1ドットを何ピクセルで描くか
+const SCALE: f32 = 3.0;
+
+#[derive(Copy, Clone)]
+struct OverlayVertex {
+    position: [f32; 2],
+    tex_coords: [f32; 2],
+    color: [f32; 4],
+}
+
+implement_vertex!(OverlayVertex, position, tex_coords, color);
+
+/// 5x7ドットの文字の各行 (下位5ビットを左から使う)
+///
+/// 英小文字は大文字で描く。無い文字は`?`になる。
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '+' => [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        ':' => [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00],
+        '=' => [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00],
+        'A' => [0x0e, 0x11, 0x11, 0x11, 0x1f, 0x11, 0x11],
+        'B' => [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e],
+        'C' => [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e],
+        'D' => [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c],
+        'E' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f],
+        'F' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10],
+        'G' => [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f],
+        'H' => [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'I' => [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f],
+        'M' => [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'P' => [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10],
+        'Q' => [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d],
+        'R' => [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11],
+        'S' => [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e],
+        'T' => [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a],
+        'X' => [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04],
+        'Z' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f],
+        BLOCK => [0x1f; 7],
+        _ => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+/// アトラスの中の文字の位置 (ピクセル)
+fn cell(c: char) -> (u32, u32) {
+    let index = match c as u32 {
+        code @ 0x20..=0x7f => code - 0x20,
+        _ => '?' as u32 - 0x20,
+    };
+    (index % COLUMNS * CELL_WIDTH, index / COLUMNS * CELL_HEIGHT)
+}
+
+/// 全ての文字を並べた白黒の画像 (文字の部分だけ不透明)
+pub fn font_atlas() -> image::RgbaImage {
+    let mut atlas = image::RgbaImage::new(COLUMNS * CELL_WIDTH, ROWS * CELL_HEIGHT);
+    for code in 0x20..=0x7fu8 {
+        let c = code as char;
+        let (left, top) = cell(c);
+        for (y, bits) in glyph(c).iter().enumerate() {
+            for x in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - x)) != 0 {
+                    atlas.put_pixel(left + x, top + y as u32, image::Rgba([255; 4]));
+                }
+            }
+        }
+    }
+    atlas
+}
+
+/// 画面の左上を原点にして、文字と四角を最後にまとめて描く
+pub struct Overlay {
+    atlas: glium::texture::Texture2d,
+    program: glium::Program,
+    vertices: Vec<OverlayVertex>,
+}
+
+impl Overlay {
+    pub fn new<F: glium::backend::Facade>(facade: &F) -> Overlay {
+        let atlas = font_atlas();
+        let size = atlas.dimensions();
+        // 画像の1行目がテクスチャ座標のv=0になる
+        let raw = glium::texture::RawImage2d::from_raw_rgba(atlas.into_raw(), size);
+        let atlas = glium::texture::Texture2d::new(facade, raw).unwrap();
+
+        // 文字の色は画面に出す色そのもの
+        let program = post::display_program(
+            facade,
+            include_str!("./overlay.vert"),
+            include_str!("./overlay.frag"),
+        );
+
+        Overlay {
+            atlas,
+            program,
+            vertices: Vec::new(),
+        }
+    }
+
+    /// 1行の高さ (ピクセル)
+    pub fn line_height(&self) -> f32 {
+        (CELL_HEIGHT + 2) as f32 * SCALE
+    }
+
+    pub fn text(&mut self, position: (f32, f32), text: &str, color: [f32; 4]) {
+        let mut x = position.0;
+        for c in text.chars() {
+            let (left, top) = cell(c);
+            self.quad(
+                (x, position.1),
+                (GLYPH_WIDTH as f32 * SCALE, GLYPH_HEIGHT as f32 * SCALE),
+                (left, top, GLYPH_WIDTH, GLYPH_HEIGHT),
+                color,
+            );
+            x += CELL_WIDTH as f32 * SCALE;
+        }
+    }
+
+    pub fn rect(&mut self, position: (f32, f32), size: (f32, f32), color: [f32; 4]) {
+        let (left, top) = cell(BLOCK);
+        // 端のドットを避けて、真ん中だけを引き伸ばす
+        self.quad(position, size, (left + 1, top + 1, 1, 1), color);
+    }
+
+    fn quad(
+        &mut self,
+        (x, y): (f32, f32),
+        (width, height): (f32, f32),
+        (left, top, cell_width, cell_height): (u32, u32, u32, u32),
+        color: [f32; 4],
+    ) {
+        let atlas_size = ((COLUMNS * CELL_WIDTH) as f32, (ROWS * CELL_HEIGHT) as f32);
+        let u0 = left as f32 / atlas_size.0;
+        let v0 = top as f32 / atlas_size.1;
+        let u1 = (left + cell_width) as f32 / atlas_size.0;
+        let v1 = (top + cell_height) as f32 / atlas_size.1;
+        let vertex = |px, py, u, v| OverlayVertex {
+            position: [px, py],
+            tex_coords: [u, v],
+            color,
+        };
+        let top_left = vertex(x, y, u0, v0);
+        let top_right = vertex(x + width, y, u1, v0);
+        let bottom_left = vertex(x, y + height, u0, v1);
+        let bottom_right = vertex(x + width, y + height, u1, v1);
+        self.vertices.extend_from_slice(&[
+            top_left,
+            bottom_left,
+            top_right,
+            top_right,
+            bottom_left,
+            bottom_right,
+        ]);
+    }
+
+    /// フレームの統計を左上に並べる
+    pub fn stats(&mut self, stats: &FrameStats) {
+        let summary = match stats.summary() {
+            Some(summary) => summary,
+            None => return,
+        };
+        let counts = stats.last().map(|s| s.counts).unwrap_or_default();
+        let lines = [
+            format!("FPS {:.1} ({:.2} MS)", summary.fps, summary.avg),
+            format!(
+                "MIN {:.2} AVG {:.2} MAX {:.2} MS",
+                summary.min, summary.avg, summary.max
+            ),
+            format!("1% LOW {:.1} FPS", summary.one_percent_low),
+            format!(
+                "DRAW CALLS {} TRIANGLES {}",
+                counts.draw_calls, counts.triangles
+            ),
+        ];
+
+        let margin = 2.0 * SCALE;
+        let width = 34.0 * CELL_WIDTH as f32 * SCALE;
+        let graph_height = 16.0 * SCALE;
+        let height = lines.len() as f32 * self.line_height() + graph_height + 2.0 * margin;
+        self.rect(
+            (0.0, 0.0),
+            (width + 2.0 * margin, height),
+            [0.0, 0.0, 0.0, 0.6],
+        );
+
+        let white = [1.0; 4];
+        for (i, line) in lines.iter().enumerate() {
+            self.text(
+                (margin, margin + i as f32 * self.line_height()),
+                line,
+                white,
+            );
+        }
+
+        // 2ミリ秒ごとのフレーム時間の分布 (一番右は32ミリ秒以上)
+        let histogram = stats.histogram(2.0, 17);
+        let highest = histogram.iter().copied().max().unwrap_or(0).max(1) as f32;
+        let bar_width = width / histogram.len() as f32;
+        let bottom = height - margin;
+        for (i, &count) in histogram.iter().enumerate() {
+            let bar_height = graph_height * count as f32 / highest;
+            self.rect(
+                (margin + i as f32 * bar_width, bottom - bar_height),
+                (bar_width - SCALE, bar_height),
+                [0.3, 1.0, 0.3, 1.0],
+            );
+        }
+    }
+
+    /// 溜めた文字と四角を1回で描いて、空にする
+    pub fn draw<F, S>(&mut self, facade: &F, target: &mut S)
+    where
+        F: glium::backend::Facade,
+        S: Surface,
+    {
+        if self.vertices.is_empty() {
+            return;
+        }
+        let vertices = glium::VertexBuffer::new(facade, &self.vertices).unwrap();
+        self.vertices.clear();
+
+        let (width, height) = target.get_dimensions();
+        let uniforms = uniform! {
+            u_screen: [width as f32, height as f32],
+            u_font: self.atlas.sampled()
+                .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
+                .minify_filter(glium::uniforms::MinifySamplerFilter::Nearest),
+        };
+        let params = glium::DrawParameters {
+            blend: glium::Blend::alpha_blending(),
+            ..Default::default()
+        };
+        target
+            .draw(
+                &vertices,
+                glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+                &self.program,
+                &uniforms,
+                &params,
+            )
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// アトラスの中に文字の形がそのまま入っているかのテスト
+    fn builds_font_atlas() {
+        let atlas = font_atlas();
+        assert_eq!(atlas.dimensions(), (96, 48));
+
+        // `1`の一番下の行は真ん中の3ドット
+        let (left, top) = cell('1');
+        let row: Vec<u8> = (0..GLYPH_WIDTH)
+            .map(|x| atlas.get_pixel(left + x, top + 6).0[3])
+            .collect();
+        assert_eq!(row, vec![0, 255, 255, 255, 0]);
+        // 文字の間は空いている
+        assert_eq!(atlas.get_pixel(left + GLYPH_WIDTH, top).0[3], 0);
+        assert_eq!(glyph('a'), glyph('A'));
+    }
+}
@@ -0,0 +1,19 @@
+#version 140
+
+// 画面の左上を原点にしたピクセル単位の位置
+in vec2 position;
+in vec2 tex_coords;
+in vec4 color;
+
+out vec2 v_tex_coords;
+out vec4 v_color;
+
+// 描き込む先の大きさ
+uniform vec2 u_screen;
+
+void main() {
+	vec2 ndc = position / u_screen * 2.0 - 1.0;
+	gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+	v_tex_coords = tex_coords;
+	v_color = color;
+}
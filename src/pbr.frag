@@ -0,0 +1,86 @@
+#version 140
+// `lit()`と`surface_normal()`、それが使う入力はsurface.glslにある
+
+in vec3 v_view_position;
+// 物の色
+in vec3 v_color;
+
+out vec4 color;
+
+// ビュー空間の光が来る向き
+uniform vec3 u_light;
+// 材質
+uniform float u_metallic;
+uniform float u_roughness;
+// 景色から来る拡散光と、ラフネスごとにぼかした景色
+uniform samplerCube u_irradiance;
+uniform samplerCube u_prefiltered;
+uniform float u_prefiltered_lod;
+// マウスで選択されているか
+uniform bool u_selected;
+// ビュー空間からワールド空間の向きに戻すのに使う
+uniform mat4 view;
+
+const float PI = 3.14159265;
+// 平行光源の明るさ
+const vec3 light_color = vec3(3.0);
+
+// GGXの法線分布
+float distribution_ggx(float n_dot_h, float roughness) {
+	float a = roughness * roughness;
+	float a2 = a * a;
+	float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+	return a2 / (PI * d * d);
+}
+
+// Smithの遮蔽 (Schlick-GGXの近似)
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+	float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+	float g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+	float g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+	return g_v * g_l;
+}
+
+// Schlickのフレネル
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+	return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// 景色の鏡面反射に掛ける係数 (Karisの解析的な近似)
+vec2 environment_brdf(float n_dot_v, float roughness) {
+	const vec4 c0 = vec4(-1.0, -0.0275, -0.572, 0.022);
+	const vec4 c1 = vec4(1.0, 0.0425, 1.04, -0.04);
+	vec4 r = roughness * c0 + c1;
+	float a004 = min(r.x * r.x, exp2(-9.28 * n_dot_v)) * r.x + r.y;
+	return vec2(-1.04, 1.04) * a004 + r.zw;
+}
+
+void main() {
+	vec3 n = surface_normal();
+	vec3 v = normalize(-v_view_position);
+	vec3 l = normalize(u_light);
+	vec3 h = normalize(v + l);
+	float n_dot_v = max(dot(n, v), 1e-4);
+	float n_dot_l = max(dot(n, l), 0.0);
+
+	// 誘電体は4%だけ反射し、金属は元の色で反射する
+	vec3 f0 = mix(vec3(0.04), v_color, u_metallic);
+	vec3 f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+	float d = distribution_ggx(max(dot(n, h), 0.0), u_roughness);
+	float g = geometry_smith(n_dot_v, n_dot_l, u_roughness);
+	vec3 specular = d * g * f / (4.0 * n_dot_v * n_dot_l + 1e-4);
+	vec3 diffuse = (1.0 - f) * (1.0 - u_metallic) * v_color / PI;
+	vec3 direct = (diffuse + specular) * light_color * n_dot_l * lit();
+
+	// 景色はワールド空間の向きで引く
+	mat3 to_world = transpose(mat3(view));
+	vec3 irradiance = texture(u_irradiance, to_world * n).rgb;
+	vec3 prefiltered = textureLod(u_prefiltered, to_world * reflect(-v, n), u_roughness * u_prefiltered_lod).rgb;
+	vec2 brdf = environment_brdf(n_dot_v, u_roughness);
+	vec3 environment_specular = f0 * brdf.x + brdf.y;
+	vec3 ambient = (1.0 - environment_specular) * (1.0 - u_metallic) * v_color * irradiance
+		+ environment_specular * prefiltered;
+
+	vec3 selected_color = u_selected ? vec3(0.3, 0.3, 0.0) : vec3(0.0);
+	color = vec4(direct + ambient + selected_color, 1.0);
+}
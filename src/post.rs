@@ -0,0 +1,487 @@
+use crate::camera::CameraState;
+use glium::{CapabilitiesSource, Surface};
+use std::rc::Rc;
+
+/// 明るい色を画面に収める曲線
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMapping {
+    /// 1を超えたところを切り捨てる
+    Clamp,
+    Reinhard,
+    Aces,
+}
+
+impl ToneMapping {
+    pub fn next(self) -> ToneMapping {
+        match self {
+            ToneMapping::Clamp => ToneMapping::Reinhard,
+            ToneMapping::Reinhard => ToneMapping::Aces,
+            ToneMapping::Aces => ToneMapping::Clamp,
+        }
+    }
+
+    /// tonemap.fragの`u_tone_mapping`
+    fn mode(self) -> i32 {
+        match self {
+            ToneMapping::Clamp => 0,
+            ToneMapping::Reinhard => 1,
+            ToneMapping::Aces => 2,
+        }
+    }
+}
+
+impl std::str::FromStr for ToneMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ToneMapping, String> {
+        match s {
+            "clamp" | "none" => Ok(ToneMapping::Clamp),
+            "reinhard" => Ok(ToneMapping::Reinhard),
+            "aces" => Ok(ToneMapping::Aces),
+            _ => Err(format!("unknown tone mapping `{}`", s)),
+        }
+    }
+}
+
+/// トーンマッピングの設定
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ToneMapSettings {
+    pub tone_mapping: ToneMapping,
+    /// 明るさを2の何乗倍にするか
+    pub exposure: f32,
+}
+
+impl Default for ToneMapSettings {
+    fn default() -> ToneMapSettings {
+        ToneMapSettings {
+            tone_mapping: ToneMapping::Aces,
+            exposure: 0.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct ScreenVertex {
+    position: [f32; 2],
+}
+
+implement_vertex!(ScreenVertex, position);
+
+/// 画面全体を覆う1枚の三角形
+pub fn fullscreen_triangle<F: glium::backend::Facade>(
+    facade: &F,
+) -> glium::VertexBuffer<ScreenVertex> {
+    glium::VertexBuffer::new(
+        facade,
+        &[
+            ScreenVertex {
+                position: [-1.0, -1.0],
+            },
+            ScreenVertex {
+                position: [3.0, -1.0],
+            },
+            ScreenVertex {
+                position: [-1.0, 3.0],
+            },
+        ],
+    )
+    .unwrap()
+}
+
+/// 自分でsRGBにした色を書くプログラム
+///
+/// gliumが`GL_FRAMEBUFFER_SRGB`でもう一度変換しないようにする。
+pub fn display_program<F: glium::backend::Facade>(
+    facade: &F,
+    vertex_shader: &str,
+    fragment_shader: &str,
+) -> glium::Program {
+    glium::Program::new(
+        facade,
+        glium::program::ProgramCreationInput::SourceCode {
+            vertex_shader,
+            tessellation_control_shader: None,
+            tessellation_evaluation_shader: None,
+            geometry_shader: None,
+            fragment_shader,
+            transform_feedback_varyings: None,
+            outputs_srgb: true,
+            uses_point_size: false,
+        },
+    )
+    .unwrap()
+}
+
+/// 画面と同じ大きさの浮動小数点のテクスチャ
+pub fn color_texture<F: glium::backend::Facade>(
+    facade: &F,
+    (width, height): (u32, u32),
+) -> glium::texture::Texture2d {
+    glium::texture::Texture2d::empty_with_format(
+        facade,
+        glium::texture::UncompressedFloatFormat::F16F16F16F16,
+        glium::texture::MipmapsOption::NoMipmap,
+        width,
+        height,
+    )
+    .unwrap()
+}
+
+/// テクスチャを線形補間で読む
+pub fn linear(
+    texture: &glium::texture::Texture2d,
+) -> glium::uniforms::Sampler<'_, glium::texture::Texture2d> {
+    texture
+        .sampled()
+        .magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear)
+        .minify_filter(glium::uniforms::MinifySamplerFilter::Linear)
+        .wrap_function(glium::uniforms::SamplerWrapFunction::Clamp)
+}
+
+/// エフェクトに渡す、1つ前の結果とシーンの情報
+pub struct EffectInput<'a> {
+    /// 1つ前のエフェクトの結果 (最初はシーンそのもの)
+    pub color: &'a glium::texture::Texture2d,
+    /// シーンの深度
+    pub depth: &'a glium::texture::DepthTexture2d,
+    pub perspective: [[f32; 4]; 4],
+    pub tone_map: ToneMapSettings,
+    triangle: &'a glium::VertexBuffer<ScreenVertex>,
+}
+
+impl<'a> EffectInput<'a> {
+    pub fn size(&self) -> (u32, u32) {
+        self.color.dimensions()
+    }
+
+    /// 画面全体を覆う三角形を`program`で描く (頂点シェーダーはfullscreen.vert)
+    pub fn draw_fullscreen<S: Surface, U: glium::uniforms::Uniforms>(
+        &self,
+        target: &mut S,
+        program: &glium::Program,
+        uniforms: &U,
+    ) {
+        target
+            .draw(
+                self.triangle,
+                glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+                program,
+                uniforms,
+                &Default::default(),
+            )
+            .unwrap();
+    }
+}
+
+/// 画面全体に掛けるエフェクト
+///
+/// 自分で作ったエフェクトも`PostProcess::effects`に入れれば順に掛けられる。
+pub trait PostEffect {
+    /// 設定ファイルで使う名前
+    fn name(&self) -> &str;
+
+    /// `input.color`を読んで`output`に描く
+    fn apply(&mut self, input: &EffectInput, output: &mut glium::framebuffer::SimpleFrameBuffer);
+}
+
+/// リニアな明るさをトーンマッピングしてsRGBにする
+///
+/// これより後のエフェクトは、画面に出す色を扱うことになる。
+pub struct ToneMap {
+    program: glium::Program,
+}
+
+impl ToneMap {
+    pub fn new<F: glium::backend::Facade>(facade: &F) -> ToneMap {
+        ToneMap {
+            program: display_program(
+                facade,
+                include_str!("./fullscreen.vert"),
+                include_str!("./tonemap.frag"),
+            ),
+        }
+    }
+}
+
+impl PostEffect for ToneMap {
+    fn name(&self) -> &str {
+        "tonemap"
+    }
+
+    fn apply(&mut self, input: &EffectInput, output: &mut glium::framebuffer::SimpleFrameBuffer) {
+        let uniforms = uniform! {
+            u_input: input.color.sampled()
+                .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
+                .minify_filter(glium::uniforms::MinifySamplerFilter::Nearest),
+            u_exposure: input.tone_map.exposure,
+            u_tone_mapping: input.tone_map.tone_mapping.mode(),
+        };
+        input.draw_fullscreen(output, &self.program, &uniforms);
+    }
+}
+
+/// `requested`以下で、どのフォーマットでも使えるサンプル数
+///
+/// 使えるサンプル数が分からないフォーマット (`None`) は何でも使えるとみなす。
+fn supported_samples(requested: u32, formats: &[Option<&[i32]>]) -> u32 {
+    let mut samples = requested.max(1);
+    while samples > 1
+        && !formats
+            .iter()
+            .all(|list| list.is_none_or(|list| list.contains(&(samples as i32))))
+    {
+        samples /= 2;
+    }
+    samples
+}
+
+/// シーンを描くテクスチャと、エフェクトを交互に書き込む2枚のテクスチャ
+struct Buffers {
+    scene: glium::texture::Texture2d,
+    depth: glium::texture::DepthTexture2d,
+    /// マルチサンプルするときに先に描く色と深度
+    multisample: Option<(
+        glium::texture::Texture2dMultisample,
+        glium::texture::DepthTexture2dMultisample,
+    )>,
+    ping_pong: [glium::texture::Texture2d; 2],
+}
+
+/// シーンをリニアな明るさのまま画面外に描いてから、エフェクトを順に掛けて画面に出す
+pub struct PostProcess {
+    context: Rc<glium::backend::Context>,
+    buffers: Option<Buffers>,
+    triangle: glium::VertexBuffer<ScreenVertex>,
+    copy: glium::Program,
+    resolve: glium::Program,
+    samples: u32,
+    /// 掛ける順に並んだエフェクト
+    pub effects: Vec<Box<dyn PostEffect>>,
+    pub settings: ToneMapSettings,
+    /// エフェクトを掛けずにそのまま出す
+    pub passthrough: bool,
+}
+
+impl PostProcess {
+    /// トーンマッピングだけを掛ける
+    pub fn new<F: glium::backend::Facade>(facade: &F, settings: ToneMapSettings) -> PostProcess {
+        PostProcess {
+            context: facade.get_context().clone(),
+            buffers: None,
+            triangle: fullscreen_triangle(facade),
+            copy: display_program(
+                facade,
+                include_str!("./fullscreen.vert"),
+                include_str!("./copy.frag"),
+            ),
+            resolve: glium::Program::from_source(
+                facade,
+                include_str!("./fullscreen.vert"),
+                include_str!("./resolve.frag"),
+                None,
+            )
+            .unwrap(),
+            samples: 1,
+            effects: vec![Box::new(ToneMap::new(facade))],
+            settings,
+            passthrough: false,
+        }
+    }
+
+    /// シーンを描くときのサンプル数を変える
+    ///
+    /// 使えないときはそれより少ない数にして、実際の数を返す。
+    pub fn set_samples(&mut self, requested: u32) -> u32 {
+        let capabilities = self.context.get_capabilities();
+        let formats = [
+            glium::texture::TextureFormat::UncompressedFloat(
+                glium::texture::UncompressedFloatFormat::F16F16F16F16,
+            ),
+            glium::texture::TextureFormat::DepthFormat(glium::texture::DepthFormat::I24),
+        ];
+        let lists: Vec<_> = formats
+            .iter()
+            .map(|format| {
+                capabilities
+                    .internal_formats_textures
+                    .get(format)
+                    .and_then(|infos| infos.multisamples.as_deref())
+            })
+            .collect();
+        let samples = supported_samples(requested, &lists);
+        if samples != requested {
+            println!(
+                "msaa: {} samples are not supported, using {}",
+                requested, samples
+            );
+        }
+        if samples != self.samples {
+            self.samples = samples;
+            self.buffers = None;
+        }
+        samples
+    }
+
+    /// 今のエフェクトの名前を順に
+    pub fn names(&self) -> Vec<&str> {
+        self.effects.iter().map(|effect| effect.name()).collect()
+    }
+
+    /// `draw`でシーンを描き、`target`の大きさに合わせてエフェクトを掛ける
+    pub fn render<S, D, R>(&mut self, target: &mut S, camera: &CameraState, draw: D) -> R
+    where
+        S: Surface,
+        D: FnOnce(&mut glium::framebuffer::SimpleFrameBuffer) -> R,
+    {
+        let size = target.get_dimensions();
+        // 画面の大きさが変わったときだけ作り直す
+        let resized = match &self.buffers {
+            Some(buffers) => buffers.scene.dimensions() != size,
+            None => true,
+        };
+        if resized {
+            self.buffers = Some(Buffers {
+                scene: color_texture(&self.context, size),
+                depth: glium::texture::DepthTexture2d::empty_with_format(
+                    &self.context,
+                    glium::texture::DepthFormat::I24,
+                    glium::texture::MipmapsOption::NoMipmap,
+                    size.0,
+                    size.1,
+                )
+                .unwrap(),
+                multisample: if self.samples > 1 {
+                    Some((
+                        glium::texture::Texture2dMultisample::empty_with_format(
+                            &self.context,
+                            glium::texture::UncompressedFloatFormat::F16F16F16F16,
+                            glium::texture::MipmapsOption::NoMipmap,
+                            size.0,
+                            size.1,
+                            self.samples,
+                        )
+                        .unwrap(),
+                        glium::texture::DepthTexture2dMultisample::empty_with_format(
+                            &self.context,
+                            glium::texture::DepthFormat::I24,
+                            glium::texture::MipmapsOption::NoMipmap,
+                            size.0,
+                            size.1,
+                            self.samples,
+                        )
+                        .unwrap(),
+                    ))
+                } else {
+                    None
+                },
+                ping_pong: [
+                    color_texture(&self.context, size),
+                    color_texture(&self.context, size),
+                ],
+            });
+        }
+        let buffers = self.buffers.as_ref().unwrap();
+
+        let mut scene = glium::framebuffer::SimpleFrameBuffer::with_depth_buffer(
+            &self.context,
+            &buffers.scene,
+            &buffers.depth,
+        )
+        .unwrap();
+        let result = match &buffers.multisample {
+            Some((color, depth)) => {
+                let mut framebuffer = glium::framebuffer::SimpleFrameBuffer::with_depth_buffer(
+                    &self.context,
+                    color,
+                    depth,
+                )
+                .unwrap();
+                let result = draw(&mut framebuffer);
+                // エフェクトは深度も読むので、色と一緒に描いて1サンプルにする
+                let uniforms = uniform! {
+                    u_color: color,
+                    u_depth: depth,
+                    u_samples: self.samples as i32,
+                };
+                scene
+                    .draw(
+                        &self.triangle,
+                        glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+                        &self.resolve,
+                        &uniforms,
+                        &glium::DrawParameters {
+                            depth: glium::Depth {
+                                test: glium::DepthTest::Overwrite,
+                                write: true,
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        },
+                    )
+                    .unwrap();
+                result
+            }
+            None => draw(&mut scene),
+        };
+
+        let mut color = &buffers.scene;
+        if !self.passthrough {
+            for (i, effect) in self.effects.iter_mut().enumerate() {
+                let output = &buffers.ping_pong[i % 2];
+                let input = EffectInput {
+                    color,
+                    depth: &buffers.depth,
+                    perspective: camera.get_perspective(),
+                    tone_map: self.settings,
+                    triangle: &self.triangle,
+                };
+                let mut framebuffer =
+                    glium::framebuffer::SimpleFrameBuffer::new(&self.context, output).unwrap();
+                effect.apply(&input, &mut framebuffer);
+                color = output;
+            }
+        }
+
+        let uniforms = uniform! {
+            u_input: color.sampled()
+                .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
+                .minify_filter(glium::uniforms::MinifySamplerFilter::Nearest),
+        };
+        target
+            .draw(
+                &self.triangle,
+                glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+                &self.copy,
+                &uniforms,
+                &Default::default(),
+            )
+            .unwrap();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tone_mapping() {
+        assert_eq!("aces".parse(), Ok(ToneMapping::Aces));
+        assert_eq!("none".parse(), Ok(ToneMapping::Clamp));
+        assert!("filmic".parse::<ToneMapping>().is_err());
+        // 順に切り替えると元に戻る
+        let start = ToneMapping::Reinhard;
+        assert_eq!(start.next().next().next(), start);
+    }
+
+    #[test]
+    fn falls_back_to_supported_samples() {
+        let color: &[i32] = &[8, 4, 2];
+        let depth: &[i32] = &[4, 2];
+        assert_eq!(supported_samples(4, &[Some(color), Some(depth)]), 4);
+        assert_eq!(supported_samples(16, &[Some(color), Some(depth)]), 4);
+        assert_eq!(supported_samples(8, &[Some(color), None]), 8);
+        assert_eq!(supported_samples(4, &[Some(&[])]), 1);
+        assert_eq!(supported_samples(1, &[None]), 1);
+    }
+}
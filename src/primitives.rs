@@ -0,0 +1,489 @@
+extern crate cgmath;
+use crate::mesh::MeshVertex;
+use crate::teapot;
+use cgmath::{InnerSpace, Vector3};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+/// 生成した形 (どれも原点が中心で、y軸が上)
+#[derive(Clone)]
+pub struct Primitive {
+    pub positions: Vec<teapot::Vertex>,
+    pub normals: Vec<teapot::Normal>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+impl Primitive {
+    fn new() -> Primitive {
+        Primitive {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            tex_coords: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, position: Vector3<f32>, normal: Vector3<f32>, tex_coords: [f32; 2]) -> u32 {
+        self.positions.push(teapot::Vertex {
+            position: position.into(),
+        });
+        self.normals.push(teapot::Normal {
+            normal: normal.into(),
+        });
+        self.tex_coords.push(tex_coords);
+        self.positions.len() as u32 - 1
+    }
+
+    /// UV付きの頂点
+    pub fn vertices(&self) -> Vec<MeshVertex> {
+        (0..self.positions.len())
+            .map(|i| MeshVertex {
+                tex_coords: self.tex_coords[i],
+                ..MeshVertex::new(
+                    self.positions[i].position.into(),
+                    self.normals[i].normal.into(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// 上を向いた`half_size`四方の板を、縦横`subdivisions`個の四角形に分けたもの
+pub fn plane(half_size: f32, subdivisions: u32) -> Primitive {
+    let n = subdivisions.max(1);
+    let mut plane = Primitive::new();
+    for i in 0..=n {
+        for j in 0..=n {
+            let (u, v) = (i as f32 / n as f32, j as f32 / n as f32);
+            plane.push(
+                Vector3::new(u * 2.0 - 1.0, 0.0, v * 2.0 - 1.0) * half_size,
+                Vector3::unit_y(),
+                [u, v],
+            );
+        }
+    }
+    let index = |i: u32, j: u32| i * (n + 1) + j;
+    for i in 0..n {
+        for j in 0..n {
+            let quad = [
+                index(i, j),
+                index(i, j + 1),
+                index(i + 1, j + 1),
+                index(i + 1, j),
+            ];
+            plane
+                .indices
+                .extend_from_slice(&[quad[0], quad[1], quad[2], quad[0], quad[2], quad[3]]);
+        }
+    }
+    plane
+}
+
+/// 1辺が`half_size`の2倍の立方体 (角がはっきり出るように面ごとに頂点を持つ)
+pub fn cube(half_size: f32) -> Primitive {
+    let mut cube = Primitive::new();
+    let axes = [
+        Vector3::unit_x(),
+        Vector3::unit_y(),
+        Vector3::unit_z(),
+        -Vector3::unit_x(),
+        -Vector3::unit_y(),
+        -Vector3::unit_z(),
+    ];
+    for &normal in &axes {
+        // normal = u x v になるように面の中の2軸を選ぶ
+        let u = Vector3::new(normal.z, normal.x, normal.y);
+        let v = normal.cross(u);
+        let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+        let quad: Vec<u32> = corners
+            .iter()
+            .map(|&(s, t)| {
+                let position = (normal + u * s + v * t) * half_size;
+                cube.push(position, normal, [(s + 1.0) / 2.0, (t + 1.0) / 2.0])
+            })
+            .collect();
+        cube.indices
+            .extend_from_slice(&[quad[0], quad[1], quad[2], quad[0], quad[2], quad[3]]);
+    }
+    cube
+}
+
+/// 回転体の断面の1点
+struct ProfilePoint {
+    /// y軸からの距離
+    radius: f32,
+    y: f32,
+    /// 断面の中での法線 (y軸から離れる向き, y)
+    normal: [f32; 2],
+    v: f32,
+}
+
+/// 上から下へ並んだ断面`profile`をy軸の周りに`segments`等分して回した面
+///
+/// 軸の上の点は1点に集まるので、そこには三角形を1枚ずつ置く。
+/// 続く2点が同じ位置なら (角を作るために法線だけ変えた点)、その間には面を張らない。
+fn lathe(profile: &[ProfilePoint], segments: u32) -> Primitive {
+    let n = segments.max(3);
+    let mut lathe = Primitive::new();
+    for point in profile {
+        for j in 0..=n {
+            // 継ぎ目の頂点が最初と全く同じ位置になるように、角度は一周したら0に戻す
+            let angle = 2.0 * PI * (j % n) as f32 / n as f32;
+            let (sin, cos) = angle.sin_cos();
+            let position = if point.radius == 0.0 {
+                Vector3::new(0.0, point.y, 0.0)
+            } else {
+                Vector3::new(point.radius * sin, point.y, point.radius * cos)
+            };
+            let [nr, ny] = point.normal;
+            let normal = Vector3::new(nr * sin, ny, nr * cos).normalize();
+            lathe.push(position, normal, [j as f32 / n as f32, point.v]);
+        }
+    }
+    let index = |i: usize, j: u32| i as u32 * (n + 1) + j;
+    for (i, pair) in profile.windows(2).enumerate() {
+        if pair[0].radius == pair[1].radius && pair[0].y == pair[1].y {
+            continue;
+        }
+        for j in 0..n {
+            let quad = [
+                index(i, j),
+                index(i + 1, j),
+                index(i + 1, j + 1),
+                index(i, j + 1),
+            ];
+            if pair[0].radius != 0.0 {
+                lathe
+                    .indices
+                    .extend_from_slice(&[quad[0], quad[2], quad[3]]);
+            }
+            if pair[1].radius != 0.0 {
+                lathe
+                    .indices
+                    .extend_from_slice(&[quad[0], quad[1], quad[2]]);
+            }
+        }
+    }
+    lathe
+}
+
+/// 断面の点に上から順に0から1の`v`を振る
+fn profile(points: Vec<(f32, f32, [f32; 2])>) -> Vec<ProfilePoint> {
+    let last = (points.len() - 1) as f32;
+    points
+        .into_iter()
+        .enumerate()
+        .map(|(i, (radius, y, normal))| ProfilePoint {
+            radius,
+            y,
+            normal,
+            v: i as f32 / last,
+        })
+        .collect()
+}
+
+/// 高さ`y`を中心にした半径`radius`の円弧を、上からの角度`from`から`to`まで`steps`等分した点
+fn arc(y: f32, radius: f32, from: f32, to: f32, steps: u32) -> Vec<(f32, f32, [f32; 2])> {
+    (0..=steps)
+        .map(|k| {
+            let angle = from + (to - from) * k as f32 / steps as f32;
+            let (sin, cos) = angle.sin_cos();
+            // 極ではsin(PI)が0にならないので、軸の上に揃える
+            let sin = if angle == 0.0 || angle == PI {
+                0.0
+            } else {
+                sin
+            };
+            (radius * sin, y + radius * cos, [sin, cos])
+        })
+        .collect()
+}
+
+/// 経線`segments`本、緯線で`rings`個の帯に分けた球
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Primitive {
+    lathe(&profile(arc(0.0, radius, 0.0, PI, rings.max(2))), segments)
+}
+
+/// 正二十面体の面を`subdivisions`回4つに分けて膨らませた球
+///
+/// 頂点は面どうしで共有する。UVは方向から球面座標で付けるので、経度0の継ぎ目では繋がらない。
+pub fn icosphere(radius: f32, subdivisions: u32) -> Primitive {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut directions: Vec<Vector3<f32>> = [
+        [-1.0, t, 0.0],
+        [1.0, t, 0.0],
+        [-1.0, -t, 0.0],
+        [1.0, -t, 0.0],
+        [0.0, -1.0, t],
+        [0.0, 1.0, t],
+        [0.0, -1.0, -t],
+        [0.0, 1.0, -t],
+        [t, 0.0, -1.0],
+        [t, 0.0, 1.0],
+        [-t, 0.0, -1.0],
+        [-t, 0.0, 1.0],
+    ]
+    .iter()
+    .map(|&p| Vector3::from(p).normalize())
+    .collect();
+    let mut faces: Vec<[u32; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+    for _ in 0..subdivisions {
+        // 隣の面と同じ中点を使うように、辺ごとに覚えておく
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let d = directions[a as usize] + directions[b as usize];
+                directions.push(d.normalize());
+                directions.len() as u32 - 1
+            })
+        };
+        faces = faces
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+    let mut sphere = Primitive::new();
+    for d in directions {
+        let u = 0.5 + d.x.atan2(d.z) / (2.0 * PI);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+        sphere.push(d * radius, d, [u, v]);
+    }
+    sphere.indices = faces.concat();
+    sphere
+}
+
+/// 高さ`height`で、上下に蓋のある円柱
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> Primitive {
+    let (top, bottom) = (height / 2.0, -height / 2.0);
+    let points = vec![
+        (0.0, top, [0.0, 1.0]),
+        (radius, top, [0.0, 1.0]),
+        (radius, top, [1.0, 0.0]),
+        (radius, bottom, [1.0, 0.0]),
+        (radius, bottom, [0.0, -1.0]),
+        (0.0, bottom, [0.0, -1.0]),
+    ];
+    lathe(&profile(points), segments)
+}
+
+/// 高さ`height`で、底に蓋のある円錐
+pub fn cone(radius: f32, height: f32, segments: u32) -> Primitive {
+    let (top, bottom) = (height / 2.0, -height / 2.0);
+    // 側面の法線は斜面に垂直
+    let side = [height, radius];
+    let points = vec![
+        (0.0, top, side),
+        (radius, bottom, side),
+        (radius, bottom, [0.0, -1.0]),
+        (0.0, bottom, [0.0, -1.0]),
+    ];
+    lathe(&profile(points), segments)
+}
+
+/// y軸の周りを半径`major`で回る、太さの半径が`minor`の輪
+pub fn torus(major: f32, minor: f32, segments: u32, sides: u32) -> Primitive {
+    let sides = sides.max(3);
+    // 上から外側を通って一周する (最後の点は最初と全く同じにする)
+    let points = (0..=sides)
+        .map(|k| {
+            let angle = PI / 2.0 - 2.0 * PI * (k % sides) as f32 / sides as f32;
+            let (sin, cos) = angle.sin_cos();
+            (major + minor * cos, minor * sin, [cos, sin])
+        })
+        .collect();
+    lathe(&profile(points), segments)
+}
+
+/// 高さ`height`の円柱の上下に半球を付けたもの (全体の高さは`height + 2 * radius`)
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Primitive {
+    let rings = rings.max(1);
+    let mut points = arc(height / 2.0, radius, 0.0, PI / 2.0, rings);
+    points.extend(arc(-height / 2.0, radius, PI / 2.0, PI, rings));
+    lathe(&profile(points), segments)
+}
+
+/// `--shape`で選べる形
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Shape {
+    Plane,
+    Cube,
+    UvSphere,
+    Icosphere,
+    Cylinder,
+    Cone,
+    Torus,
+    Capsule,
+}
+
+impl std::str::FromStr for Shape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Shape, String> {
+        match s {
+            "plane" => Ok(Shape::Plane),
+            "cube" => Ok(Shape::Cube),
+            "sphere" | "uv-sphere" => Ok(Shape::UvSphere),
+            "icosphere" => Ok(Shape::Icosphere),
+            "cylinder" => Ok(Shape::Cylinder),
+            "cone" => Ok(Shape::Cone),
+            "torus" => Ok(Shape::Torus),
+            "capsule" => Ok(Shape::Capsule),
+            _ => Err(format!("unknown shape `{}`", s)),
+        }
+    }
+}
+
+impl Shape {
+    /// 半径や半分の大きさが`size`くらいの形
+    pub fn primitive(self, size: f32) -> Primitive {
+        match self {
+            Shape::Plane => plane(size, 8),
+            Shape::Cube => cube(size),
+            Shape::UvSphere => uv_sphere(size, 32, 16),
+            Shape::Icosphere => icosphere(size, 3),
+            Shape::Cylinder => cylinder(size, size * 2.0, 32),
+            Shape::Cone => cone(size, size * 2.0, 32),
+            Shape::Torus => torus(size * 0.7, size * 0.3, 48, 24),
+            Shape::Capsule => capsule(size * 0.6, size, 32, 8),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(primitive: &Primitive, i: u32) -> Vector3<f32> {
+        primitive.positions[i as usize].position.into()
+    }
+
+    /// 位置が同じ頂点をまとめると、どの辺もちょうど2枚の三角形に逆向きで使われているか
+    fn assert_watertight(primitive: &Primitive) {
+        let mut welded = HashMap::new();
+        let mut weld = |i: u32| {
+            let p = primitive.positions[i as usize].position;
+            let key = [p.0.to_bits(), p.1.to_bits(), p.2.to_bits()];
+            let next = welded.len();
+            *welded.entry(key).or_insert(next)
+        };
+        let mut edges = HashMap::new();
+        for t in primitive.indices.chunks(3) {
+            let t = [weld(t[0]), weld(t[1]), weld(t[2])];
+            for k in 0..3 {
+                *edges.entry((t[k], t[(k + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "edge {:?} is used {} times", (a, b), count);
+            assert_eq!(edges.get(&(b, a)), Some(&1), "edge {:?} is open", (a, b));
+        }
+    }
+
+    /// 三角形の向きと頂点の法線が揃っているか調べて、囲まれた体積を返す
+    fn assert_outward(primitive: &Primitive) -> f32 {
+        assert_eq!(primitive.positions.len(), primitive.normals.len());
+        assert_eq!(primitive.positions.len(), primitive.tex_coords.len());
+        let mut volume = 0.0;
+        for t in primitive.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| position(primitive, t[k]));
+            let face = (b - a).cross(c - a);
+            assert!(face.magnitude() > 0.0, "degenerate triangle {:?}", t);
+            for &i in t {
+                let normal = Vector3::from(primitive.normals[i as usize].normal);
+                assert!((normal.magnitude() - 1.0).abs() < 1e-4);
+                assert!(face.normalize().dot(normal) > 0.0, "triangle {:?}", t);
+            }
+            volume += a.dot(b.cross(c)) / 6.0;
+        }
+        for uv in &primitive.tex_coords {
+            assert!(uv.iter().all(|&t| (0.0..=1.0).contains(&t)));
+        }
+        volume
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() < expected * tolerance,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn plane_faces_up() {
+        let plane = plane(2.0, 3);
+        assert_eq!(plane.positions.len(), 16);
+        assert_eq!(plane.indices.len(), 3 * 3 * 6);
+        assert_eq!(assert_outward(&plane), 0.0);
+        for t in plane.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| position(&plane, t[k]));
+            assert!((b - a).cross(c - a).y > 0.0);
+        }
+    }
+
+    #[test]
+    fn closed_shapes_are_watertight() {
+        let shapes = [
+            (cube(1.0), 8.0),
+            (uv_sphere(1.0, 64, 32), 4.0 / 3.0 * PI),
+            (icosphere(1.0, 4), 4.0 / 3.0 * PI),
+            (cylinder(1.0, 2.0, 64), 2.0 * PI),
+            (cone(1.0, 3.0, 64), PI),
+            (torus(2.0, 0.5, 64, 32), 2.0 * PI * PI * 2.0 * 0.25),
+            (capsule(1.0, 2.0, 64, 16), 2.0 * PI + 4.0 / 3.0 * PI),
+        ];
+        for (shape, expected) in &shapes {
+            assert_watertight(shape);
+            // 外を向いていれば体積は正で、本来の形に近い
+            assert_close(assert_outward(shape), *expected, 0.02);
+        }
+    }
+
+    #[test]
+    fn icosphere_shares_vertices() {
+        let sphere = icosphere(2.0, 2);
+        assert_eq!(sphere.positions.len(), 162);
+        assert_eq!(sphere.indices.len(), 320 * 3);
+        for (p, n) in sphere.positions.iter().zip(&sphere.normals) {
+            let p = Vector3::from(p.position);
+            assert!((p.magnitude() - 2.0).abs() < 1e-5);
+            assert!((p / 2.0 - Vector3::from(n.normal)).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn parses_shapes() {
+        assert_eq!("torus".parse(), Ok(Shape::Torus));
+        assert_eq!("sphere".parse(), Ok(Shape::UvSphere));
+        assert!("teapot".parse::<Shape>().is_err());
+        let cube = Shape::Cube.primitive(1.0);
+        assert_eq!(cube.indices.len(), 36);
+        assert_eq!(cube.positions.len(), 24);
+    }
+}
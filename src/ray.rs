@@ -0,0 +1,152 @@
+extern crate cgmath;
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+
+/// 半直線 `origin + t * direction`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+/// レイと三角形の交差結果
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hit {
+    /// レイのパラメータ
+    pub t: f32,
+    /// 交点
+    pub point: Vector3<f32>,
+    /// `INDICES`の中で何番目の三角形か
+    pub triangle: usize,
+    /// 重心座標 (u, v)
+    pub barycentric: (f32, f32),
+}
+
+impl Ray {
+    pub fn new(origin: Vector3<f32>, direction: Vector3<f32>) -> Ray {
+        Ray {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Vector3<f32> {
+        self.origin + self.direction * t
+    }
+
+    /// スクリーン座標(左上が原点のピクセル)からワールド空間のレイを作る
+    ///
+    /// `view`と`perspective`は`CameraState`が返す行列をそのまま渡す。
+    pub fn from_screen(
+        cursor: (f64, f64),
+        size: (u32, u32),
+        view: [[f32; 4]; 4],
+        perspective: [[f32; 4]; 4],
+    ) -> Option<Ray> {
+        if size.0 == 0 || size.1 == 0 {
+            return None;
+        }
+        let x = (2.0 * cursor.0 / size.0 as f64 - 1.0) as f32;
+        let y = (1.0 - 2.0 * cursor.1 / size.1 as f64) as f32;
+
+        let view_projection = Matrix4::from(perspective) * Matrix4::from(view);
+        let inverse = view_projection.invert()?;
+
+        // NDCのnear面とfar面の点をワールド空間に戻す
+        let unproject = |z: f32| {
+            let p = inverse * Vector4::new(x, y, z, 1.0);
+            p.truncate() / p.w
+        };
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+
+        Some(Ray::new(near, far - near))
+    }
+
+    /// 行列でレイを変換する
+    ///
+    /// 方向ベクトルは正規化しないので、変換後の`t`は変換前と同じ点を指す。
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Ray {
+        Ray {
+            origin: (matrix * self.origin.extend(1.0)).truncate(),
+            direction: (matrix * self.direction.extend(0.0)).truncate(),
+        }
+    }
+
+    /// Möller–Trumboreの方法でレイと三角形の交差を調べる
+    ///
+    /// 両面とも判定し、交差すれば`(t, u, v)`を返す。
+    pub fn intersect_triangle(
+        &self,
+        a: Vector3<f32>,
+        b: Vector3<f32>,
+        c: Vector3<f32>,
+    ) -> Option<(f32, f32, f32)> {
+        const EPSILON: f32 = 1e-7;
+
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = self.direction.cross(edge2);
+        let det = edge1.dot(p);
+        if det.abs() < EPSILON {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let s = self.origin - a;
+        let u = s.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(edge1);
+        let v = self.direction.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = edge2.dot(q) * inv_det;
+        if t < 0.0 {
+            return None;
+        }
+        Some((t, u, v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// 画面中央のレイがカメラの向きと一致するかのテスト
+    fn screen_center_ray_points_forward() {
+        let mut camera = crate::camera::CameraState::new();
+        camera.set_position((1.0, 2.0, 3.0));
+        camera.set_direction((0.0, 0.0, 1.0));
+        let ray = Ray::from_screen(
+            (400.0, 300.0),
+            (800, 600),
+            camera.get_view(),
+            camera.get_perspective(),
+        )
+        .unwrap();
+        assert!((ray.direction - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-4);
+        assert!((ray.origin - Vector3::new(1.0, 2.0, 3.1)).magnitude() < 1e-3);
+    }
+
+    #[test]
+    fn intersects_triangle() {
+        let ray = Ray::new(Vector3::new(0.2, 0.2, -1.0), Vector3::new(0.0, 0.0, 1.0));
+        let a = Vector3::new(0.0, 0.0, 0.0);
+        let b = Vector3::new(1.0, 0.0, 0.0);
+        let c = Vector3::new(0.0, 1.0, 0.0);
+        let (t, u, v) = ray.intersect_triangle(a, b, c).unwrap();
+        assert!((t - 1.0).abs() < 1e-6);
+        assert!((u - 0.2).abs() < 1e-6);
+        assert!((v - 0.2).abs() < 1e-6);
+
+        let miss = Ray::new(Vector3::new(0.8, 0.8, -1.0), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(miss.intersect_triangle(a, b, c), None);
+        let behind = Ray::new(Vector3::new(0.2, 0.2, 1.0), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(behind.intersect_triangle(a, b, c), None);
+    }
+}
@@ -0,0 +1,22 @@
+#version 150
+
+in vec2 v_tex_coords;
+
+out vec4 color;
+
+uniform sampler2DMS u_color;
+uniform sampler2DMS u_depth;
+uniform int u_samples;
+
+// マルチサンプルの色を平均し、深度は一番手前を残す
+void main() {
+	ivec2 p = ivec2(gl_FragCoord.xy);
+	vec3 sum = vec3(0.0);
+	float depth = 1.0;
+	for (int i = 0; i < u_samples; i++) {
+		sum += texelFetch(u_color, p, i).rgb;
+		depth = min(depth, texelFetch(u_depth, p, i).r);
+	}
+	color = vec4(sum / float(u_samples), 1.0);
+	gl_FragDepth = depth;
+}
@@ -0,0 +1,763 @@
+extern crate cgmath;
+use crate::bvh::Aabb;
+use crate::camera::{self, CameraState};
+use crate::debug_draw::{DebugDraw, DebugLines, LineVertex};
+use crate::environment::{Environment, ImageBasedLight, Skybox};
+use crate::instancing::Instance;
+use crate::lod;
+use crate::material::{self, Material};
+use crate::mesh::{self, Attributes, Indices, MeshVertex};
+use crate::primitives;
+use crate::shadow::{self, ShadowMap, ShadowSettings};
+use crate::stats::DrawCounts;
+use crate::teapot;
+use cgmath::{InnerSpace, Matrix4, Vector3};
+use glium::Surface;
+use std::rc::Rc;
+
+/// 法線の線の長さ (モデル空間)
+const NORMAL_LENGTH: f32 = 4.0;
+/// 深度の表示で真っ黒になる距離
+const DEPTH_DISTANCE: f32 = 4.0;
+/// ワールド空間の光が来る向きの初期値
+pub const DEFAULT_LIGHT: [f32; 3] = [-1.0, 0.4, 0.9];
+/// 元のティーポットも含めた細かさの段階の数
+const LOD_LEVELS: usize = 4;
+/// 粗い段階に切り替えてもよい、画面上のずれのピクセル数
+const LOD_PIXEL_ERROR: f32 = 1.0;
+/// main.vertとinstanced.vertが読む属性
+const ATTRIBUTES: Attributes = Attributes {
+    normal: true,
+    tex_coords: false,
+    tangent: false,
+    color: false,
+};
+/// 法線マップを使うときに、さらに読む属性
+const NORMAL_MAPPED_ATTRIBUTES: Attributes = Attributes {
+    normal: true,
+    tex_coords: true,
+    tangent: true,
+    color: false,
+};
+
+/// main.fragとpbr.fragの頭に足す、共通の関数
+const SURFACE_SHADER: &str = include_str!("./surface.glsl");
+
+/// `#version`の行のすぐ後に`#define`と`header`を足したシェーダー
+fn with_defines(source: &str, defines: &[&str], header: &str) -> String {
+    let (version, rest) = source.split_at(source.find('\n').map_or(source.len(), |i| i + 1));
+    let defines: String = defines.iter().map(|d| format!("#define {}\n", d)).collect();
+    format!("{}{}{}{}", version, defines, header, rest)
+}
+
+/// 同じ頂点シェーダーで、main.fragとpbr.fragを使うプログラム
+struct Programs {
+    lit: glium::Program,
+    /// `Material::Pbr`の物を描くプログラム
+    pbr: glium::Program,
+}
+
+impl Programs {
+    fn new<F: glium::backend::Facade>(
+        facade: &F,
+        vertex_shader: &str,
+        defines: &[&str],
+    ) -> Programs {
+        let program = |fragment_shader: &str| {
+            glium::Program::from_source(
+                facade,
+                &with_defines(vertex_shader, defines, ""),
+                &with_defines(fragment_shader, defines, SURFACE_SHADER),
+                None,
+            )
+            .unwrap()
+        };
+        Programs {
+            lit: program(include_str!("./main.frag")),
+            pbr: program(include_str!("./pbr.frag")),
+        }
+    }
+
+    /// デバッグ用の塗り方はmain.fragにしかない
+    fn get(&self, material: &Material, mode: i32) -> &glium::Program {
+        if material.is_pbr() && mode == 0 {
+            &self.pbr
+        } else {
+            &self.lit
+        }
+    }
+}
+
+/// 三角形の塗り方
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Shading {
+    /// 普通に光を当てる
+    Lit,
+    /// モデル空間の法線をそのまま色にする
+    Normals,
+    /// 深度バッファの値を距離に戻して、近いほど白くする
+    Depth,
+}
+
+impl Shading {
+    pub fn next(self) -> Shading {
+        match self {
+            Shading::Lit => Shading::Normals,
+            Shading::Normals => Shading::Depth,
+            Shading::Depth => Shading::Lit,
+        }
+    }
+
+    /// main.fragの`u_mode`
+    fn mode(self) -> i32 {
+        match self {
+            Shading::Lit => 0,
+            Shading::Normals => 1,
+            Shading::Depth => 2,
+        }
+    }
+}
+
+/// 実行中に切り替えられるデバッグ用の表示
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DebugView {
+    pub shading: Shading,
+    /// 三角形の辺を重ねて描く
+    pub wireframe: bool,
+    /// 頂点から法線の向きに線を描く
+    pub normals: bool,
+}
+
+impl Default for DebugView {
+    fn default() -> DebugView {
+        DebugView {
+            shading: Shading::Lit,
+            wireframe: false,
+            normals: false,
+        }
+    }
+}
+
+/// `teapot::NORMALS`の各頂点から法線の向きに伸ばした線
+///
+/// 長さが1でない法線は、向きだけ揃えて赤く描く。
+pub fn normal_lines(length: f32) -> Vec<LineVertex> {
+    mesh_normal_lines(
+        &MeshVertex::from_arrays(&teapot::VERTICES, &teapot::NORMALS),
+        length,
+    )
+}
+
+/// `normal_lines`の、任意の頂点の版
+fn mesh_normal_lines(vertices: &[MeshVertex], length: f32) -> Vec<LineVertex> {
+    let mut lines = Vec::with_capacity(vertices.len() * 2);
+    for vertex in vertices {
+        let p = vertex.position;
+        let n = vertex.normal;
+        let norm = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+        let color = if (norm - 1.0).abs() < 1e-3 {
+            [1.0, 1.0, 0.0]
+        } else {
+            [1.0, 0.0, 0.0]
+        };
+        let scale = if norm > 0.0 { length / norm } else { 0.0 };
+        lines.push(LineVertex { position: p, color });
+        lines.push(LineVertex {
+            position: [
+                p[0] + n[0] * scale,
+                p[1] + n[1] * scale,
+                p[2] + n[2] * scale,
+            ],
+            color,
+        });
+    }
+    lines
+}
+
+/// モデル行列の拡大率
+fn model_scale(model: [[f32; 4]; 4]) -> f32 {
+    Vector3::new(model[0][0], model[0][1], model[0][2]).magnitude()
+}
+
+/// 影を受ける地面
+struct Ground {
+    vertices: glium::vertex::VertexBufferAny,
+    indices: glium::index::IndexBufferAny,
+    triangles: u32,
+    /// 原点にある板を地面の位置に動かす行列
+    model: [[f32; 4]; 4],
+    bounds: Aabb,
+}
+
+/// ティーポットの細かさの段階の1つ
+struct LodMesh {
+    vertices: glium::vertex::VertexBufferAny,
+    /// 頂点の数に合わせて16ビットか32ビット
+    indices: glium::index::IndexBufferAny,
+    triangles: u32,
+    /// 元の形からずれた距離 (ワールド空間)
+    error: f32,
+    /// 接線があって、法線マップを使って描くか
+    normal_mapped: bool,
+}
+
+impl LodMesh {
+    fn new<F: glium::backend::Facade>(
+        facade: &F,
+        vertices: &[MeshVertex],
+        indices: &[u32],
+        error: f32,
+    ) -> LodMesh {
+        let normal_mapped = vertices.iter().any(|v| v.tangent != [0.0; 4]);
+        let attributes = if normal_mapped {
+            NORMAL_MAPPED_ATTRIBUTES
+        } else {
+            ATTRIBUTES
+        };
+        LodMesh {
+            vertices: mesh::vertex_buffer(facade, vertices, attributes),
+            indices: Indices::new(indices).index_buffer(facade),
+            triangles: indices.len() as u32 / 3,
+            error,
+            normal_mapped,
+        }
+    }
+
+    /// `mesh`を簡略化した段階 (先頭の`mesh`そのものは含めない)
+    ///
+    /// ずれはモデルの拡大率`scale`を掛けてワールド空間にする。
+    /// 簡略化した段階にはUVが無いので、法線マップは使わない。
+    fn coarser<F: glium::backend::Facade>(
+        facade: &F,
+        mesh: &lod::Mesh,
+        scale: f32,
+    ) -> Vec<LodMesh> {
+        lod::lod_chain(mesh, LOD_LEVELS)
+            .iter()
+            .skip(1)
+            .map(|level| {
+                LodMesh::new(
+                    facade,
+                    &level.mesh.vertices(),
+                    &level.mesh.indices,
+                    level.error * scale,
+                )
+            })
+            .collect()
+    }
+}
+
+/// モデル空間とワールド空間での`vertices`の範囲
+fn model_bounds(vertices: &[MeshVertex], model: [[f32; 4]; 4]) -> (Aabb, Aabb) {
+    let model = Matrix4::from(model);
+    let mut model_bounds = Aabb::empty();
+    let mut bounds = Aabb::empty();
+    for vertex in vertices {
+        let p = Vector3::from(vertex.position);
+        model_bounds.grow(p);
+        bounds.grow((model * p.extend(1.0)).truncate());
+    }
+    (model_bounds, bounds)
+}
+
+/// ティーポットと一緒に描く複製
+///
+/// 毎フレーム、細かさの段階ごとに分けてからまとめて描く。
+struct Instances {
+    instances: Vec<Instance>,
+    bounds: Aabb,
+}
+
+/// 描画するティーポットとシェーダー
+pub struct Scene {
+    context: Rc<glium::backend::Context>,
+    /// 細かい順に並んだティーポット (先頭は元のまま)
+    lods: Vec<LodMesh>,
+    programs: Programs,
+    /// インスタンスごとの行列と色で描くプログラム
+    instanced_programs: Programs,
+    /// 接線のあるメッシュを法線マップで描くプログラム
+    normal_mapped_programs: Programs,
+    instanced_normal_mapped_programs: Programs,
+    /// 接線空間の法線マップ (指定が無ければ平らなもの)
+    normal_map: glium::texture::Texture2d,
+    normal_lines: glium::VertexBuffer<LineVertex>,
+    debug_draw: DebugDraw,
+    shadow_map: ShadowMap,
+    skybox: Skybox,
+    ibl: ImageBasedLight,
+    ground: Option<Ground>,
+    instances: Option<Instances>,
+    /// モデル空間のティーポットの範囲
+    model_bounds: Aabb,
+    /// ワールド空間のティーポットの範囲
+    bounds: Aabb,
+    /// 頂点位置を変更するための行列
+    pub model: [[f32; 4]; 4],
+    /// マウスで選択されているか
+    pub selected: bool,
+    pub debug: DebugView,
+    /// 毎フレーム描く補助線 (ワールド空間)
+    pub lines: DebugLines,
+    /// ワールド空間の光が来る向き
+    pub light: Vector3<f32>,
+    pub teapot_material: Material,
+    pub ground_material: Material,
+    /// 細かさの段階を固定する (Noneなら画面上の大きさで選ぶ)
+    pub lod: Option<usize>,
+}
+
+impl Scene {
+    pub fn new<F: glium::backend::Facade>(
+        facade: &F,
+        model: [[f32; 4]; 4],
+        shadow: &ShadowSettings,
+        environment: &Environment,
+    ) -> Scene {
+        let vertices = MeshVertex::from_arrays(&teapot::VERTICES, &teapot::NORMALS);
+        let indices: Vec<u32> = teapot::INDICES.iter().map(|&i| i as u32).collect();
+        let mut lods = vec![LodMesh::new(facade, &vertices, &indices, 0.0)];
+        lods.extend(LodMesh::coarser(
+            facade,
+            &lod::Mesh::teapot(),
+            model_scale(model),
+        ));
+
+        // main.vertとinstanced.vertを読み込む
+        let vertex_shader_src = include_str!("./main.vert");
+        let instanced_shader_src = include_str!("./instanced.vert");
+        let programs = Programs::new(facade, vertex_shader_src, &[]);
+        let instanced_programs = Programs::new(facade, instanced_shader_src, &[]);
+        let normal_mapped_programs = Programs::new(facade, vertex_shader_src, &["NORMAL_MAP"]);
+        let instanced_normal_mapped_programs =
+            Programs::new(facade, instanced_shader_src, &["NORMAL_MAP"]);
+        // 接線空間でまっすぐ上を向いた法線
+        let flat = glium::texture::RawImage2d::from_raw_rgba(vec![128u8, 128, 255, 255], (1, 1));
+        let normal_map = glium::texture::Texture2d::new(facade, flat).unwrap();
+
+        let normal_lines = glium::VertexBuffer::new(facade, &normal_lines(NORMAL_LENGTH)).unwrap();
+        let debug_draw = DebugDraw::new(facade);
+        let shadow_map = ShadowMap::new(facade, shadow);
+        let skybox = Skybox::new(facade, environment);
+        let ibl = ImageBasedLight::new(facade, environment);
+
+        // 先頭のダミーの頂点は三角形に使われていないので含めない
+        let (model_bounds, bounds) = model_bounds(&vertices[1..], model);
+
+        Scene {
+            context: facade.get_context().clone(),
+            lods,
+            programs,
+            instanced_programs,
+            normal_mapped_programs,
+            instanced_normal_mapped_programs,
+            normal_map,
+            normal_lines,
+            debug_draw,
+            shadow_map,
+            skybox,
+            ibl,
+            ground: None,
+            instances: None,
+            model_bounds,
+            bounds,
+            model,
+            selected: false,
+            debug: DebugView::default(),
+            lines: DebugLines::new(),
+            light: DEFAULT_LIGHT.into(),
+            teapot_material: material::TEAPOT_MATERIALS[0],
+            ground_material: material::GROUND,
+            lod: None,
+        }
+    }
+
+    /// ワールド空間のティーポットの範囲
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    /// ティーポットの底の高さに、`half_size`だけ広がった地面を置く
+    pub fn set_ground(&mut self, half_size: f32) {
+        let center = self.bounds.center();
+        let y = self.bounds.min.y;
+        let plane = primitives::plane(half_size, 1);
+        self.ground = Some(Ground {
+            vertices: mesh::vertex_buffer(&self.context, &plane.vertices(), ATTRIBUTES),
+            indices: Indices::new(&plane.indices).index_buffer(&self.context),
+            triangles: plane.indices.len() as u32 / 3,
+            model: Matrix4::from_translation(Vector3::new(center.x, y, center.z)).into(),
+            bounds: Aabb {
+                min: Vector3::new(center.x - half_size, y, center.z - half_size),
+                max: Vector3::new(center.x + half_size, y, center.z + half_size),
+            },
+        });
+    }
+
+    /// ティーポットと一緒に描く複製を置き換える (空なら描かない)
+    pub fn set_instances(&mut self, instances: &[Instance]) {
+        if instances.is_empty() {
+            self.instances = None;
+            return;
+        }
+        let mut bounds = Aabb::empty();
+        for instance in instances {
+            bounds = bounds.union(&instance.bounds(&self.model_bounds));
+        }
+        self.instances = Some(Instances {
+            instances: instances.to_vec(),
+            bounds,
+        });
+    }
+
+    /// ティーポットを`vertices`と`indices`の三角形に置き換える
+    ///
+    /// 粗い段階は、同じ位置の頂点をまとめてから作り直す。
+    /// 接線のある頂点なら、一番細かい段階は法線マップを使って描く。
+    pub fn set_teapot(&mut self, vertices: &[MeshVertex], indices: &[u32]) {
+        let positions: Vec<[f32; 3]> = vertices.iter().map(|v| v.position).collect();
+        let normals: Vec<[f32; 3]> = vertices.iter().map(|v| v.normal).collect();
+        let mesh = lod::Mesh::welded(&positions, &normals, indices);
+        self.lods = vec![LodMesh::new(&self.context, vertices, indices, 0.0)];
+        self.lods.extend(LodMesh::coarser(
+            &self.context,
+            &mesh,
+            model_scale(self.model),
+        ));
+        self.normal_lines =
+            glium::VertexBuffer::new(&self.context, &mesh_normal_lines(vertices, NORMAL_LENGTH))
+                .unwrap();
+        let (model_bounds, bounds) = model_bounds(vertices, self.model);
+        self.model_bounds = model_bounds;
+        self.bounds = bounds;
+        // 複製の範囲もモデル空間の範囲から決まる
+        if let Some(instances) = self.instances.take() {
+            self.set_instances(&instances.instances);
+        }
+    }
+
+    /// 法線マップを置き換える
+    ///
+    /// 色ではなく向きなので、sRGBとして読まずにそのままの値を使う。
+    /// 1行目を画像の上端とし、glTFと同じくUVの(0, 0)が左上になる。
+    pub fn set_normal_map(&mut self, image: &image::RgbaImage) {
+        let raw =
+            glium::texture::RawImage2d::from_raw_rgba(image.as_raw().clone(), image.dimensions());
+        self.normal_map = glium::texture::Texture2d::new(&self.context, raw).unwrap();
+    }
+
+    /// 細かさの段階の数
+    pub fn lod_levels(&self) -> usize {
+        self.lods.len()
+    }
+
+    /// ワールド空間の`center`にあるティーポットを描く段階
+    fn lod_level(&self, center: Vector3<f32>, camera: &CameraState, viewport_height: u32) -> usize {
+        if let Some(level) = self.lod {
+            return level.min(self.lods.len() - 1);
+        }
+        let distance = (Vector3::from(camera.get_position()) - center).magnitude();
+        let pixels_per_unit =
+            lod::pixels_per_unit(&camera.get_perspective(), viewport_height, distance);
+        let errors: Vec<f32> = self.lods.iter().map(|mesh| mesh.error).collect();
+        lod::select(&errors, pixels_per_unit, LOD_PIXEL_ERROR)
+    }
+
+    /// 画面を消してからシーンを描く
+    ///
+    /// ウィンドウにも画面外のフレームバッファにも描ける。
+    pub fn draw<S: Surface>(&self, target: &mut S, camera: &CameraState) -> DrawCounts {
+        let mut counts = DrawCounts::default();
+        let viewport_height = target.get_dimensions().1;
+        let teapot = &self.lods[self.lod_level(self.bounds.center(), camera, viewport_height)];
+        // 複製は段階ごとに分けて、段階ごとに1回で描く
+        let instance_groups: Vec<(&LodMesh, glium::VertexBuffer<Instance>)> = match &self.instances
+        {
+            Some(instances) => {
+                let center = self.model_bounds.center().extend(1.0);
+                let mut groups = vec![Vec::new(); self.lods.len()];
+                for instance in &instances.instances {
+                    let center = (Matrix4::from(instance.i_model) * center).truncate();
+                    groups[self.lod_level(center, camera, viewport_height)].push(*instance);
+                }
+                self.lods
+                    .iter()
+                    .zip(groups)
+                    .filter(|(_, group)| !group.is_empty())
+                    .map(|(mesh, group)| {
+                        (
+                            mesh,
+                            glium::VertexBuffer::new(&self.context, &group).unwrap(),
+                        )
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        // 地面も含めた全体が入るように、光源から見た深度を先に描く
+        let mut bounds = self.bounds;
+        if let Some(ground) = &self.ground {
+            bounds = bounds.union(&ground.bounds);
+        }
+        if let Some(instances) = &self.instances {
+            bounds = bounds.union(&instances.bounds);
+        }
+        let light_space = shadow::light_space_matrix(self.light, &bounds);
+        self.shadow_map.clear();
+        if self.debug.shading == Shading::Lit {
+            counts += self.shadow_map.render(
+                light_space,
+                self.model,
+                &teapot.vertices,
+                &teapot.indices,
+                teapot.triangles,
+            );
+            for (mesh, buffer) in &instance_groups {
+                counts += self.shadow_map.render_instances(
+                    light_space,
+                    (&mesh.vertices, buffer.per_instance().unwrap()),
+                    &mesh.indices,
+                    mesh.triangles * buffer.len() as u32,
+                );
+            }
+        }
+
+        // 何も無いところは後で景色を描く (深度の表示では一番遠い色の黒のままにする)
+        target.clear_color_and_depth((0.0, 0.0, 0.0, 1.0), 1.0);
+
+        let view = camera.get_view();
+        let view_light = (Matrix4::from(view) * self.light.extend(0.0)).truncate();
+        let shadow_map = self
+            .shadow_map
+            .texture()
+            .sampled()
+            .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
+            .minify_filter(glium::uniforms::MinifySamplerFilter::Nearest)
+            .wrap_function(glium::uniforms::SamplerWrapFunction::Clamp);
+        let environment = self
+            .skybox
+            .cubemap()
+            .sampled()
+            .magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear)
+            .minify_filter(glium::uniforms::MinifySamplerFilter::Linear);
+        let normal_map = self
+            .normal_map
+            .sampled()
+            .magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear)
+            .minify_filter(glium::uniforms::MinifySamplerFilter::LinearMipmapLinear)
+            .wrap_function(glium::uniforms::SamplerWrapFunction::Repeat);
+        let uniforms = |model: [[f32; 4]; 4], material: &Material, selected: bool, mode: i32| {
+            let (reflectivity, metallic, roughness) = material.parameters();
+            uniform! {
+                model: model,
+                view: view,
+                u_light: Into::<[f32; 3]>::into(view_light),
+                perspective: camera.get_perspective(),
+                u_color: material.color(),
+                u_selected: selected,
+                u_mode: mode,
+                u_near: camera::ZNEAR,
+                u_far: camera::ZFAR,
+                u_depth_distance: DEPTH_DISTANCE,
+                u_light_space: Into::<[[f32; 4]; 4]>::into(light_space),
+                u_shadow_map: shadow_map,
+                u_shadow_bias: self.shadow_map.bias,
+                u_environment: environment,
+                u_reflectivity: reflectivity,
+                u_metallic: metallic,
+                u_roughness: roughness,
+                u_irradiance: self.ibl.irradiance(),
+                u_prefiltered: self.ibl.prefiltered(),
+                u_prefiltered_lod: self.ibl.max_lod(),
+                u_normal_map: normal_map,
+            }
+        };
+        let programs = |mesh: &LodMesh| {
+            if mesh.normal_mapped {
+                &self.normal_mapped_programs
+            } else {
+                &self.programs
+            }
+        };
+        let instanced_programs = |mesh: &LodMesh| {
+            if mesh.normal_mapped {
+                &self.instanced_normal_mapped_programs
+            } else {
+                &self.instanced_programs
+            }
+        };
+        let mode = self.debug.shading.mode();
+        let params = glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            backface_culling: glium::draw_parameters::BackfaceCullingMode::CullClockwise,
+            ..Default::default()
+        };
+
+        target
+            .draw(
+                &teapot.vertices,
+                &teapot.indices,
+                programs(teapot).get(&self.teapot_material, mode),
+                &uniforms(self.model, &self.teapot_material, self.selected, mode),
+                &params,
+            )
+            .unwrap();
+        counts += DrawCounts {
+            draw_calls: 1,
+            triangles: teapot.triangles,
+        };
+
+        // 色はインスタンスごとのものを使い、材質の残りはティーポットと揃える
+        for (mesh, buffer) in &instance_groups {
+            target
+                .draw(
+                    (&mesh.vertices, buffer.per_instance().unwrap()),
+                    &mesh.indices,
+                    instanced_programs(mesh).get(&self.teapot_material, mode),
+                    &uniforms(self.model, &self.teapot_material, false, mode),
+                    &params,
+                )
+                .unwrap();
+            counts += DrawCounts {
+                draw_calls: 1,
+                triangles: mesh.triangles * buffer.len() as u32,
+            };
+        }
+
+        if let Some(ground) = &self.ground {
+            target
+                .draw(
+                    &ground.vertices,
+                    &ground.indices,
+                    self.programs.get(&self.ground_material, mode),
+                    &uniforms(ground.model, &self.ground_material, false, mode),
+                    &params,
+                )
+                .unwrap();
+            counts += DrawCounts {
+                draw_calls: 1,
+                triangles: ground.triangles,
+            };
+        }
+
+        // 不透明な物を全部描いてから、残ったところに景色を描く
+        if self.debug.shading != Shading::Depth {
+            counts += self.skybox.draw(target, view, camera.get_perspective());
+        }
+
+        if self.debug.wireframe {
+            // 面と同じ深さの辺が隠れないように、少しだけ手前にずらす
+            let params = glium::DrawParameters {
+                depth: glium::Depth {
+                    test: glium::DepthTest::IfLessOrEqual,
+                    write: false,
+                    ..Default::default()
+                },
+                polygon_mode: glium::PolygonMode::Line,
+                polygon_offset: glium::draw_parameters::PolygonOffset {
+                    factor: -1.0,
+                    units: -1.0,
+                    line: true,
+                    ..Default::default()
+                },
+                ..params.clone()
+            };
+            target
+                .draw(
+                    &teapot.vertices,
+                    &teapot.indices,
+                    &self.programs.lit,
+                    &uniforms(self.model, &self.teapot_material, false, 3),
+                    &params,
+                )
+                .unwrap();
+            counts += DrawCounts {
+                draw_calls: 1,
+                triangles: teapot.triangles,
+            };
+        }
+
+        if self.debug.normals {
+            let uniforms = uniform! {
+                model: self.model,
+                view: view,
+                perspective: camera.get_perspective(),
+            };
+            target
+                .draw(
+                    &self.normal_lines,
+                    glium::index::NoIndices(glium::index::PrimitiveType::LinesList),
+                    self.debug_draw.program(),
+                    &uniforms,
+                    &glium::DrawParameters {
+                        depth: params.depth,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+            counts += DrawCounts {
+                draw_calls: 1,
+                triangles: 0,
+            };
+        }
+
+        counts += self.debug_draw.draw(target, camera, &self.lines);
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// 長さのおかしい法線が赤くなるかのテスト
+    fn marks_bad_normals() {
+        let lines = normal_lines(2.0);
+        assert_eq!(lines.len(), teapot::VERTICES.len() * 2);
+        // 先頭はインデックスを1から始めるためのダミーで、法線が0になっている
+        assert_eq!(lines[0].color, [1.0, 0.0, 0.0]);
+        assert_eq!(lines[0].position, lines[1].position);
+
+        let (start, end) = (lines[2].position, lines[3].position);
+        let length = (0..3)
+            .map(|i| (end[i] - start[i]).powi(2))
+            .sum::<f32>()
+            .sqrt();
+        assert!((length - 2.0).abs() < 1e-4);
+        assert_eq!(lines[2].color, [1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn inserts_defines_after_version() {
+        let source = with_defines("#version 150\nvoid main() {}", &["NORMAL_MAP"], "");
+        assert_eq!(source, "#version 150\n#define NORMAL_MAP\nvoid main() {}");
+        assert_eq!(with_defines("#version 140\n", &[], ""), "#version 140\n");
+        // `#ifdef`が効くように、共通の関数は`#define`の後に入る
+        let source = with_defines("#version 140\nvoid main() {}", &["A"], "float f();\n");
+        assert_eq!(
+            source,
+            "#version 140\n#define A\nfloat f();\nvoid main() {}"
+        );
+        // 頂点シェーダーと共通の関数が同じ定義で切り替わる
+        for source in &[
+            include_str!("./main.vert"),
+            include_str!("./instanced.vert"),
+        ] {
+            assert!(source.starts_with("#version"));
+            assert!(source.contains("#ifdef NORMAL_MAP"));
+        }
+        assert!(SURFACE_SHADER.contains("#ifdef NORMAL_MAP"));
+        // フラグメントシェーダーは共通の関数を自分では持たない
+        for source in &[include_str!("./main.frag"), include_str!("./pbr.frag")] {
+            assert!(source.starts_with("#version"));
+            assert!(!source.contains("float lit()"));
+            assert!(!source.contains("vec3 surface_normal()"));
+        }
+    }
+}
@@ -0,0 +1,5 @@
+#version 140
+
+// 深度だけを書き込む
+void main() {
+}
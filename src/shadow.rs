@@ -0,0 +1,235 @@
+extern crate cgmath;
+use crate::bvh::Aabb;
+use crate::stats::DrawCounts;
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Vector3};
+use glium::Surface;
+use std::rc::Rc;
+
+/// シャドウマップの設定
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ShadowSettings {
+    /// シャドウマップの縦横のピクセル数
+    pub resolution: u32,
+    /// 自分の影で面がまだらにならないように、深度から引く量
+    pub bias: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> ShadowSettings {
+        ShadowSettings {
+            resolution: 2048,
+            bias: 0.002,
+        }
+    }
+}
+
+/// 平行光源から`bounds`全体がちょうど入る正射影の行列
+///
+/// `direction`は光が来る向き (面から光源へ向かう向き)。
+pub fn light_space_matrix(direction: Vector3<f32>, bounds: &Aabb) -> Matrix4<f32> {
+    let direction = direction.normalize();
+    let center = Point3::from_vec(bounds.center());
+    // 真上から照らすときはupに別の向きを使う
+    let up = if direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    };
+    let view = Matrix4::look_at_rh(center + direction, center, up);
+
+    let mut light_bounds = Aabb::empty();
+    for i in 0..8 {
+        let corner = bounds.corner(i);
+        light_bounds.grow((view * corner.extend(1.0)).truncate());
+    }
+    // 光源の座標では奥が-zになる
+    let projection = cgmath::ortho(
+        light_bounds.min.x,
+        light_bounds.max.x,
+        light_bounds.min.y,
+        light_bounds.max.y,
+        -light_bounds.max.z,
+        -light_bounds.min.z,
+    );
+    projection * view
+}
+
+/// 光源から見た深度だけを描いたテクスチャ
+pub struct ShadowMap {
+    context: Rc<glium::backend::Context>,
+    texture: glium::texture::DepthTexture2d,
+    program: glium::Program,
+    /// インスタンスごとの行列で描くプログラム
+    instanced_program: glium::Program,
+    pub bias: f32,
+}
+
+impl ShadowMap {
+    pub fn new<F: glium::backend::Facade>(facade: &F, settings: &ShadowSettings) -> ShadowMap {
+        let texture = glium::texture::DepthTexture2d::empty_with_format(
+            facade,
+            glium::texture::DepthFormat::F32,
+            glium::texture::MipmapsOption::NoMipmap,
+            settings.resolution,
+            settings.resolution,
+        )
+        .unwrap();
+        let program = glium::Program::from_source(
+            facade,
+            include_str!("./shadow.vert"),
+            include_str!("./shadow.frag"),
+            None,
+        )
+        .unwrap();
+        let instanced_program = glium::Program::from_source(
+            facade,
+            include_str!("./shadow_instanced.vert"),
+            include_str!("./shadow.frag"),
+            None,
+        )
+        .unwrap();
+        ShadowMap {
+            context: facade.get_context().clone(),
+            texture,
+            program,
+            instanced_program,
+            bias: settings.bias,
+        }
+    }
+
+    pub fn texture(&self) -> &glium::texture::DepthTexture2d {
+        &self.texture
+    }
+
+    /// 深度を消す。影を落とす物を`render`で描く前に呼ぶ
+    pub fn clear(&self) {
+        let mut framebuffer =
+            glium::framebuffer::SimpleFrameBuffer::depth_only(&self.context, &self.texture)
+                .unwrap();
+        framebuffer.clear_depth(1.0);
+    }
+
+    /// 影を落とす物を1つ描く
+    pub fn render<'a, V, I>(
+        &self,
+        light_space: Matrix4<f32>,
+        model: [[f32; 4]; 4],
+        vertices: V,
+        indices: I,
+        triangles: u32,
+    ) -> DrawCounts
+    where
+        V: glium::vertex::MultiVerticesSource<'a>,
+        I: Into<glium::index::IndicesSource<'a>>,
+    {
+        let uniforms = uniform! {
+            u_light_space: Into::<[[f32; 4]; 4]>::into(light_space),
+            model: model,
+        };
+        self.draw(&self.program, &uniforms, vertices, indices, triangles)
+    }
+
+    /// 影を落とす物を`vertices`に含まれるインスタンスの数だけ描く
+    ///
+    /// `triangles`は全てのインスタンスを合わせた数。
+    pub fn render_instances<'a, V, I>(
+        &self,
+        light_space: Matrix4<f32>,
+        vertices: V,
+        indices: I,
+        triangles: u32,
+    ) -> DrawCounts
+    where
+        V: glium::vertex::MultiVerticesSource<'a>,
+        I: Into<glium::index::IndicesSource<'a>>,
+    {
+        let uniforms = uniform! {
+            u_light_space: Into::<[[f32; 4]; 4]>::into(light_space),
+        };
+        self.draw(
+            &self.instanced_program,
+            &uniforms,
+            vertices,
+            indices,
+            triangles,
+        )
+    }
+
+    fn draw<'a, V, I, U>(
+        &self,
+        program: &glium::Program,
+        uniforms: &U,
+        vertices: V,
+        indices: I,
+        triangles: u32,
+    ) -> DrawCounts
+    where
+        V: glium::vertex::MultiVerticesSource<'a>,
+        I: Into<glium::index::IndicesSource<'a>>,
+        U: glium::uniforms::Uniforms,
+    {
+        let mut framebuffer =
+            glium::framebuffer::SimpleFrameBuffer::depth_only(&self.context, &self.texture)
+                .unwrap();
+        let params = glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        framebuffer
+            .draw(vertices, indices, program, uniforms, &params)
+            .unwrap();
+        DrawCounts {
+            draw_calls: 1,
+            triangles,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// 箱の全ての角が光源の正射影の中に入るかのテスト
+    fn light_space_covers_bounds() {
+        let bounds = Aabb {
+            min: Vector3::new(-3.0, -0.4, -1.0),
+            max: Vector3::new(3.0, 0.5, 5.0),
+        };
+        for direction in &[
+            Vector3::new(-1.0, 0.4, 0.9),
+            Vector3::new(0.0, 1.0, 0.0),
+            Vector3::new(0.3, 2.0, -0.2),
+        ] {
+            let matrix = light_space_matrix(*direction, &bounds);
+            let mut extent = 0.0f32;
+            for i in 0..8 {
+                let corner = bounds.corner(i);
+                let p = matrix * corner.extend(1.0);
+                for k in 0..3 {
+                    assert!(p[k].abs() <= 1.0 + 1e-4, "{:?} {:?}", direction, p);
+                    extent = extent.max(p[k].abs());
+                }
+            }
+            // ぴったり合わせているので、どこかの角は端にある
+            assert!((extent - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    /// 光に近い点ほど深度が小さくなるかのテスト
+    fn nearer_to_light_is_shallower() {
+        let bounds = Aabb {
+            min: Vector3::new(-1.0, -1.0, -1.0),
+            max: Vector3::new(1.0, 1.0, 1.0),
+        };
+        let matrix = light_space_matrix(Vector3::new(0.0, 1.0, 0.0), &bounds);
+        let top = matrix * Vector3::new(0.0, 0.9, 0.0).extend(1.0);
+        let bottom = matrix * Vector3::new(0.0, -0.9, 0.0).extend(1.0);
+        assert!(top.z < bottom.z);
+    }
+}
@@ -0,0 +1,11 @@
+#version 140
+
+in vec3 position;
+
+// 光源から見た正射影
+uniform mat4 u_light_space;
+uniform mat4 model;
+
+void main() {
+	gl_Position = u_light_space * model * vec4(position, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 140
+
+in vec3 position;
+// インスタンスごとの行列 (shadow.vertの`model`の代わり)
+in mat4 i_model;
+
+// 光源から見た正射影
+uniform mat4 u_light_space;
+
+void main() {
+	gl_Position = u_light_space * i_model * vec4(position, 1.0);
+}
@@ -0,0 +1,11 @@
+#version 140
+
+in vec3 v_direction;
+
+out vec4 color;
+
+uniform samplerCube u_environment;
+
+void main() {
+	color = vec4(texture(u_environment, normalize(v_direction)).rgb, 1.0);
+}
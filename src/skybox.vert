@@ -0,0 +1,15 @@
+#version 140
+
+in vec2 position;
+
+// 画面の位置からワールド空間の向きを求める
+out vec3 v_direction;
+
+// 回転だけのビュー行列と射影行列を掛けたものの逆行列
+uniform mat4 u_inverse_view_projection;
+
+void main() {
+	// 一番奥(深度1)に置いて、物の後ろだけに描かれるようにする
+	gl_Position = vec4(position, 1.0, 1.0);
+	v_direction = (u_inverse_view_projection * vec4(position, 1.0, 1.0)).xyz;
+}
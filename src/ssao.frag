@@ -0,0 +1,84 @@
+#version 140
+
+in vec2 v_tex_coords;
+
+out vec4 color;
+
+// 0: 遮られている割合を求める 1: ぼかして元の色に掛ける
+uniform int u_pass;
+uniform sampler2D u_input;
+uniform sampler2D u_occlusion;
+uniform sampler2D u_depth;
+uniform mat4 u_projection;
+uniform mat4 u_inverse_projection;
+// 1ピクセルの大きさ
+uniform vec2 u_texel;
+// 調べる半球の半径 (ビュー空間)
+uniform float u_radius;
+uniform float u_intensity;
+// 自分の面に遮られないように離す量
+uniform float u_bias;
+
+const int samples = 16;
+
+// 深度からビュー空間の位置に戻す
+vec3 view_position(vec2 uv) {
+	float depth = texture(u_depth, uv).r;
+	vec4 p = u_inverse_projection * vec4(vec3(uv, depth) * 2.0 - 1.0, 1.0);
+	return p.xyz / p.w;
+}
+
+float hash(vec2 p) {
+	return fract(sin(dot(p, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+float occlusion() {
+	if (texture(u_depth, v_tex_coords).r >= 1.0) {
+		return 1.0;
+	}
+	vec3 p = view_position(v_tex_coords);
+	// 隣のピクセルとの差から面の向きを求める (ビュー空間は+zが奥なので、外積はカメラと逆を向く)
+	vec3 n = -normalize(cross(dFdx(p), dFdy(p)));
+	// ピクセルごとに回して、少ないサンプル数の縞を目立たなくする
+	float angle = hash(gl_FragCoord.xy) * 6.2831853;
+	vec3 random = vec3(cos(angle), sin(angle), 0.0);
+	vec3 t = normalize(random - n * dot(random, n));
+	mat3 tbn = mat3(t, cross(n, t), n);
+
+	float occluded = 0.0;
+	for (int i = 0; i < samples; i++) {
+		// 半球の中に、中心ほど多くなるように散らす
+		float k = (float(i) + 0.5) / float(samples);
+		float phi = float(i) * 2.3999632;
+		float z = sqrt(1.0 - k);
+		vec3 offset = vec3(cos(phi) * sqrt(k), sin(phi) * sqrt(k), z) * mix(0.1, 1.0, k * k);
+		vec3 s = p + tbn * offset * u_radius;
+		vec4 q = u_projection * vec4(s, 1.0);
+		vec2 uv = q.xy / q.w * 0.5 + 0.5;
+		// 画面の外の深度は分からないので数えない
+		if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+			continue;
+		}
+		float scene_z = view_position(uv).z;
+		// 遠く離れた物には遮られないようにする
+		float range = smoothstep(0.0, 1.0, u_radius / abs(p.z - scene_z));
+		// 奥が+zなので、サンプルより手前に面があれば遮られている
+		occluded += (scene_z <= s.z - u_bias ? 1.0 : 0.0) * range;
+	}
+	return clamp(1.0 - occluded / float(samples) * u_intensity, 0.0, 1.0);
+}
+
+void main() {
+	if (u_pass == 0) {
+		color = vec4(vec3(occlusion()), 1.0);
+		return;
+	}
+	// 回したサンプルの縞が消えるように4x4でぼかす
+	float sum = 0.0;
+	for (int x = -2; x < 2; x++) {
+		for (int y = -2; y < 2; y++) {
+			sum += texture(u_occlusion, v_tex_coords + (vec2(x, y) + 0.5) * u_texel).r;
+		}
+	}
+	color = vec4(texture(u_input, v_tex_coords).rgb * sum / 16.0, 1.0);
+}
@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// 1フレームで行った描画の数
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct DrawCounts {
+    pub draw_calls: u32,
+    pub triangles: u32,
+}
+
+impl std::ops::AddAssign for DrawCounts {
+    fn add_assign(&mut self, other: DrawCounts) {
+        self.draw_calls += other.draw_calls;
+        self.triangles += other.triangles;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FrameSample {
+    /// 前のフレームからの時間 (ミリ秒)
+    pub frame_time: f32,
+    pub counts: DrawCounts,
+}
+
+/// 直近のフレームの統計
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Summary {
+    pub min: f32,
+    pub avg: f32,
+    pub max: f32,
+    /// 平均のフレームレート
+    pub fps: f32,
+    /// 遅かった1%のフレームの平均から求めたフレームレート
+    pub one_percent_low: f32,
+}
+
+/// フレーム時間と描画の数を集める
+pub struct FrameStats {
+    samples: VecDeque<FrameSample>,
+    capacity: usize,
+    frame: u64,
+    csv: Option<BufWriter<File>>,
+}
+
+impl FrameStats {
+    /// 直近の`capacity`フレームを覚えておく
+    pub fn new(capacity: usize) -> FrameStats {
+        FrameStats {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            frame: 0,
+            csv: None,
+        }
+    }
+
+    /// 全てのフレームをCSVにも書き出す
+    pub fn with_csv(capacity: usize, path: &Path) -> io::Result<FrameStats> {
+        let mut csv = BufWriter::new(File::create(path)?);
+        writeln!(csv, "frame,frame_time_ms,draw_calls,triangles")?;
+        let mut stats = FrameStats::new(capacity);
+        stats.csv = Some(csv);
+        Ok(stats)
+    }
+
+    pub fn record(&mut self, frame_time_ms: f32, counts: DrawCounts) -> io::Result<()> {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(FrameSample {
+            frame_time: frame_time_ms,
+            counts,
+        });
+        if let Some(csv) = self.csv.as_mut() {
+            writeln!(
+                csv,
+                "{},{:.3},{},{}",
+                self.frame, frame_time_ms, counts.draw_calls, counts.triangles
+            )?;
+        }
+        self.frame += 1;
+        Ok(())
+    }
+
+    pub fn last(&self) -> Option<&FrameSample> {
+        self.samples.back()
+    }
+
+    pub fn summary(&self) -> Option<Summary> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut times: Vec<f32> = self.samples.iter().map(|s| s.frame_time).collect();
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+        let avg = times.iter().sum::<f32>() / times.len() as f32;
+        let slowest = (times.len() / 100).max(1);
+        let slow_avg = times[times.len() - slowest..].iter().sum::<f32>() / slowest as f32;
+        Some(Summary {
+            min: times[0],
+            avg,
+            max: times[times.len() - 1],
+            fps: 1000.0 / avg,
+            one_percent_low: 1000.0 / slow_avg,
+        })
+    }
+
+    /// `bucket_ms`ミリ秒ごとに数える。最後の区間はそれより遅いフレームも含む
+    pub fn histogram(&self, bucket_ms: f32, buckets: usize) -> Vec<u32> {
+        let mut histogram = vec![0; buckets];
+        for sample in &self.samples {
+            let bucket = (sample.frame_time / bucket_ms) as usize;
+            histogram[bucket.min(buckets - 1)] += 1;
+        }
+        histogram
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.csv.as_mut() {
+            Some(csv) => csv.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts() -> DrawCounts {
+        DrawCounts {
+            draw_calls: 2,
+            triangles: 1024,
+        }
+    }
+
+    #[test]
+    fn summarizes_frame_times() {
+        let mut stats = FrameStats::new(1000);
+        for i in 0..200 {
+            // 100フレームに1回だけ遅いフレームがある
+            let time = if i % 100 == 0 { 50.0 } else { 10.0 };
+            stats.record(time, counts()).unwrap();
+        }
+        let summary = stats.summary().unwrap();
+        assert_eq!(summary.min, 10.0);
+        assert_eq!(summary.max, 50.0);
+        assert!((summary.avg - 10.4).abs() < 1e-4);
+        assert!((summary.one_percent_low - 20.0).abs() < 1e-4);
+        assert_eq!(stats.histogram(20.0, 2), vec![198, 2]);
+    }
+
+    #[test]
+    fn forgets_old_frames() {
+        let mut stats = FrameStats::new(2);
+        for &time in &[100.0, 10.0, 20.0] {
+            stats.record(time, counts()).unwrap();
+        }
+        assert_eq!(stats.summary().unwrap().max, 20.0);
+        assert_eq!(stats.last().unwrap().frame_time, 20.0);
+    }
+
+    #[test]
+    fn writes_every_frame_to_csv() {
+        let path = std::env::temp_dir().join("opengl-by-rust-stats-test.csv");
+        let mut stats = FrameStats::with_csv(1, &path).unwrap();
+        stats.record(16.5, counts()).unwrap();
+        stats.record(17.25, counts()).unwrap();
+        stats.flush().unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            csv,
+            "frame,frame_time_ms,draw_calls,triangles\n0,16.500,2,1024\n1,17.250,2,1024\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
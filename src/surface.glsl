@@ -0,0 +1,39 @@
+// main.fragとpbr.fragで共通の、影と法線の求め方 (`#version`と`#define`の後に足す)
+
+in vec3 v_normal;
+in vec4 v_light_space_position;
+#ifdef NORMAL_MAP
+in vec2 v_tex_coords;
+in vec3 v_tangent;
+in vec3 v_bitangent;
+// 接線空間の法線
+uniform sampler2D u_normal_map;
+#endif
+
+// 光源から見た深度
+uniform sampler2D u_shadow_map;
+uniform float u_shadow_bias;
+
+// 光が当たっている割合 (周りの3x3テクセルと比べて縁をぼかす)
+float lit() {
+	vec3 p = v_light_space_position.xyz / v_light_space_position.w * 0.5 + 0.5;
+	vec2 texel = 1.0 / vec2(textureSize(u_shadow_map, 0));
+	float lit = 0.0;
+	for (int x = -1; x <= 1; x++) {
+		for (int y = -1; y <= 1; y++) {
+			float depth = texture(u_shadow_map, p.xy + vec2(x, y) * texel).r;
+			lit += p.z - u_shadow_bias > depth ? 0.0 : 1.0;
+		}
+	}
+	return lit / 9.0;
+}
+
+// 光を当てる向きの法線 (法線マップがあれば接線空間から戻す)
+vec3 surface_normal() {
+	vec3 n = normalize(v_normal);
+#ifdef NORMAL_MAP
+	vec3 m = texture(u_normal_map, v_tex_coords).xyz * 2.0 - 1.0;
+	n = normalize(m.x * normalize(v_tangent) + m.y * normalize(v_bitangent) + m.z * n);
+#endif
+	return n;
+}
@@ -0,0 +1,235 @@
+extern crate bevy_mikktspace;
+use crate::mesh::MeshVertex;
+use std::collections::HashMap;
+
+/// mikktspaceに三角形の角ごとの値を渡し、求めた接線を角ごとに受け取る
+struct Corners<'a> {
+    vertices: &'a [MeshVertex],
+    indices: &'a [u32],
+    tangents: Vec<[f32; 4]>,
+}
+
+impl Corners<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> &MeshVertex {
+        &self.vertices[self.indices[face * 3 + vert] as usize]
+    }
+}
+
+impl bevy_mikktspace::Geometry for Corners<'_> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).position
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).normal
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.vertex(face, vert).tex_coords
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.tangents[face * 3 + vert] = tangent;
+    }
+}
+
+/// `indices`の角ごとの接線 (求められなければ全て0)
+fn corner_tangents(vertices: &[MeshVertex], indices: &[u32]) -> Vec<[f32; 4]> {
+    let mut corners = Corners {
+        vertices,
+        indices,
+        tangents: vec![[0.0; 4]; indices.len() / 3 * 3],
+    };
+    if !bevy_mikktspace::generate_tangents(&mut corners) {
+        corners.tangents = vec![[0.0; 4]; corners.tangents.len()];
+    }
+    corners.tangents
+}
+
+/// 三角形のUVからMikkTSpaceで頂点ごとの接線を求めて`tangent`に入れる
+///
+/// BlenderやglTFの法線マップと同じ接線になる。wには従接線が`cross(normal, tangent)`と同じ向きなら1、
+/// 逆なら-1が入る。UVを鏡に映した継ぎ目のように角ごとに接線が違う頂点は複製して分けるので、
+/// `vertices`が増えて`indices`が書き換わることがある。
+pub fn generate(vertices: &mut Vec<MeshVertex>, indices: &mut [u32]) {
+    let tangents = corner_tangents(vertices, indices);
+    let mut used = vec![false; vertices.len()];
+    let mut copies: HashMap<(u32, [u32; 4]), u32> = HashMap::new();
+    for (index, tangent) in indices.iter_mut().zip(tangents) {
+        let original = *index;
+        *index = *copies
+            .entry((original, tangent.map(f32::to_bits)))
+            .or_insert_with(|| {
+                // 最初の接線は元の頂点に入れ、違う接線が来たら複製を足す
+                let mut vertex = vertices[original as usize];
+                vertex.tangent = tangent;
+                if !used[original as usize] {
+                    used[original as usize] = true;
+                    vertices[original as usize] = vertex;
+                    original
+                } else {
+                    vertices.push(vertex);
+                    (vertices.len() - 1) as u32
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::{self, Primitive, Shape};
+    use cgmath::{InnerSpace, Vector3};
+
+    /// 三角形の角ごとの頂点 (どの三角形にも使われない頂点には接線が入らない)
+    fn tangents(primitive: &Primitive) -> Vec<MeshVertex> {
+        let mut vertices = primitive.vertices();
+        let mut indices = primitive.indices.clone();
+        generate(&mut vertices, &mut indices);
+        indices.iter().map(|&i| vertices[i as usize]).collect()
+    }
+
+    #[test]
+    fn follows_uv_directions() {
+        // 板のuはx、vはzの向きに増える
+        let plane = primitives::plane(1.0, 2);
+        for vertex in tangents(&plane) {
+            assert_eq!(vertex.tangent, [1.0, 0.0, 0.0, -1.0]);
+        }
+
+        // uを鏡に映すと接線が逆を向いて、従接線の向きはそのまま
+        let mut mirrored = plane.clone();
+        for uv in &mut mirrored.tex_coords {
+            uv[0] = 1.0 - uv[0];
+        }
+        for vertex in tangents(&mirrored) {
+            assert_eq!(vertex.tangent, [-1.0, 0.0, 0.0, 1.0]);
+        }
+
+        // 球のuは経度の向きで、vは上から下に増える
+        let sphere = primitives::uv_sphere(1.0, 16, 8);
+        for vertex in tangents(&sphere) {
+            let [x, y, z] = vertex.position;
+            if y.abs() > 0.99 {
+                continue;
+            }
+            let around = Vector3::new(z, 0.0, -x).normalize();
+            let t = Vector3::new(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+            assert!(t.dot(around) > 0.95, "{:?}", vertex);
+            assert_eq!(vertex.tangent[3], -1.0);
+        }
+    }
+
+    #[test]
+    fn splits_mirrored_seams() {
+        // 板の左半分だけuを鏡に映すと、真ん中の列で向きが食い違う
+        let mut plane = primitives::plane(1.0, 2);
+        for uv in &mut plane.tex_coords {
+            uv[0] = (uv[0] * 2.0 - 1.0).abs();
+        }
+        let mut vertices = plane.vertices();
+        let mut indices = plane.indices.clone();
+        generate(&mut vertices, &mut indices);
+        assert_eq!(vertices.len(), 9 + 3);
+        for t in indices.chunks_exact(3) {
+            let left = t.iter().any(|&i| vertices[i as usize].position[0] < 0.0);
+            let expected = if left {
+                [-1.0, 0.0, 0.0, 1.0]
+            } else {
+                [1.0, 0.0, 0.0, -1.0]
+            };
+            for &i in t {
+                assert_eq!(vertices[i as usize].tangent, expected, "{:?}", t);
+            }
+        }
+    }
+
+    #[test]
+    /// 緑だけの法線マップが、main.vertと同じ計算でビュー空間の+vの側に傾くかのテスト
+    fn green_tilts_towards_v_in_view_space() {
+        use crate::camera::CameraState;
+        use cgmath::{Matrix, Matrix3, Matrix4, SquareMatrix};
+
+        let mut camera = CameraState::new();
+        camera.set_position((2.0, -1.0, 0.0));
+        camera.set_direction((-2.0, 1.0, 1.0));
+        camera.set_up((0.0, 1.0, 0.0));
+        let view = Matrix4::from(camera.get_view());
+        let view = Matrix3::from_cols(view.x.truncate(), view.y.truncate(), view.z.truncate());
+        // ビュー行列は鏡映なので、ビュー空間で外積を取ると向きが逆になる
+        assert!(view.determinant() < 0.0);
+
+        // 板のvはzの向きに増える
+        let expected = (view * Vector3::unit_z()).normalize();
+        for vertex in tangents(&primitives::plane(1.0, 1)) {
+            let n = Vector3::from(vertex.normal);
+            let t = Vector3::new(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+            let normal = view.invert().unwrap().transpose() * n;
+            let bitangent = view * (n.cross(t) * vertex.tangent[3]);
+            // テクセル(0.5, 1.0, 0.5)はm = (0, 1, 0)で、法線が従接線の向きになる
+            let m = Vector3::new(0.0, 1.0, 0.0);
+            let shaded = (view * t * m.x + bitangent * m.y + normal.normalize() * m.z).normalize();
+            assert!(shaded.dot(expected) > 0.999, "{:?}", vertex);
+        }
+    }
+
+    #[test]
+    /// 頂点を分けても、角ごとの接線がMikkTSpaceの結果のままかのテスト
+    fn keeps_mikktspace_corner_tangents() {
+        for &shape in &[Shape::Cube, Shape::UvSphere, Shape::Torus, Shape::Capsule] {
+            let primitive = shape.primitive(1.0);
+            let original = primitive.vertices();
+            let expected = corner_tangents(&original, &primitive.indices);
+            let mut vertices = original.clone();
+            let mut indices = primitive.indices.clone();
+            generate(&mut vertices, &mut indices);
+            for (corner, (&i, &j)) in indices.iter().zip(&primitive.indices).enumerate() {
+                let vertex = vertices[i as usize];
+                assert_eq!(vertex.tangent, expected[corner], "{:?}", shape);
+                assert_eq!(
+                    MeshVertex {
+                        tangent: original[j as usize].tangent,
+                        ..vertex
+                    },
+                    original[j as usize]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn orthogonal_to_normals() {
+        let shapes = [
+            Shape::Plane,
+            Shape::Cube,
+            Shape::UvSphere,
+            Shape::Icosphere,
+            Shape::Cylinder,
+            Shape::Cone,
+            Shape::Torus,
+            Shape::Capsule,
+        ];
+        for &shape in &shapes {
+            for vertex in tangents(&shape.primitive(1.0)) {
+                let n = Vector3::from(vertex.normal);
+                let t = Vector3::new(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+                assert!(
+                    (t.magnitude() - 1.0).abs() < 1e-4,
+                    "{:?} {:?}",
+                    shape,
+                    vertex
+                );
+                assert!(t.dot(n).abs() < 1e-4, "{:?} {:?}", shape, vertex);
+                assert_eq!(vertex.tangent[3].abs(), 1.0);
+            }
+        }
+    }
+}
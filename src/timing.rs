@@ -0,0 +1,121 @@
+use std::time::{Duration, Instant};
+
+/// 一度に進めるステップの最大数
+///
+/// 止まっていた後などに、追いつこうとして更新に時間がかかり続けるのを防ぐ。
+const MAX_STEPS_PER_FRAME: u32 = 8;
+
+/// 経過時間を貯めて、決まった間隔でシミュレーションを進める
+pub struct FixedStep {
+    step: f32,
+    accumulator: f32,
+}
+
+impl FixedStep {
+    /// `rate`回/秒で進める
+    pub fn new(rate: f32) -> FixedStep {
+        FixedStep {
+            step: 1.0 / rate,
+            accumulator: 0.0,
+        }
+    }
+
+    /// 1ステップの長さ(秒)
+    pub fn step(&self) -> f32 {
+        self.step
+    }
+
+    /// `elapsed`秒を足して、進めるステップ数を返す
+    pub fn advance(&mut self, elapsed: f32) -> u32 {
+        self.accumulator += elapsed;
+        let steps = (self.accumulator / self.step).floor() as u32;
+        self.accumulator -= steps as f32 * self.step;
+        if steps > MAX_STEPS_PER_FRAME {
+            self.accumulator = 0.0;
+            return MAX_STEPS_PER_FRAME;
+        }
+        steps
+    }
+
+    /// 前のステップと今のステップの間のどこを描くか (0から1)
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step).min(1.0)
+    }
+}
+
+/// 目標のフレームレートで描画するタイミングを決める
+pub struct FramePacer {
+    /// `None`なら垂直同期に任せて毎回描く
+    frame_time: Option<Duration>,
+    next_frame: Instant,
+}
+
+impl FramePacer {
+    pub fn new(fps: Option<f32>, now: Instant) -> FramePacer {
+        FramePacer {
+            frame_time: fps.map(|fps| Duration::from_secs_f64(1.0 / fps as f64)),
+            next_frame: now,
+        }
+    }
+
+    /// 描く時間になっていれば、次のフレームの時刻を進めて`true`を返す
+    pub fn should_draw(&mut self, now: Instant) -> bool {
+        let frame_time = match self.frame_time {
+            Some(frame_time) => frame_time,
+            None => return true,
+        };
+        if now < self.next_frame {
+            return false;
+        }
+        self.next_frame += frame_time;
+        // 大きく遅れたときはまとめて描かずに今から数え直す
+        if self.next_frame < now {
+            self.next_frame = now + frame_time;
+        }
+        true
+    }
+
+    /// 次に起きる時刻 (`None`なら待たない)
+    pub fn next_frame(&self) -> Option<Instant> {
+        self.frame_time.map(|_| self.next_frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// 経過時間が何回に分かれて届いても、同じ回数だけ進むかのテスト
+    fn steps_do_not_depend_on_frame_rate() {
+        let mut coarse = FixedStep::new(64.0);
+        let mut fine = FixedStep::new(64.0);
+        let coarse_steps: u32 = (0..8).map(|_| coarse.advance(0.125)).sum();
+        let fine_steps: u32 = (0..1024).map(|_| fine.advance(1.0 / 1024.0)).sum();
+        assert_eq!(coarse_steps, 64);
+        assert_eq!(fine_steps, 64);
+    }
+
+    #[test]
+    fn keeps_remainder_for_interpolation() {
+        let mut step = FixedStep::new(10.0);
+        assert_eq!(step.advance(0.25), 2);
+        assert!((step.alpha() - 0.5).abs() < 1e-4);
+        assert_eq!(step.advance(10.0), MAX_STEPS_PER_FRAME);
+        assert_eq!(step.alpha(), 0.0);
+    }
+
+    #[test]
+    fn paces_frames() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(Some(50.0), start);
+        assert!(pacer.should_draw(start));
+        assert!(!pacer.should_draw(start + Duration::from_millis(10)));
+        assert!(pacer.should_draw(start + Duration::from_millis(20)));
+        assert_eq!(pacer.next_frame(), Some(start + Duration::from_millis(40)));
+
+        let mut vsync = FramePacer::new(None, start);
+        assert!(vsync.should_draw(start));
+        assert_eq!(vsync.next_frame(), None);
+    }
+}
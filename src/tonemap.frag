@@ -0,0 +1,37 @@
+#version 140
+
+in vec2 v_tex_coords;
+
+out vec4 color;
+
+// リニアな明るさで描いたシーン
+uniform sampler2D u_input;
+// 明るさを2の何乗倍にするか
+uniform float u_exposure;
+// 0: 切り捨てるだけ 1: Reinhard 2: ACES
+uniform int u_tone_mapping;
+
+// ACESのフィルミックな曲線の近似 (Narkowicz)
+vec3 aces(vec3 x) {
+	return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+// リニアな値をsRGBの値にする
+vec3 to_srgb(vec3 c) {
+	vec3 low = c * 12.92;
+	vec3 high = 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055;
+	return mix(low, high, step(vec3(0.0031308), c));
+}
+
+void main() {
+	vec3 hdr = texture(u_input, v_tex_coords).rgb * exp2(u_exposure);
+	vec3 mapped;
+	if (u_tone_mapping == 1) {
+		mapped = hdr / (1.0 + hdr);
+	} else if (u_tone_mapping == 2) {
+		mapped = aces(hdr);
+	} else {
+		mapped = clamp(hdr, 0.0, 1.0);
+	}
+	color = vec4(to_srgb(mapped), 1.0);
+}
@@ -0,0 +1,22 @@
+#version 140
+
+in vec2 v_tex_coords;
+
+out vec4 color;
+
+uniform sampler2D u_input;
+// 横と縦の比 (丸く暗くするため)
+uniform float u_aspect;
+// 一番端でどれだけ暗くするか
+uniform float u_strength;
+// 暗くなり始める中心からの距離と、暗くなりきるまでの幅
+uniform float u_radius;
+uniform float u_softness;
+
+void main() {
+	vec2 p = (v_tex_coords - 0.5) * vec2(u_aspect, 1.0);
+	// 画面の角を1にする
+	float distance = length(p) / length(vec2(u_aspect, 1.0) * 0.5);
+	float dark = smoothstep(u_radius, u_radius + u_softness, distance) * u_strength;
+	color = vec4(texture(u_input, v_tex_coords).rgb * (1.0 - dark), 1.0);
+}